@@ -0,0 +1,738 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/
+
+//! A two pass 6502 assembler.
+//!
+//! ```text
+//!         .org $0400
+//! count = 10
+//! start:  ldx #count
+//! @loop:  dex             ; @loop is local to start
+//!         bne @loop
+//!         lda #<message   ; low byte
+//!         ldy #>message   ; high byte
+//!         jmp *
+//! message:
+//!         .byte "hi", 0
+//!         .word start, message+1
+//! ```
+//!
+//! Numbers are decimal, `$hex`, `%binary` or `'c'`. Expressions support
+//! `+ - * / % & | ^ << >>`, parentheses, unary `-` and `~`, and `<` and
+//! `>` for the low and high byte. `*` on its own is the current address.
+//! Operands that are known in the first pass and fit in a byte use zero
+//! page addressing when the instruction has it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::opcodes::{Mnemonic, Mode, OpcodeTable, OPCODES};
+use crate::CPU;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+    /// Line number, starting at 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Bytes assembled at consecutive addresses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+}
+
+/// The output of the assembler. Local labels appear in `symbols` as
+/// `global@local`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub segments: Vec<Segment>,
+    pub symbols: BTreeMap<String, u16>,
+}
+
+impl Program {
+    pub fn symbol(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Address of the first byte of the program.
+    pub fn start(&self) -> Option<u16> {
+        self.segments.first().map(|segment| segment.address)
+    }
+
+    /// Copy all segments into the memory of `cpu`.
+    pub fn load_into<B: Bus>(&self, cpu: &mut CPU<B>) {
+        for segment in &self.segments {
+            cpu.load(segment.address, &segment.bytes);
+        }
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    assemble_with(&OPCODES, source)
+}
+
+/// Like `assemble`, with another opcode table such as `CPU::opcodes`.
+/// Where the table has several opcodes for an instruction, the
+/// documented one is used.
+pub fn assemble_with(table: &'static OpcodeTable, source: &str) -> Result<Program, AsmError> {
+    let lines = parse(table, source)?;
+    let mut assembler = Assembler { table, symbols: HashMap::new(), modes: HashMap::new(), segments: Vec::new(), pc: 0 };
+    assembler.pass(&lines, false)?;
+    assembler.pass(&lines, true)?;
+    Ok(Program {
+        segments: assembler.segments,
+        symbols: assembler.symbols.into_iter().map(|(name, v)| (name, v as u16)).collect(),
+    })
+}
+
+// Parsing
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum UnOp {
+    Neg,
+    Not,
+    Lo,
+    Hi,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Num(i64),
+    Sym(String),
+    Pc,
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+enum Operand {
+    None,
+    Acc,
+    Imm(Expr),
+    Plain(Expr),
+    X(Expr),
+    Y(Expr),
+    Ind(Expr),
+    Xind(Expr),
+    Indy(Expr),
+    // Zero page address and branch target
+    Zpr(Expr, Expr),
+}
+
+#[derive(Debug)]
+enum Item {
+    Expr(Expr),
+    Str(Vec<u8>),
+}
+
+#[derive(Debug)]
+enum Statement {
+    Org(Expr),
+    Byte(Vec<Item>),
+    Word(Vec<Expr>),
+    Const(String, Expr),
+    Instruction(Mnemonic, Operand),
+}
+
+struct Line {
+    number: usize,
+    label: Option<String>,
+    statement: Option<Statement>,
+}
+
+fn parse(table: &OpcodeTable, source: &str) -> Result<Vec<Line>, AsmError> {
+    let mut lines = Vec::new();
+    let mut scope = String::new();
+    for (i, text) in source.lines().enumerate() {
+        let number = i + 1;
+        let error = |message: String| AsmError { line: number, message };
+        let mut rest = strip_comment(text).trim();
+
+        let mut label = None;
+        if let Some((name, after)) = split_label(rest) {
+            if !name.starts_with('@') {
+                scope = name.to_string();
+            }
+            label = Some(qualify(&scope, name).map_err(error)?);
+            rest = after.trim();
+        }
+
+        let statement = if rest.is_empty() {
+            None
+        } else {
+            Some(parse_statement(table, rest, &scope).map_err(error)?)
+        };
+        lines.push(Line { number, label, statement });
+    }
+    Ok(lines)
+}
+
+// Removes a ; comment that is not inside a string or character literal
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &line[..i],
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_symbol_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '@'
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn split_label(s: &str) -> Option<(&str, &str)> {
+    let end = s.find(|c: char| !(is_symbol_char(c) || c == '@'))?;
+    if end > 0 && s.starts_with(is_symbol_start) && s[end..].starts_with(':') {
+        Some((&s[..end], &s[end + 1..]))
+    } else {
+        None
+    }
+}
+
+fn qualify(scope: &str, name: &str) -> Result<String, String> {
+    if name.starts_with('@') {
+        if scope.is_empty() {
+            return Err(format!("local label {} before any global label", name));
+        }
+        Ok(format!("{}{}", scope, name))
+    } else {
+        Ok(name.to_string())
+    }
+}
+
+fn parse_statement(table: &OpcodeTable, s: &str, scope: &str) -> Result<Statement, String> {
+    let (word, rest) = match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim()),
+        None => (s, ""),
+    };
+
+    // name = expr
+    if let Some(expr) = rest.strip_prefix('=') {
+        if !word.starts_with(is_symbol_start) || !word.chars().skip(1).all(is_symbol_char) {
+            return Err(format!("bad symbol name {}", word));
+        }
+        return Ok(Statement::Const(qualify(scope, word)?, parse_expr(expr, scope)?));
+    }
+    if let Some((name, expr)) = s.split_once('=') {
+        let name = name.trim();
+        if name.starts_with(is_symbol_start) && name.chars().skip(1).all(is_symbol_char) {
+            return Ok(Statement::Const(qualify(scope, name)?, parse_expr(expr, scope)?));
+        }
+    }
+
+    match word.to_ascii_lowercase().as_str() {
+        ".org" => Ok(Statement::Org(parse_expr(rest, scope)?)),
+        ".byte" => {
+            let items = split_list(rest)?.into_iter().map(|item| {
+                if let Some(text) = item.strip_prefix('"') {
+                    let text = text.strip_suffix('"').ok_or(format!("unterminated string {}", item))?;
+                    Ok(Item::Str(text.as_bytes().to_vec()))
+                } else {
+                    Ok(Item::Expr(parse_expr(item, scope)?))
+                }
+            });
+            Ok(Statement::Byte(items.collect::<Result<_, String>>()?))
+        }
+        ".word" => {
+            let items = split_list(rest)?.into_iter().map(|item| parse_expr(item, scope));
+            Ok(Statement::Word(items.collect::<Result<_, String>>()?))
+        }
+        directive if directive.starts_with('.') => Err(format!("unknown directive {}", word)),
+        _ => {
+            let mnemonic = find_mnemonic(table, word).ok_or(format!("unknown instruction {}", word))?;
+            Ok(Statement::Instruction(mnemonic, parse_operand(rest, scope)?))
+        }
+    }
+}
+
+// Splits on commas that are not inside a string or character literal
+fn split_list(s: &str) -> Result<Vec<&str>, String> {
+    let mut items = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (None, ',') => {
+                items.push(s[start..i].trim());
+                start = i + 1;
+            }
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    items.push(s[start..].trim());
+    if items.iter().any(|item| item.is_empty()) {
+        return Err(format!("empty item in {}", s));
+    }
+    Ok(items)
+}
+
+fn find_mnemonic(table: &OpcodeTable, word: &str) -> Option<Mnemonic> {
+    table.iter().flatten().map(|op| op.mnemonic).find(|m| m.to_string().eq_ignore_ascii_case(word))
+}
+
+fn parse_operand(s: &str, scope: &str) -> Result<Operand, String> {
+    let upper = s.to_ascii_uppercase().replace(' ', "");
+    if s.is_empty() {
+        return Ok(Operand::None);
+    }
+    if upper == "A" {
+        return Ok(Operand::Acc);
+    }
+    if let Some(expr) = s.strip_prefix('#') {
+        return Ok(Operand::Imm(parse_expr(expr, scope)?));
+    }
+    if s.starts_with('(') {
+        // Positions come from s itself, since upper has no spaces
+        if upper.ends_with(",X)") {
+            let comma = s.rfind(',').unwrap();
+            return Ok(Operand::Xind(parse_expr(&s[1..comma], scope)?));
+        }
+        if upper.ends_with("),Y") {
+            let close = s.rfind(')').unwrap();
+            return Ok(Operand::Indy(parse_expr(&s[1..close], scope)?));
+        }
+        if matching_paren(s) == Some(s.len() - 1) {
+            return Ok(Operand::Ind(parse_expr(&s[1..s.len() - 1], scope)?));
+        }
+    }
+    if upper.ends_with(",X") {
+        let comma = s.rfind(',').unwrap();
+        return Ok(Operand::X(parse_expr(&s[..comma], scope)?));
+    }
+    if upper.ends_with(",Y") {
+        let comma = s.rfind(',').unwrap();
+        return Ok(Operand::Y(parse_expr(&s[..comma], scope)?));
+    }
+    if let [zp, target] = split_list(s)?[..] {
+        return Ok(Operand::Zpr(parse_expr(zp, scope)?, parse_expr(target, scope)?));
+    }
+    Ok(Operand::Plain(parse_expr(s, scope)?))
+}
+
+// Index of the parenthesis that closes the one at the start of s
+fn matching_paren(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Expressions
+
+fn parse_expr(s: &str, scope: &str) -> Result<Expr, String> {
+    let mut parser = ExprParser { chars: s.chars().collect(), pos: 0, scope };
+    let expr = parser.binary(0)?;
+    parser.skip_space();
+    if parser.pos != parser.chars.len() {
+        return Err(format!("unexpected {} in {}", parser.chars[parser.pos], s.trim()));
+    }
+    Ok(expr)
+}
+
+struct ExprParser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    scope: &'a str,
+}
+
+// Binary operators from lowest to highest precedence
+const LEVELS: [&[(&str, BinOp)]; 6] = [
+    &[("|", BinOp::Or)],
+    &[("^", BinOp::Xor)],
+    &[("&", BinOp::And)],
+    &[("<<", BinOp::Shl), (">>", BinOp::Shr)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Rem)],
+];
+
+impl ExprParser<'_> {
+    fn skip_space(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        let end = self.pos + token.chars().count();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(token.chars()) {
+            self.pos = end;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        'outer: loop {
+            for (token, op) in LEVELS[level] {
+                if self.eat(token) {
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        for (token, op) in [("-", UnOp::Neg), ("~", UnOp::Not), ("<", UnOp::Lo), (">", UnOp::Hi)] {
+            if self.eat(token) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        self.skip_space();
+        let start = self.pos;
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let expr = self.binary(0)?;
+                if !self.eat(")") {
+                    return Err("missing )".to_string());
+                }
+                Ok(expr)
+            }
+            Some('*') => {
+                self.pos += 1;
+                Ok(Expr::Pc)
+            }
+            Some('$') => self.number(16, start + 1),
+            Some('%') => self.number(2, start + 1),
+            Some('\'') => {
+                let c = self.chars.get(self.pos + 1).copied();
+                if c.is_none() || self.chars.get(self.pos + 2) != Some(&'\'') {
+                    return Err("bad character literal".to_string());
+                }
+                self.pos += 3;
+                Ok(Expr::Num(c.unwrap() as i64))
+            }
+            Some(c) if c.is_ascii_digit() => self.number(10, start),
+            Some(c) if is_symbol_start(c) => {
+                self.pos += 1;
+                while self.peek().is_some_and(is_symbol_char) {
+                    self.pos += 1;
+                }
+                let name: String = self.chars[start..self.pos].iter().collect();
+                Ok(Expr::Sym(qualify(self.scope, &name)?))
+            }
+            Some(c) => Err(format!("unexpected {}", c)),
+            None => Err("missing expression".to_string()),
+        }
+    }
+
+    fn number(&mut self, radix: u32, digits: usize) -> Result<Expr, String> {
+        self.pos = digits;
+        while self.peek().is_some_and(|c| c.is_digit(radix)) {
+            self.pos += 1;
+        }
+        let text: String = self.chars[digits..self.pos].iter().collect();
+        i64::from_str_radix(&text, radix).map(Expr::Num).map_err(|_| format!("bad number {}", text))
+    }
+}
+
+// Assembling
+
+struct Assembler {
+    table: &'static OpcodeTable,
+    symbols: HashMap<String, i64>,
+    // Addressing mode picked for each instruction line in the first pass
+    modes: HashMap<usize, Mode>,
+    segments: Vec<Segment>,
+    pc: u32,
+}
+
+impl Assembler {
+    fn pass(&mut self, lines: &[Line], emit: bool) -> Result<(), AsmError> {
+        self.pc = 0;
+        self.segments.clear();
+        for line in lines {
+            let error = |message: String| AsmError { line: line.number, message };
+            self.line(line, emit).map_err(error)?;
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, line: &Line, emit: bool) -> Result<(), String> {
+        if let Some(label) = &line.label {
+            if !emit && self.symbols.contains_key(label) {
+                return Err(format!("{} is already defined", label));
+            }
+            self.symbols.insert(label.clone(), self.pc as i64);
+        }
+
+        let Some(statement) = &line.statement else {
+            return Ok(());
+        };
+
+        match statement {
+            Statement::Org(expr) => {
+                let address = self.eval(expr)?.ok_or("the .org address must be known in the first pass")?;
+                if !(0..=0xFFFF).contains(&address) {
+                    return Err(format!("address {} is out of range", address));
+                }
+                self.pc = address as u32;
+                if emit {
+                    self.segments.push(Segment { address: address as u16, bytes: Vec::new() });
+                }
+            }
+            Statement::Const(name, expr) => {
+                if !emit && self.symbols.contains_key(name) {
+                    return Err(format!("{} is already defined", name));
+                }
+                match self.eval(expr)? {
+                    Some(v) => { self.symbols.insert(name.clone(), v); }
+                    None if emit => return Err(format!("undefined symbol in {}", name)),
+                    None => {}
+                }
+            }
+            Statement::Byte(items) => {
+                for item in items {
+                    match item {
+                        Item::Str(bytes) => {
+                            for b in bytes {
+                                self.emit(*b, emit)?;
+                            }
+                        }
+                        Item::Expr(expr) => {
+                            let v = self.resolve(expr, emit)?;
+                            self.emit(self.byte(v)?, emit)?;
+                        }
+                    }
+                }
+            }
+            Statement::Word(items) => {
+                for expr in items {
+                    let v = self.word(self.resolve(expr, emit)?)?;
+                    self.emit(v as u8, emit)?;
+                    self.emit((v >> 8) as u8, emit)?;
+                }
+            }
+            Statement::Instruction(mnemonic, operand) => {
+                self.instruction(line.number, *mnemonic, operand, emit)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, number: usize, mnemonic: Mnemonic, operand: &Operand, emit: bool) -> Result<(), String> {
+        let mode = match self.modes.get(&number) {
+            Some(mode) => *mode,
+            None => {
+                let mode = self.pick_mode(mnemonic, operand)?;
+                self.modes.insert(number, mode);
+                mode
+            }
+        };
+        let opcode = find_opcode(self.table, mnemonic, mode).ok_or(format!("{} does not support {:?} addressing", mnemonic, mode))?;
+        let (expr, target) = match operand {
+            Operand::None | Operand::Acc => return self.emit(opcode, emit),
+            Operand::Zpr(e, target) => (e, Some(target)),
+            Operand::Imm(e) | Operand::Plain(e) | Operand::X(e) | Operand::Y(e) | Operand::Ind(e) | Operand::Xind(e) | Operand::Indy(e) => (e, None),
+        };
+        // * is the address of the instruction, so resolve before the opcode
+        let v = self.resolve(expr, emit)?;
+        let target = target.map(|target| self.resolve(target, emit)).transpose()?;
+        self.emit(opcode, emit)?;
+        match mode.bytes() {
+            2 if mode == Mode::Rel => self.branch(v, emit)?,
+            2 => self.emit(self.byte(v)?, emit)?,
+            3 if mode == Mode::Zpr => {
+                self.emit(self.byte(v)?, emit)?;
+                self.branch(target.unwrap_or(v), emit)?;
+            }
+            _ => {
+                let w = self.word(v)?;
+                self.emit(w as u8, emit)?;
+                self.emit((w >> 8) as u8, emit)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Emits the offset to target. It is the last byte of the instruction
+    // and relative to the next one.
+    fn branch(&mut self, target: i64, emit: bool) -> Result<(), String> {
+        let offset = target - (self.pc as i64 + 1);
+        if emit && !(-128..=127).contains(&offset) {
+            return Err(format!("branch target is {} bytes away", offset));
+        }
+        self.emit(offset as u8, emit)
+    }
+
+    fn pick_mode(&self, mnemonic: Mnemonic, operand: &Operand) -> Result<Mode, String> {
+        let has = |mode| find_opcode(self.table, mnemonic, mode).is_some();
+        // Zero page if the value is known now and fits, or is forced with < or >
+        let zpg = |expr: &Expr| -> Result<bool, String> {
+            Ok(matches!(expr, Expr::Unary(UnOp::Lo | UnOp::Hi, _)) || self.eval(expr)?.is_some_and(|v| (0..=0xFF).contains(&v)))
+        };
+        let mode = match operand {
+            Operand::None if has(Mode::Imp) => Mode::Imp,
+            Operand::None | Operand::Acc => Mode::Acc,
+            Operand::Imm(_) => Mode::Imm,
+            Operand::Plain(_) if has(Mode::Rel) => Mode::Rel,
+            Operand::Plain(e) if has(Mode::Zpg) && zpg(e)? => Mode::Zpg,
+            Operand::Plain(_) => Mode::Abs,
+            Operand::X(e) if has(Mode::Zpgx) && zpg(e)? => Mode::Zpgx,
+            Operand::X(_) => Mode::Absx,
+            Operand::Y(e) if has(Mode::Zpgy) && zpg(e)? => Mode::Zpgy,
+            Operand::Y(_) => Mode::Absy,
+            Operand::Ind(_) if has(Mode::Zpi) => Mode::Zpi,
+            Operand::Ind(_) => Mode::Ind,
+            Operand::Xind(_) if has(Mode::Absxi) => Mode::Absxi,
+            Operand::Xind(_) => Mode::Xind,
+            Operand::Indy(_) => Mode::Indy,
+            Operand::Zpr(..) => Mode::Zpr,
+        };
+        Ok(mode)
+    }
+
+    fn emit(&mut self, b: u8, emit: bool) -> Result<(), String> {
+        if self.pc > 0xFFFF {
+            return Err("past the end of memory".to_string());
+        }
+        if emit {
+            if self.segments.is_empty() {
+                self.segments.push(Segment { address: self.pc as u16, bytes: Vec::new() });
+            }
+            self.segments.last_mut().unwrap().bytes.push(b);
+        }
+        self.pc += 1;
+        Ok(())
+    }
+
+    // Unknown symbols are fine in the first pass, they are all defined by
+    // the second one
+    fn resolve(&self, expr: &Expr, emit: bool) -> Result<i64, String> {
+        match self.eval(expr)? {
+            Some(v) => Ok(v),
+            None if emit => Err(format!("undefined symbol in {}", describe(expr))),
+            None => Ok(0),
+        }
+    }
+
+    fn byte(&self, v: i64) -> Result<u8, String> {
+        if (-128..=255).contains(&v) {
+            Ok(v as u8)
+        } else {
+            Err(format!("{} does not fit in a byte", v))
+        }
+    }
+
+    fn word(&self, v: i64) -> Result<u16, String> {
+        if (-32768..=65535).contains(&v) {
+            Ok(v as u16)
+        } else {
+            Err(format!("{} does not fit in a word", v))
+        }
+    }
+
+    fn eval(&self, expr: &Expr) -> Result<Option<i64>, String> {
+        Ok(match expr {
+            Expr::Num(v) => Some(*v),
+            Expr::Sym(name) => self.symbols.get(name).copied(),
+            Expr::Pc => Some(self.pc as i64),
+            Expr::Unary(op, e) => self.eval(e)?.map(|v| match op {
+                UnOp::Neg => -v,
+                UnOp::Not => !v,
+                UnOp::Lo => v & 0xFF,
+                UnOp::Hi => (v >> 8) & 0xFF,
+            }),
+            Expr::Binary(op, lhs, rhs) => {
+                let (Some(a), Some(b)) = (self.eval(lhs)?, self.eval(rhs)?) else {
+                    return Ok(None);
+                };
+                Some(match op {
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::Mul => a.wrapping_mul(b),
+                    BinOp::Div | BinOp::Rem if b == 0 => return Err("division by zero".to_string()),
+                    BinOp::Div => a / b,
+                    BinOp::Rem => a % b,
+                    BinOp::And => a & b,
+                    BinOp::Or => a | b,
+                    BinOp::Xor => a ^ b,
+                    BinOp::Shl => a.wrapping_shl(b as u32),
+                    BinOp::Shr => a.wrapping_shr(b as u32),
+                })
+            }
+        })
+    }
+}
+
+fn describe(expr: &Expr) -> String {
+    match expr {
+        Expr::Sym(name) => name.clone(),
+        Expr::Unary(_, e) => describe(e),
+        Expr::Binary(_, lhs, rhs) => match describe(lhs) {
+            name if name.is_empty() => describe(rhs),
+            name => name,
+        },
+        _ => String::new(),
+    }
+}
+
+fn find_opcode(table: &OpcodeTable, mnemonic: Mnemonic, mode: Mode) -> Option<u8> {
+    let matches = |opcode: &u8| table[*opcode as usize].is_some_and(|op| op.mnemonic == mnemonic && op.mode == mode);
+    let documented = |opcode: &u8| OPCODES[*opcode as usize] == table[*opcode as usize];
+    (0..=255u8).filter(matches).min_by_key(|opcode| !documented(opcode))
+}
@@ -0,0 +1,198 @@
+use super::*;
+use asm::{assemble, assemble_with, Segment};
+
+fn bytes(source: &str) -> Vec<u8> {
+    let program = assemble(source).unwrap();
+    assert_eq!(1, program.segments.len());
+    program.segments[0].bytes.clone()
+}
+
+fn error(source: &str) -> String {
+    assemble(source).unwrap_err().to_string()
+}
+
+#[test]
+fn addressing_modes() {
+    let source = "
+        .org $0400
+        nop
+        asl
+        asl a
+        lda #$42
+        lda $42
+        lda $42,x
+        ldx $42,y
+        lda $1234
+        lda $1234,X
+        lda $1234,Y
+        lda $42,Y
+        jmp ($1234)
+        lda ($42,X)
+        lda ($42),Y
+    ";
+    assert_eq!(
+        vec![
+            0xEA, 0x0A, 0x0A, 0xA9, 0x42, 0xA5, 0x42, 0xB5, 0x42, 0xB6, 0x42, 0xAD, 0x34, 0x12, 0xBD, 0x34, 0x12, 0xB9,
+            0x34, 0x12, 0xB9, 0x42, 0x00, 0x6C, 0x34, 0x12, 0xA1, 0x42, 0xB1, 0x42,
+        ],
+        bytes(source)
+    );
+}
+
+#[test]
+fn operands_with_spaces() {
+    let source = "
+        .org $0400
+        lda ( $12 , x )
+        lda ( $12 ),y
+        lda $1234 , x
+        lda $12 , y
+        jmp ( $1234 )
+    ";
+    assert_eq!(vec![0xA1, 0x12, 0xB1, 0x12, 0xBD, 0x34, 0x12, 0xB9, 0x12, 0x00, 0x6C, 0x34, 0x12], bytes(source));
+    assert_eq!(vec![0x7C, 0x34, 0x12], assemble_with(&CMOS, ".org $0400\njmp ( $1234 ,x)").unwrap().segments[0].bytes);
+}
+
+#[test]
+fn labels_and_branches() {
+    let source = "
+        .org $0400
+start:  ldx #3
+loop:   dex
+        bne loop
+        beq done
+        nop
+done:   jmp start
+    ";
+    let program = assemble(source).unwrap();
+    assert_eq!(Some(0x0400), program.symbol("start"));
+    assert_eq!(Some(0x0402), program.symbol("loop"));
+    assert_eq!(Some(0x0408), program.symbol("done"));
+    assert_eq!(vec![0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0xF0, 0x01, 0xEA, 0x4C, 0x00, 0x04], program.segments[0].bytes);
+}
+
+#[test]
+fn forward_references_are_absolute() {
+    let source = "
+        .org $0400
+        lda later
+        lda early
+early = $10
+later = $10
+    ";
+    // `later` is not known in the first pass
+    assert_eq!(vec![0xAD, 0x10, 0x00, 0xAD, 0x10, 0x00], bytes(source));
+    assert_eq!(vec![0xA5, 0x10], bytes(".org $0400\nearly = $10\nlda early"));
+    assert_eq!(vec![0xA5, 0x10], bytes(".org $0400\nlda <later\nlater = $10"));
+}
+
+#[test]
+fn local_labels() {
+    let source = "
+        .org $0400
+one:    ldx #2
+@loop:  dex
+        bne @loop
+two:    ldy #2
+@loop:  dey
+        bne @loop
+    ";
+    let program = assemble(source).unwrap();
+    assert_eq!(Some(0x0402), program.symbol("one@loop"));
+    assert_eq!(Some(0x0407), program.symbol("two@loop"));
+    assert_eq!(vec![0xA2, 0x02, 0xCA, 0xD0, 0xFD, 0xA0, 0x02, 0x88, 0xD0, 0xFD], program.segments[0].bytes);
+}
+
+#[test]
+fn data_directives() {
+    let source = "
+        .org $1000
+table:  .byte 1, $02, %11, 'A', \"hi;\", -1
+        .word table, $BEEF
+    ";
+    assert_eq!(vec![1, 2, 3, 0x41, b'h', b'i', b';', 0xFF, 0x00, 0x10, 0xEF, 0xBE], bytes(source));
+}
+
+#[test]
+fn expressions() {
+    let source = "
+        .org $0400
+base = $1234
+        .byte <base, >base, <(base + $100), 2 + 3 * 4, (2 + 3) * 4
+        .byte 1 << 4 | 1, $F0 & $3C ^ 1, ~0 & $FF, 7 / 2, 7 % 2
+        .word *, * + 2
+    ";
+    assert_eq!(
+        vec![0x34, 0x12, 0x34, 14, 20, 0x11, 0x31, 0xFF, 3, 1, 0x0A, 0x04, 0x0E, 0x04],
+        bytes(source)
+    );
+}
+
+#[test]
+fn org_starts_segments() {
+    let source = "
+        .org $0400
+        nop
+        .org $FFFC
+        .word $0400
+    ";
+    let program = assemble(source).unwrap();
+    assert_eq!(
+        vec![Segment { address: 0x0400, bytes: vec![0xEA] }, Segment { address: 0xFFFC, bytes: vec![0x00, 0x04] }],
+        program.segments
+    );
+    assert_eq!(Some(0x0400), program.start());
+}
+
+#[test]
+fn errors() {
+    assert_eq!("line 2: unknown instruction foo", error("nop\nfoo"));
+    assert_eq!("line 1: undefined symbol in nowhere", error("jmp nowhere"));
+    assert_eq!("line 2: x is already defined", error("x: nop\nx: nop"));
+    assert_eq!("line 1: STA does not support Imm addressing", error("sta #1"));
+    assert_eq!("line 1: 256 does not fit in a byte", error("lda #256"));
+    assert_eq!("line 1: local label @x before any global label", error("@x: nop"));
+    assert!(error(".org $0400\nbne far\n.org $0500\nfar: nop").contains("branch target"));
+}
+
+#[test]
+fn load_into_cpu() {
+    let program = assemble(
+        "
+        .org $0400
+        lda #$42
+        sta result
+        brk
+result: .byte 0
+    ",
+    )
+    .unwrap();
+
+    let mut cpu = CPU::new();
+    program.load_into(&mut cpu);
+    cpu.set_pc(program.start().unwrap());
+    cpu.add_breakpoint(0x0405);
+    assert_eq!(Ok(StopReason::Breakpoint(0x0405)), cpu.run());
+    assert_eq!(0x42, cpu.peek(program.symbol("result").unwrap()));
+}
+
+#[test]
+fn illegal_opcodes() {
+    assert!(assemble("lax $10").is_err());
+    let program = assemble_with(&NMOS_ILLEGAL, ".org $0400\nlax $10\nnop\nsbc #1\nnop #2").unwrap();
+    // The documented NOP and SBC are used over their duplicates
+    assert_eq!(vec![0xA7, 0x10, 0xEA, 0xE9, 0x01, 0x80, 0x02], program.segments[0].bytes);
+}
+
+#[test]
+fn cmos_modes() {
+    let program = assemble_with(&CMOS, ".org $0400\nlda ($12)\njmp ($1234,x)\njmp ($1234)\nlda ($12,x)\ninc a\nbra *").unwrap();
+    assert_eq!(vec![0xB2, 0x12, 0x7C, 0x34, 0x12, 0x6C, 0x34, 0x12, 0xA1, 0x12, 0x1A, 0x80, 0xFE], program.segments[0].bytes);
+}
+
+#[test]
+fn bit_branches() {
+    let program = assemble_with(&ROCKWELL, ".org $0400\nloop: bbr7 $12,loop\nbbs0 $12,*+5").unwrap();
+    assert_eq!(vec![0x7F, 0x12, 0xFD, 0x8F, 0x12, 0x02], program.segments[0].bytes);
+    assert!(assemble(".org $0400\nbbr7 $12,*").is_err());
+}
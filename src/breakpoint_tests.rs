@@ -0,0 +1,91 @@
+use super::*;
+
+fn new_test_cpu() -> CPU {
+    let mut cpu = CPU::new();
+    cpu.mem[0x0400] = 0xA2; // LDX #$00
+    cpu.mem[0x0401] = 0x00;
+    cpu.mem[0x0402] = 0xE8; // INX
+    cpu.mem[0x0403] = 0x4C; // JMP $0402
+    cpu.mem[0x0404] = 0x02;
+    cpu.mem[0x0405] = 0x04;
+    cpu
+}
+
+#[test]
+fn run_stops_at_breakpoint() {
+    let mut cpu = new_test_cpu();
+    cpu.add_breakpoint(0x0403);
+    assert_eq!(cpu.run(), Ok(StopReason::Breakpoint(0x0403)));
+    assert_eq!(0x01, cpu.x);
+    assert_eq!(0x0403, cpu.pc);
+}
+
+#[test]
+fn run_resumes_from_breakpoint() {
+    let mut cpu = new_test_cpu();
+    cpu.add_breakpoint(0x0403);
+    assert_eq!(cpu.run(), Ok(StopReason::Breakpoint(0x0403)));
+    assert_eq!(cpu.run(), Ok(StopReason::Breakpoint(0x0403)));
+    assert_eq!(0x02, cpu.x);
+    cpu.remove_breakpoint(0x0403);
+    cpu.add_breakpoint(0x0400);
+    cpu.clear_breakpoints();
+    assert_eq!(cpu.run_for_instructions(10), Ok(StopReason::InstructionLimit));
+}
+
+#[test]
+fn run_until_condition() {
+    let mut cpu = new_test_cpu();
+    assert_eq!(cpu.run_until(|cpu| cpu.x == 0x10), Ok(StopReason::Condition));
+    assert_eq!(0x10, cpu.x);
+    assert_eq!(0x0403, cpu.pc);
+    assert_eq!(cpu.run_until(|cpu| cpu.x == 0x10), Ok(StopReason::Condition));
+    assert_eq!(0x0403, cpu.pc);
+}
+
+#[test]
+fn run_for_instructions() {
+    let mut cpu = new_test_cpu();
+    assert_eq!(cpu.run_for_instructions(5), Ok(StopReason::InstructionLimit));
+    assert_eq!(0x02, cpu.x);
+    assert_eq!(0x0402, cpu.pc);
+    assert_eq!(cpu.run_for_instructions(0), Ok(StopReason::InstructionLimit));
+    assert_eq!(0x0402, cpu.pc);
+}
+
+#[test]
+fn run_for_cycles() {
+    let mut cpu = new_test_cpu();
+    // LDX takes 2, then INX and JMP take 2 and 3
+    assert_eq!(cpu.run_for_cycles(7), Ok(StopReason::CycleLimit));
+    assert_eq!(7, cpu.cycles());
+    assert_eq!(cpu.run_for_cycles(1), Ok(StopReason::CycleLimit));
+    assert_eq!(9, cpu.cycles());
+}
+
+#[test]
+fn run_reports_errors() {
+    let mut cpu = new_test_cpu();
+    cpu.mem[0x0402] = 0xFF;
+    assert_eq!(cpu.run_for_cycles(100), Err(CPUError::IllegalInstruction { opcode: 0xFF, pc: 0x0402 }));
+}
+
+#[test]
+fn run_stops_at_a_breakpoint_on_the_first_instruction() {
+    let mut cpu = new_test_cpu();
+    cpu.add_breakpoint(0x0400);
+    assert_eq!(cpu.run(), Ok(StopReason::Breakpoint(0x0400)));
+    assert_eq!(0x0400, cpu.pc);
+    assert_eq!(0, cpu.cycles());
+
+    // Resuming steps over it, even after a run that executed nothing
+    assert_eq!(cpu.run_for_instructions(0), Ok(StopReason::InstructionLimit));
+    assert_eq!(cpu.run_for_instructions(1), Ok(StopReason::InstructionLimit));
+    assert_eq!(0x0402, cpu.pc);
+
+    // Stepping onto a breakpoint by hand does not count as stopping there
+    cpu.add_breakpoint(0x0403);
+    cpu.step().unwrap();
+    assert_eq!(cpu.run(), Ok(StopReason::Breakpoint(0x0403)));
+    assert_eq!(0x0403, cpu.pc);
+}
@@ -0,0 +1,169 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/
+
+use std::ops::{Index, IndexMut};
+
+/// Everything the CPU can see through its 16 bit address bus.
+///
+/// Reads take `&mut self` so that memory mapped devices can have side
+/// effects, like clearing a status register when it is read. Debugging
+/// tools look at memory with `peek` and change it with `poke`, which must
+/// not have side effects.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, v: u8);
+    fn peek(&self, address: u16) -> u8;
+
+    /// Store `v` at `address` without side effects or faults, so that
+    /// `peek` returns it where the address can hold a value. The default
+    /// calls `write`, so buses with memory mapped devices should override
+    /// it.
+    fn poke(&mut self, address: u16, v: u8) {
+        self.write(address, v);
+    }
+
+    /// Read from the 24 bit address bus of the 65C816. The default drops
+    /// the bank, as if only the low 16 address lines were connected.
+    fn read_long(&mut self, address: u32) -> u8 {
+        self.read(address as u16)
+    }
+
+    fn write_long(&mut self, address: u32, v: u8) {
+        self.write(address as u16, v);
+    }
+
+    fn peek_long(&self, address: u32) -> u8 {
+        self.peek(address as u16)
+    }
+
+    fn poke_long(&mut self, address: u32, v: u8) {
+        self.poke(address as u16, v);
+    }
+
+    /// Returns and clears the first fault since the last call. The CPU
+    /// calls this after every instruction.
+    fn take_fault(&mut self) -> Option<BusFault> {
+        None
+    }
+}
+
+/// An access the bus could not perform.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BusFault {
+    /// A write to a ROM region. The write was ignored.
+    RomWrite { address: u16, v: u8 },
+    /// A read from an address that is not mapped.
+    UnmappedRead { address: u16 },
+    /// A write to an address that is not mapped. The write was ignored.
+    UnmappedWrite { address: u16, v: u8 },
+}
+
+/// One bus cycle, as recorded by the CPU when its bus log is enabled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BusCycle {
+    pub address: u16,
+    pub data: u8,
+    pub write: bool,
+}
+
+/// Flat 64 KiB of RAM covering the whole address space.
+pub struct Ram {
+    bytes: Box<[u8]>,
+}
+
+impl Ram {
+    pub fn new() -> Self {
+        Ram { bytes: vec![0; 0x10000].into_boxed_slice() }
+    }
+}
+
+impl Default for Ram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for Ram {
+    fn read(&mut self, address: u16) -> u8 {
+        self.bytes[address as usize]
+    }
+
+    fn write(&mut self, address: u16, v: u8) {
+        self.bytes[address as usize] = v;
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.bytes[address as usize]
+    }
+
+    fn poke(&mut self, address: u16, v: u8) {
+        self.bytes[address as usize] = v;
+    }
+}
+
+/// Flat 16 MiB of RAM covering the whole 24 bit address space of the
+/// 65C816. The 16 bit accesses go to bank 0.
+pub struct LongRam {
+    bytes: Box<[u8]>,
+}
+
+impl LongRam {
+    pub fn new() -> Self {
+        LongRam { bytes: vec![0; 0x1000000].into_boxed_slice() }
+    }
+}
+
+impl Default for LongRam {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for LongRam {
+    fn read(&mut self, address: u16) -> u8 {
+        self.bytes[address as usize]
+    }
+
+    fn write(&mut self, address: u16, v: u8) {
+        self.bytes[address as usize] = v;
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.bytes[address as usize]
+    }
+
+    fn poke(&mut self, address: u16, v: u8) {
+        self.bytes[address as usize] = v;
+    }
+
+    fn read_long(&mut self, address: u32) -> u8 {
+        self.bytes[(address & 0xFFFFFF) as usize]
+    }
+
+    fn write_long(&mut self, address: u32, v: u8) {
+        self.bytes[(address & 0xFFFFFF) as usize] = v;
+    }
+
+    fn peek_long(&self, address: u32) -> u8 {
+        self.bytes[(address & 0xFFFFFF) as usize]
+    }
+
+    fn poke_long(&mut self, address: u32, v: u8) {
+        self.bytes[(address & 0xFFFFFF) as usize] = v;
+    }
+}
+
+impl Index<u16> for Ram {
+    type Output = u8;
+
+    fn index(&self, address: u16) -> &u8 {
+        &self.bytes[address as usize]
+    }
+}
+
+impl IndexMut<u16> for Ram {
+    fn index_mut(&mut self, address: u16) -> &mut u8 {
+        &mut self.bytes[address as usize]
+    }
+}
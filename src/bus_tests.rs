@@ -0,0 +1,89 @@
+use super::*;
+
+#[test]
+fn ram_covers_full_address_space() {
+    let mut ram = Ram::new();
+    ram.write(0x0000, 0x11);
+    ram.write(0x8000, 0x22);
+    ram.write(0xffff, 0x33);
+    assert_eq!(0x11, ram.read(0x0000));
+    assert_eq!(0x22, ram.read(0x8000));
+    assert_eq!(0x33, ram.read(0xffff));
+    assert_eq!(0x33, ram[0xffff]);
+}
+
+#[test]
+fn cpu_accesses_high_memory() {
+    let mut cpu = CPU::new();
+    cpu.mem[0xC000] = 0xAE; // LDX $1234
+    cpu.mem[0xC001] = 0x34;
+    cpu.mem[0xC002] = 0x12;
+    cpu.mem[0xC003] = 0x8E; // STX $FFF0
+    cpu.mem[0xC004] = 0xF0;
+    cpu.mem[0xC005] = 0xFF;
+    cpu.mem[0xC006] = 0xFF; // So we exit with CPUError::IllegalInstruction
+    cpu.mem[0x1234] = 0x42;
+    cpu.pc = 0xC000;
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
+    assert_eq!(0x42, cpu.mem[0xFFF0]);
+}
+
+// A device that counts accesses to a single register and is RAM elsewhere
+struct Counter {
+    ram: Ram,
+    reads: usize,
+    writes: Vec<u8>,
+}
+
+impl Bus for Counter {
+    fn read(&mut self, address: u16) -> u8 {
+        if address == 0xD000 {
+            self.reads += 1;
+            return self.reads as u8;
+        }
+        self.ram.read(address)
+    }
+
+    fn write(&mut self, address: u16, v: u8) {
+        if address == 0xD000 {
+            self.writes.push(v);
+            return;
+        }
+        self.ram.write(address, v);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.ram.peek(address)
+    }
+}
+
+#[test]
+fn cpu_with_custom_bus() {
+    let mut ram = Ram::new();
+    ram[0x0400] = 0xAE; // LDX $D000
+    ram[0x0401] = 0x00;
+    ram[0x0402] = 0xD0;
+    ram[0x0403] = 0x8E; // STX $D000
+    ram[0x0404] = 0x00;
+    ram[0x0405] = 0xD0;
+    ram[0x0406] = 0xFF; // So we exit with CPUError::IllegalInstruction
+    let mut cpu = CPU::with_bus(Counter { ram, reads: 0, writes: vec![] });
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
+    assert_eq!(0x01, cpu.x);
+    assert_eq!(1, cpu.mem.reads);
+    assert_eq!(vec![0x01], cpu.mem.writes);
+}
+
+#[test]
+fn dummy_writes_reach_the_bus() {
+    let mut ram = Ram::new();
+    ram[0x0400] = 0xEE; // INC $D000
+    ram[0x0401] = 0x00;
+    ram[0x0402] = 0xD0;
+    let mut cpu = CPU::with_bus(Counter { ram, reads: 0, writes: vec![] });
+    cpu.pc = 0x0400;
+    assert_eq!(Ok(6), cpu.step());
+    assert!(cpu.bus_log().is_empty());
+    assert_eq!(1, cpu.mem.reads);
+    assert_eq!(vec![0x01, 0x02], cpu.mem.writes);
+}
@@ -27,8 +27,8 @@ fn load_and_store() {
     cpu.mem[0x0401] = 0x42;
     cpu.mem[0x0402] = 0x85; // STA $07
     cpu.mem[0x0403] = 0x07;
-    cpu.mem[0x0405] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    cpu.mem[0x0404] = 0xFF; // So we exit with CPUError::IllegalInstruction
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x42, cpu.a);
     assert_eq!(0x42, cpu.mem[0x0007]);
 }
@@ -76,7 +76,7 @@ fn call_and_return() {
     cpu.mem[0x040B] = 0x86; // STX $06
     cpu.mem[0x040C] = 0x06;
     cpu.mem[0x040D] = 0x60; // RTS
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x65, cpu.mem[0x0005]);
     assert_eq!(0x02, cpu.mem[0x0006]);
 }
@@ -87,12 +87,12 @@ fn n_is_set() {
     cpu.mem[0x0400] = 0xA9; // LDA #$80
     cpu.mem[0x0401] = 0x80;
     cpu.mem[0x0402] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert!(cpu.p.contains(Status::N));
     cpu.mem[0x0403] = 0xA9; // LDA #$7F
     cpu.mem[0x0404] = 0x7F;
     cpu.mem[0x0405] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert!(!cpu.p.contains(Status::N));
 }
 
@@ -102,11 +102,86 @@ fn z_is_set() {
     cpu.mem[0x0400] = 0xA9; // LDA #$00
     cpu.mem[0x0401] = 0x00;
     cpu.mem[0x0402] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert!(cpu.p.contains(Status::Z));
     cpu.mem[0x0403] = 0xA9; // LDA #$01
     cpu.mem[0x0404] = 0x01;
     cpu.mem[0x0405] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert!(!cpu.p.contains(Status::N));
-}
\ No newline at end of file
+}
+#[test]
+fn builder_and_accessors() {
+    let mut cpu = CPU::builder().variant(Variant::Cmos65C02).pc(0x0800).build();
+    assert_eq!(Variant::Cmos65C02, cpu.variant());
+    assert_eq!(0x0800, cpu.pc());
+    cpu.load(0x0800, &[0xE8, 0xC8]); // INX, INY
+    cpu.set_x(0x41);
+    cpu.set_y(0xFF);
+    cpu.set_p(Status::C);
+    assert_eq!(cpu.run_for_instructions(2), Ok(StopReason::InstructionLimit));
+    assert_eq!(0x42, cpu.x());
+    assert_eq!(0x00, cpu.y());
+    assert_eq!(Status::C | Status::Z, cpu.p());
+    assert_eq!(0xE8, cpu.peek(0x0800));
+    assert_eq!(0xC8, cpu.mem()[0x0801]);
+}
+
+#[test]
+fn error_display() {
+    let e: Box<dyn std::error::Error> = Box::new(CPUError::IllegalInstruction { opcode: 0xFF, pc: 0x0402 });
+    assert_eq!("illegal instruction FF at 0402", e.to_string());
+    assert_eq!("stack overflow at 0400", CPUError::StackOverflow { pc: 0x0400 }.to_string());
+}
+
+#[test]
+fn illegal_instruction_reports_opcode_and_pc() {
+    let mut cpu = CPU::new();
+    cpu.mem[0x0400] = 0xEA; // NOP
+    cpu.mem[0x0401] = 0xFF;
+    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction { opcode: 0xFF, pc: 0x0401 }));
+}
+
+#[test]
+fn jam_halts() {
+    let mut cpu = CPU::new();
+    cpu.mem[0x0400] = 0x02; // JAM
+    assert_eq!(cpu.step(), Err(CPUError::Jam { opcode: 0x02, pc: 0x0400 }));
+    assert_eq!(cpu.step(), Err(CPUError::Jam { opcode: 0x02, pc: 0x0400 }));
+    assert_eq!(0x0400, cpu.pc);
+}
+
+#[test]
+fn stack_wraps_by_default() {
+    let mut cpu = CPU::new();
+    cpu.s = 0x00;
+    cpu.mem[0x0400] = 0x48; // PHA
+    cpu.mem[0x0401] = 0x68; // PLA
+    assert_eq!(cpu.step(), Ok(3));
+    assert_eq!(0xff, cpu.s);
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(0x00, cpu.s);
+}
+
+#[test]
+fn strict_stack_reports_overflow_and_underflow() {
+    let mut cpu = CPU::builder().strict_stack(true).build();
+    cpu.s = 0x00;
+    cpu.mem[0x0400] = 0x48; // PHA
+    cpu.mem[0x0401] = 0x68; // PLA
+    assert_eq!(cpu.step(), Err(CPUError::StackOverflow { pc: 0x0400 }));
+    assert_eq!(cpu.step(), Err(CPUError::StackUnderflow { pc: 0x0401 }));
+}
+
+#[test]
+fn jmp_indirect_does_not_carry_into_the_pointer_high_byte() {
+    for (variant, target) in [(Variant::Nmos6502, 0x1234), (Variant::Cmos65C02, 0x5634)] {
+        let mut cpu = CPU::builder().variant(variant).build();
+        cpu.load(0x0400, &[0x6C, 0xFF, 0x10]); // JMP ($10FF)
+        cpu.mem[0x10FF] = 0x34;
+        cpu.mem[0x1000] = 0x12;
+        cpu.mem[0x1100] = 0x56;
+        cpu.step().unwrap();
+        assert_eq!(target, cpu.pc, "{:?}", variant);
+    }
+}
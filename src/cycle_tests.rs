@@ -0,0 +1,194 @@
+use super::*;
+
+fn step(cpu: &mut CPU, program: &[u8]) -> Result<u8, CPUError> {
+    for (i, b) in program.iter().enumerate() {
+        cpu.mem[0x0400 + i as u16] = *b;
+    }
+    cpu.pc = 0x0400;
+    cpu.step()
+}
+
+#[test]
+fn base_cycles() {
+    let mut cpu = CPU::new();
+    assert_eq!(step(&mut cpu, &[0xEA]), Ok(2)); // NOP
+    assert_eq!(step(&mut cpu, &[0xA9, 0x01]), Ok(2)); // LDA #$01
+    assert_eq!(step(&mut cpu, &[0x85, 0x10]), Ok(3)); // STA $10
+    assert_eq!(step(&mut cpu, &[0xE6, 0x10]), Ok(5)); // INC $10
+    assert_eq!(step(&mut cpu, &[0x20, 0x00, 0x05]), Ok(6)); // JSR $0500
+    assert_eq!(18, cpu.cycles());
+}
+
+#[test]
+fn absx_page_cross() {
+    let mut cpu = CPU::new();
+    cpu.x = 0x01;
+    assert_eq!(step(&mut cpu, &[0xBD, 0xFE, 0x10]), Ok(4)); // LDA $10FE,X
+    assert_eq!(step(&mut cpu, &[0xBD, 0xFF, 0x10]), Ok(5)); // LDA $10FF,X
+    assert_eq!(step(&mut cpu, &[0x9D, 0xFF, 0x10]), Ok(5)); // STA $10FF,X
+    assert_eq!(step(&mut cpu, &[0xFE, 0xFE, 0x10]), Ok(7)); // INC $10FE,X
+    assert_eq!(step(&mut cpu, &[0xFE, 0xFF, 0x10]), Ok(7)); // INC $10FF,X
+}
+
+#[test]
+fn cmos_shifts_absx_page_cross() {
+    let mut cpu = CPU::builder().variant(Variant::Cmos65C02).bus_log(true).build();
+    cpu.x = 0x01;
+    for opcode in [0x1E, 0x3E, 0x5E, 0x7E] { // ASL, ROL, LSR, ROR
+        assert_eq!(step(&mut cpu, &[opcode, 0xFE, 0x10]), Ok(6), "{:02X}", opcode);
+        assert_eq!(6, cpu.bus_log().len());
+        assert_eq!(step(&mut cpu, &[opcode, 0xFF, 0x10]), Ok(7), "{:02X}", opcode);
+        assert_eq!(7, cpu.bus_log().len());
+    }
+    assert_eq!(step(&mut cpu, &[0xFE, 0xFE, 0x10]), Ok(7)); // INC $10FE,X
+    assert_eq!(step(&mut cpu, &[0xDE, 0xFF, 0x10]), Ok(7)); // DEC $10FF,X
+}
+
+#[test]
+fn absy_page_cross() {
+    let mut cpu = CPU::new();
+    cpu.y = 0x10;
+    assert_eq!(step(&mut cpu, &[0xB9, 0x00, 0x10]), Ok(4)); // LDA $1000,Y
+    assert_eq!(step(&mut cpu, &[0xB9, 0xF0, 0x10]), Ok(5)); // LDA $10F0,Y
+}
+
+#[test]
+fn indy_page_cross() {
+    let mut cpu = CPU::new();
+    cpu.mem[0x0010] = 0x80;
+    cpu.mem[0x0011] = 0x20;
+    cpu.y = 0x7F;
+    assert_eq!(step(&mut cpu, &[0xB1, 0x10]), Ok(5)); // LDA ($10),Y
+    cpu.y = 0x80;
+    assert_eq!(step(&mut cpu, &[0xB1, 0x10]), Ok(6)); // LDA ($10),Y
+    assert_eq!(step(&mut cpu, &[0x91, 0x10]), Ok(6)); // STA ($10),Y
+}
+
+#[test]
+fn branch_penalties() {
+    let mut cpu = CPU::new();
+    cpu.p.insert(Status::Z);
+    assert_eq!(step(&mut cpu, &[0xD0, 0x10]), Ok(2)); // BNE not taken
+    assert_eq!(step(&mut cpu, &[0xF0, 0x10]), Ok(3)); // BEQ same page
+    assert_eq!(0x0412, cpu.pc);
+    assert_eq!(step(&mut cpu, &[0xF0, 0x80]), Ok(4)); // BEQ previous page
+    assert_eq!(0x0382, cpu.pc);
+}
+
+#[test]
+fn interrupts_take_seven_cycles() {
+    let mut cpu = CPU::new();
+    cpu.raise_irq();
+    assert_eq!(step(&mut cpu, &[0xEA]), Ok(7));
+    cpu.trigger_nmi();
+    assert_eq!(cpu.step(), Ok(7));
+    assert_eq!(14, cpu.cycles());
+}
+
+fn bus_log(cpu: &CPU) -> Vec<(u16, u8, bool)> {
+    cpu.bus_log().iter().map(|c| (c.address, c.data, c.write)).collect()
+}
+
+#[test]
+fn bus_log_has_one_entry_per_cycle() {
+    for variant in [Variant::Nmos6502, Variant::Cmos65C02, Variant::Rockwell65C02, Variant::Wdc65C02, Variant::Ricoh2A03] {
+        let opcodes = CPU::builder().variant(variant).illegal_opcodes(true).build().opcodes();
+        for (opcode, op) in opcodes.iter().enumerate() {
+            let Some(op) = op.filter(|op| op.mnemonic != Mnemonic::JAM) else {
+                continue;
+            };
+            // Operands that do and do not cross a page, and every flag set and
+            // clear so that each branch is taken once and not taken once
+            for (operand, p) in [(0x10, Status::empty()), (0xF0, Status::all())] {
+                let mut cpu = CPU::builder().variant(variant).illegal_opcodes(true).bus_log(true).build();
+                cpu.x = 0x20;
+                cpu.y = 0x20;
+                cpu.p = p;
+                cpu.mem[0x0010] = 0xF0;
+                cpu.mem[0x00F0] = 0xF0;
+                cpu.mem[0x00F1] = 0x12;
+                let cycles = step(&mut cpu, &[opcode as u8, operand, 0x12]).unwrap();
+                assert_eq!(cycles as usize, cpu.bus_log().len(), "{:?} {:02X} {} {:?}", variant, opcode, op.mnemonic, op.mode);
+            }
+        }
+    }
+}
+
+#[test]
+fn cmos_5c_reads_the_last_page() {
+    let mut cpu = CPU::builder().variant(Variant::Cmos65C02).bus_log(true).build();
+    assert_eq!(step(&mut cpu, &[0x5C, 0x34, 0x12]), Ok(8));
+    assert_eq!(vec![(0xFF34, 0x00, false); 5], bus_log(&cpu)[3..]);
+
+    // The NMOS opcode is an ordinary NOP abs,X
+    let mut cpu = CPU::builder().illegal_opcodes(true).bus_log(true).build();
+    assert_eq!(step(&mut cpu, &[0x5C, 0x34, 0x12]), Ok(4));
+    assert_eq!((0x1234, 0x00, false), bus_log(&cpu)[3]);
+}
+
+#[test]
+fn bus_log_is_off_by_default() {
+    let mut cpu = CPU::new();
+    assert_eq!(step(&mut cpu, &[0xEA]), Ok(2));
+    assert!(cpu.bus_log().is_empty());
+}
+
+#[test]
+fn read_modify_write_writes_twice() {
+    let mut cpu = CPU::builder().bus_log(true).build();
+    cpu.x = 0x01;
+    cpu.mem[0x1100] = 0x41;
+    assert_eq!(step(&mut cpu, &[0xFE, 0xFF, 0x10]), Ok(7)); // INC $10FF,X
+    assert_eq!(
+        vec![
+            (0x0400, 0xFE, false),
+            (0x0401, 0xFF, false),
+            (0x0402, 0x10, false),
+            (0x1000, 0x00, false),
+            (0x1100, 0x41, false),
+            (0x1100, 0x41, true),
+            (0x1100, 0x42, true),
+        ],
+        bus_log(&cpu)
+    );
+}
+
+#[test]
+fn page_cross_reads_the_wrong_page() {
+    let mut cpu = CPU::builder().bus_log(true).build();
+    cpu.y = 0x10;
+    assert_eq!(step(&mut cpu, &[0xB9, 0xF8, 0x10]), Ok(5)); // LDA $10F8,Y
+    assert_eq!((0x1008, 0x00, false), bus_log(&cpu)[3]);
+    assert_eq!((0x1108, 0x00, false), bus_log(&cpu)[4]);
+}
+
+#[test]
+fn jsr_and_rts_bus_cycles() {
+    let mut cpu = CPU::builder().bus_log(true).build();
+    cpu.mem[0x0500] = 0x60;
+    assert_eq!(step(&mut cpu, &[0x20, 0x00, 0x05]), Ok(6)); // JSR $0500
+    assert_eq!(
+        vec![
+            (0x0400, 0x20, false),
+            (0x0401, 0x00, false),
+            (0x01FF, 0x00, false),
+            (0x01FF, 0x04, true),
+            (0x01FE, 0x02, true),
+            (0x0402, 0x05, false),
+        ],
+        bus_log(&cpu)
+    );
+    assert_eq!(cpu.step(), Ok(6)); // RTS
+    assert_eq!(
+        vec![
+            (0x0500, 0x60, false),
+            (0x0501, 0x00, false),
+            (0x01FD, 0x00, false),
+            (0x01FE, 0x02, false),
+            (0x01FF, 0x04, false),
+            (0x0402, 0x05, false),
+        ],
+        bus_log(&cpu)
+    );
+    assert_eq!(0x0403, cpu.pc);
+}
@@ -0,0 +1,122 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/
+
+use std::fmt;
+use std::fmt::Write;
+
+use crate::bus::Bus;
+use crate::opcodes::{Mode, Opcode, OpcodeTable, OPCODES};
+
+/// A decoded instruction. Opcodes that are not in the opcode table
+/// decode to a one byte `.byte` directive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub opcode: Option<Opcode>,
+}
+
+impl Instruction {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The operand in standard syntax, for example `($12),Y`. Branch
+    /// targets are shown as absolute addresses.
+    pub fn operand(&self) -> String {
+        let Some(op) = self.opcode else {
+            return format!("${:02X}", self.bytes[0]);
+        };
+        let b = self.bytes.get(1).copied().unwrap_or(0);
+        let w = (self.bytes.get(2).copied().unwrap_or(0) as u16) << 8 | b as u16;
+        match op.mode {
+            Mode::Imp => String::new(),
+            Mode::Acc => "A".to_string(),
+            Mode::Imm => format!("#${:02X}", b),
+            Mode::Zpg => format!("${:02X}", b),
+            Mode::Zpgx => format!("${:02X},X", b),
+            Mode::Zpgy => format!("${:02X},Y", b),
+            Mode::Abs => format!("${:04X}", w),
+            Mode::Absx => format!("${:04X},X", w),
+            Mode::Absy => format!("${:04X},Y", w),
+            Mode::Ind => format!("(${:04X})", w),
+            Mode::Xind => format!("(${:02X},X)", b),
+            Mode::Indy => format!("(${:02X}),Y", b),
+            Mode::Zpi => format!("(${:02X})", b),
+            Mode::Absxi => format!("(${:04X},X)", w),
+            Mode::Rel => format!("${:04X}", self.branch_target()),
+            Mode::Zpr => format!("${:02X},${:04X}", b, self.branch_target()),
+        }
+    }
+
+    // The offset is the last byte, relative to the next instruction
+    fn branch_target(&self) -> u16 {
+        let offset = self.bytes[self.len() - 1] as i8 as i16;
+        (self.address.wrapping_add(self.len() as u16) as i16).wrapping_add(offset) as u16
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.opcode {
+            Some(op) if op.mode == Mode::Imp => write!(f, "{}", op.mnemonic),
+            Some(op) => write!(f, "{} {}", op.mnemonic, self.operand()),
+            None => write!(f, ".byte {}", self.operand()),
+        }
+    }
+}
+
+/// Decode the instruction at `address` without side effects.
+pub fn disassemble<B: Bus>(mem: &B, address: u16) -> Instruction {
+    disassemble_with(&OPCODES, mem, address)
+}
+
+/// Like `disassemble`, with another opcode table such as
+/// `CPU::opcodes`.
+pub fn disassemble_with<B: Bus>(table: &OpcodeTable, mem: &B, address: u16) -> Instruction {
+    let opcode = table[mem.peek(address) as usize];
+    let len = opcode.map_or(1, |op| op.bytes);
+    let bytes = (0..len).map(|i| mem.peek(address.wrapping_add(i as u16))).collect();
+    Instruction { address, bytes, opcode }
+}
+
+/// Decode the instructions that start between `start` and `end`, both
+/// inclusive.
+pub fn disassemble_range<B: Bus>(mem: &B, start: u16, end: u16) -> Vec<Instruction> {
+    disassemble_range_with(&OPCODES, mem, start, end)
+}
+
+pub fn disassemble_range_with<B: Bus>(table: &OpcodeTable, mem: &B, start: u16, end: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut address = start as u32;
+    while address <= end as u32 {
+        let instruction = disassemble_with(table, mem, address as u16);
+        address += instruction.len() as u32;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+/// A listing with one instruction per line, like:
+///
+/// ```text
+/// 0400  A9 42     LDA #$42
+/// 0402  8D 00 02  STA $0200
+/// ```
+pub fn listing<B: Bus>(mem: &B, start: u16, end: u16) -> String {
+    listing_with(&OPCODES, mem, start, end)
+}
+
+pub fn listing_with<B: Bus>(table: &OpcodeTable, mem: &B, start: u16, end: u16) -> String {
+    let mut s = String::new();
+    for instruction in disassemble_range_with(table, mem, start, end) {
+        let bytes: Vec<String> = instruction.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        writeln!(s, "{:04X}  {:<8}  {}", instruction.address, bytes.join(" "), instruction).unwrap();
+    }
+    s
+}
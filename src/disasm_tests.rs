@@ -0,0 +1,94 @@
+use super::*;
+use disasm::{disassemble, disassemble_range, disassemble_with, listing};
+
+fn text(bytes: &[u8]) -> String {
+    let mut ram = Ram::new();
+    for (i, b) in bytes.iter().enumerate() {
+        ram[0x0400 + i as u16] = *b;
+    }
+    disassemble(&ram, 0x0400).to_string()
+}
+
+#[test]
+fn addressing_modes() {
+    assert_eq!("NOP", text(&[0xEA]));
+    assert_eq!("ASL A", text(&[0x0A]));
+    assert_eq!("LDA #$42", text(&[0xA9, 0x42]));
+    assert_eq!("LDA $42", text(&[0xA5, 0x42]));
+    assert_eq!("LDA $42,X", text(&[0xB5, 0x42]));
+    assert_eq!("LDX $42,Y", text(&[0xB6, 0x42]));
+    assert_eq!("LDA $1234", text(&[0xAD, 0x34, 0x12]));
+    assert_eq!("LDA $1234,X", text(&[0xBD, 0x34, 0x12]));
+    assert_eq!("LDA $1234,Y", text(&[0xB9, 0x34, 0x12]));
+    assert_eq!("JMP ($1234)", text(&[0x6C, 0x34, 0x12]));
+    assert_eq!("LDA ($42,X)", text(&[0xA1, 0x42]));
+    assert_eq!("LDA ($42),Y", text(&[0xB1, 0x42]));
+    assert_eq!(".byte $FF", text(&[0xFF]));
+}
+
+#[test]
+fn branch_targets() {
+    assert_eq!("BNE $0412", text(&[0xD0, 0x10]));
+    assert_eq!("BEQ $03FE", text(&[0xF0, 0xFC]));
+}
+
+#[test]
+fn instruction_fields() {
+    let mut ram = Ram::new();
+    ram[0x0400] = 0x8D;
+    ram[0x0401] = 0x00;
+    ram[0x0402] = 0x02;
+    let instruction = disassemble(&ram, 0x0400);
+    assert_eq!(3, instruction.len());
+    assert_eq!(vec![0x8D, 0x00, 0x02], instruction.bytes);
+    assert_eq!(Some(Mnemonic::STA), instruction.opcode.map(|op| op.mnemonic));
+    assert_eq!("$0200", instruction.operand());
+}
+
+#[test]
+fn range_listing() {
+    let mut cpu = CPU::new();
+    cpu.load(0x0400, &[0xA9, 0x42, 0x8D, 0x00, 0x02, 0xEA, 0xFF]);
+    assert_eq!(4, disassemble_range(cpu.mem(), 0x0400, 0x0406).len());
+    let expected = "\
+0400  A9 42     LDA #$42
+0402  8D 00 02  STA $0200
+0405  EA        NOP
+0406  FF        .byte $FF
+";
+    assert_eq!(expected, listing(cpu.mem(), 0x0400, 0x0406));
+}
+
+#[test]
+fn range_ends_at_top_of_memory() {
+    let ram = Ram::new();
+    assert_eq!(2, disassemble_range(&ram, 0xFFFE, 0xFFFF).len());
+}
+
+#[test]
+fn illegal_opcodes() {
+    let mut ram = Ram::new();
+    ram[0x0400] = 0xA7;
+    ram[0x0401] = 0x10;
+    assert_eq!(".byte $A7", disassemble(&ram, 0x0400).to_string());
+    assert_eq!("LAX $10", disassemble_with(&NMOS_ILLEGAL, &ram, 0x0400).to_string());
+}
+
+#[test]
+fn cmos_modes() {
+    let mut ram = Ram::new();
+    for (i, b) in [0xB2, 0x12, 0x7C, 0x34, 0x12].into_iter().enumerate() {
+        ram[0x0400 + i as u16] = b;
+    }
+    let text: Vec<String> = disasm::disassemble_range_with(&CMOS, &ram, 0x0400, 0x0404).iter().map(|i| i.to_string()).collect();
+    assert_eq!(vec!["LDA ($12)", "JMP ($1234,X)"], text);
+}
+
+#[test]
+fn bit_branches() {
+    let mut ram = Ram::new();
+    for (i, b) in [0x7F, 0x12, 0xFD].into_iter().enumerate() {
+        ram[0x0400 + i as u16] = b;
+    }
+    assert_eq!("BBR7 $12,$0400", disassemble_with(&ROCKWELL, &ram, 0x0400).to_string());
+}
@@ -0,0 +1,86 @@
+// Klaus Dormann's functional and decimal tests, see
+// https://github.com/Klaus2m5/6502_65C02_functional_tests
+//
+// The binaries are not part of this repository, so the tests are ignored
+// by default. Put them in tests/roms, or point CPU_TEST_ROMS at the
+// directory that has them, and run cargo test -- --ignored. A missing
+// binary is a failure then.
+
+use super::*;
+use std::path::PathBuf;
+
+// Addresses in the standard builds of the tests
+const FUNCTIONAL_START: u16 = 0x0400;
+const FUNCTIONAL_SUCCESS: u16 = 0x3469;
+const FUNCTIONAL_TEST_CASE: u16 = 0x0200;
+const DECIMAL_START: u16 = 0x0200;
+const DECIMAL_ERROR: u16 = 0x000B;
+
+fn rom(name: &str) -> Vec<u8> {
+    let dir = std::env::var_os("CPU_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms"));
+    let path = dir.join(name);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("can not read {}, {}", path.display(), e))
+}
+
+// The tests signal both success and failure with an instruction that
+// jumps to itself. Returns the address of that instruction.
+fn run_to_trap(cpu: &mut CPU) -> Result<u16, CPUError> {
+    loop {
+        let pc = cpu.pc;
+        cpu.step()?;
+        if cpu.pc == pc {
+            return Ok(pc);
+        }
+    }
+}
+
+fn context(cpu: &CPU, pc: u16) -> String {
+    let start = pc.saturating_sub(0x10);
+    format!(
+        "A={:02X} X={:02X} Y={:02X} S={:02X} P={:?}\n{}",
+        cpu.a,
+        cpu.x,
+        cpu.y,
+        cpu.s,
+        cpu.p,
+        disasm::listing(&cpu.mem, start, pc)
+    )
+}
+
+#[test]
+#[ignore = "needs 6502_functional_test.bin, see the top of this file"]
+fn functional_test() {
+    let bin = rom("6502_functional_test.bin");
+    let mut cpu = CPU::new();
+    cpu.load(0x0000, &bin);
+    cpu.set_pc(FUNCTIONAL_START);
+
+    let result = run_to_trap(&mut cpu);
+    let test_case = cpu.peek(FUNCTIONAL_TEST_CASE);
+    match result {
+        Ok(FUNCTIONAL_SUCCESS) => {}
+        Ok(pc) => panic!("test case {:02X} failed, trapped at {:04X}\n{}", test_case, pc, context(&cpu, pc)),
+        Err(e) => panic!("test case {:02X} failed, {}\n{}", test_case, e, context(&cpu, cpu.pc)),
+    }
+}
+
+#[test]
+#[ignore = "needs 6502_decimal_test.bin, see the top of this file"]
+fn decimal_test() {
+    let bin = rom("6502_decimal_test.bin");
+    let mut cpu = CPU::new();
+    cpu.load(DECIMAL_START, &bin);
+    cpu.set_pc(DECIMAL_START);
+
+    // Depending on how it was built, the test ends in a trap or on the
+    // 65C02 STP, which the NMOS part does not have. Either way ERROR has
+    // the result. Any other error is a real failure.
+    let pc = match run_to_trap(&mut cpu) {
+        Ok(pc) | Err(CPUError::IllegalInstruction { opcode: 0xDB, pc }) => pc,
+        Err(e) => panic!("decimal test stopped, {}\n{}", e, context(&cpu, cpu.pc)),
+    };
+    let error = cpu.peek(DECIMAL_ERROR);
+    assert_eq!(0, error, "decimal test failed at {:04X}\n{}", pc, context(&cpu, pc));
+}
@@ -0,0 +1,102 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/
+
+use std::collections::VecDeque;
+
+use crate::bus::Bus;
+use crate::{Registers, CPU};
+
+// One instruction: the registers before it and the old value of every
+// byte it wrote, in the order it wrote them
+struct Step {
+    registers: Registers,
+    writes: Vec<(u16, u8)>,
+}
+
+// The last `limit` instructions, oldest first. Each one only keeps the
+// few bytes it wrote, so the memory used is bounded by the limit.
+pub(crate) struct History {
+    limit: usize,
+    steps: VecDeque<Step>,
+    // The instruction that is running, which tick may be part way through
+    current: Option<Step>,
+}
+
+impl History {
+    pub(crate) fn new(limit: usize) -> Self {
+        History { limit, steps: VecDeque::new(), current: None }
+    }
+
+    pub(crate) fn begin(&mut self, registers: Registers) {
+        if self.current.is_none() {
+            self.current = Some(Step { registers, writes: Vec::new() });
+        }
+    }
+
+    pub(crate) fn write(&mut self, address: u16, old: u8) {
+        if let Some(step) = &mut self.current {
+            step.writes.push((address, old));
+        }
+    }
+
+    pub(crate) fn end(&mut self) {
+        if let Some(step) = self.current.take() {
+            if self.steps.len() == self.limit {
+                self.steps.pop_front();
+            }
+            self.steps.push_back(step);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.steps.clear();
+        self.current = None;
+    }
+}
+
+impl<B: Bus> CPU<B> {
+    /// Record the last `steps` instructions so that `step_back` and
+    /// `rewind_to` can undo them. 0 turns the history off. Changes made
+    /// between instructions, with `load` or the register setters, are not
+    /// recorded.
+    pub fn set_history(&mut self, steps: usize) {
+        self.history = if steps == 0 { None } else { Some(History::new(steps)) };
+    }
+
+    /// The number of instructions `step_back` can undo.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |history| history.steps.len())
+    }
+
+    /// Undo the last instruction, putting back the registers, the interrupt
+    /// state, the cycle counter and the memory it wrote. Memory is put back
+    /// with `Bus::poke`, so memory mapped devices see no writes. An
+    /// instruction that `tick` is part way through is undone first. Returns
+    /// false when there is nothing left to undo.
+    pub fn step_back(&mut self) -> bool {
+        let Some(history) = &mut self.history else {
+            return false;
+        };
+        let Some(step) = history.current.take().or_else(|| history.steps.pop_back()) else {
+            return false;
+        };
+        for (address, old) in step.writes.iter().rev() {
+            self.mem.poke(*address, *old);
+        }
+        self.restore(&step.registers);
+        self.tick = None;
+        true
+    }
+
+    /// Step back to the start of the instruction that was running at
+    /// `cycle`. Returns false if the history runs out first.
+    pub fn rewind_to(&mut self, cycle: u64) -> bool {
+        while self.cycles > cycle {
+            if !self.step_back() {
+                return false;
+            }
+        }
+        true
+    }
+}
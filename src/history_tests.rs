@@ -0,0 +1,174 @@
+use super::*;
+
+fn registers(cpu: &CPU) -> (u16, u8, u8, u8, u8, Status, u64) {
+    (cpu.pc(), cpu.a(), cpu.x(), cpu.y(), cpu.s(), cpu.p(), cpu.cycles())
+}
+
+fn cpu_with(program: &[u8]) -> CPU {
+    let mut cpu = CPU::builder().history(100).build();
+    cpu.load(0x0400, program);
+    cpu
+}
+
+#[test]
+fn history_is_off_by_default() {
+    let mut cpu = CPU::new();
+    cpu.load(0x0400, &[0xE8]); // INX
+    cpu.step().unwrap();
+    assert_eq!(0, cpu.history_len());
+    assert!(!cpu.step_back());
+    assert_eq!(1, cpu.x());
+}
+
+#[test]
+fn step_back_undoes_registers_and_memory() {
+    let mut cpu = cpu_with(&[
+        0xA9, 0x42, // LDA #$42
+        0x85, 0x10, // STA $10
+        0xE6, 0x10, // INC $10
+        0x48,       // PHA
+    ]);
+    let mut seen = vec![(registers(&cpu), cpu.peek(0x0010), cpu.peek(0x01FF))];
+    for _ in 0..4 {
+        cpu.step().unwrap();
+        seen.push((registers(&cpu), cpu.peek(0x0010), cpu.peek(0x01FF)));
+    }
+    assert_eq!(4, cpu.history_len());
+    assert_eq!((0x43, 0x42), (cpu.peek(0x0010), cpu.peek(0x01FF)));
+
+    seen.pop();
+    while let Some(expected) = seen.pop() {
+        assert!(cpu.step_back());
+        assert_eq!(expected, (registers(&cpu), cpu.peek(0x0010), cpu.peek(0x01FF)));
+    }
+    assert!(!cpu.step_back());
+    assert_eq!(0, cpu.history_len());
+}
+
+#[test]
+fn step_back_undoes_interrupts() {
+    let mut cpu = cpu_with(&[0xEA]); // NOP
+    cpu.load(0xFFFA, &[0x00, 0x05]);
+    cpu.trigger_nmi();
+    assert_eq!(Ok(7), cpu.step());
+    assert_eq!(0x0500, cpu.pc());
+    assert!(cpu.step_back());
+    assert_eq!((0x0400, 0xFF, 0), (cpu.pc(), cpu.s(), cpu.cycles()));
+    assert_eq!([0, 0, 0], [cpu.peek(0x01FD), cpu.peek(0x01FE), cpu.peek(0x01FF)]);
+
+    // The NMI is pending again
+    assert_eq!(Ok(7), cpu.step());
+    assert_eq!(0x0500, cpu.pc());
+}
+
+#[test]
+fn history_is_bounded() {
+    let mut cpu = cpu_with(&[0xE8; 10]); // INX
+    cpu.set_history(3);
+    cpu.run_for_instructions(10).unwrap();
+    assert_eq!(3, cpu.history_len());
+    for x in [9, 8, 7] {
+        assert!(cpu.step_back());
+        assert_eq!(x, cpu.x());
+    }
+    assert!(!cpu.step_back());
+    assert_eq!(7, cpu.x());
+}
+
+#[test]
+fn rewind_to_cycle() {
+    let mut cpu = cpu_with(&[
+        0xA2, 0x00, // LDX #$00      2 cycles
+        0xE8,       // loop: INX     2 cycles
+        0x96, 0x10, // STX $10,Y     4 cycles
+        0xC8,       // INY           2 cycles
+        0xD0, 0xFA, // BNE loop      3 cycles
+    ]);
+    cpu.run_for_cycles(100).unwrap();
+    let end = (registers(&cpu), cpu.peek(0x0010), cpu.peek(0x0018));
+
+    // Cycle 40 is part way through the fourth STX, from 37 to 41
+    assert!(cpu.rewind_to(40));
+    assert_eq!(37, cpu.cycles());
+    assert_eq!(0x0403, cpu.pc());
+    assert_eq!((3, 0), (cpu.peek(0x0012), cpu.peek(0x0013)));
+
+    // Running forward again ends up in the same place
+    cpu.run_for_cycles(100 - 37).unwrap();
+    assert_eq!(end, (registers(&cpu), cpu.peek(0x0010), cpu.peek(0x0018)));
+
+    assert!(cpu.rewind_to(0));
+    assert_eq!(registers(&cpu), (0x0400, 0, 0, 0, 0xFF, Status::empty(), 0));
+    assert_eq!(0, cpu.peek(0x0010));
+
+    cpu.set_history(2);
+    cpu.run_for_instructions(5).unwrap();
+    assert!(!cpu.rewind_to(0));
+    assert_eq!(8, cpu.cycles());
+}
+
+#[test]
+fn step_back_abandons_a_ticked_instruction() {
+    let mut cpu = cpu_with(&[0x20, 0x00, 0x05]); // JSR $0500
+    // The return address is pushed on cycles 4 and 5
+    for _ in 0..5 {
+        assert_eq!(Ok(None), cpu.tick());
+    }
+    assert_eq!([0x04, 0x02], [cpu.peek(0x01FF), cpu.peek(0x01FE)]);
+    assert!(cpu.step_back());
+    assert_eq!([0x00, 0x00], [cpu.peek(0x01FF), cpu.peek(0x01FE)]);
+    assert_eq!((0x0400, 0), (cpu.pc(), cpu.cycles()));
+
+    // A ticked instruction is recorded once it finishes
+    while cpu.tick() == Ok(None) {}
+    assert_eq!(1, cpu.history_len());
+    assert!(cpu.step_back());
+    assert_eq!([0x00, 0x00], [cpu.peek(0x01FF), cpu.peek(0x01FE)]);
+    assert_eq!(0x0400, cpu.pc());
+}
+
+#[test]
+fn reset_clears_the_history() {
+    let mut cpu = cpu_with(&[0xE8]); // INX
+    cpu.step().unwrap();
+    cpu.reset();
+    assert_eq!(0, cpu.history_len());
+    assert!(!cpu.step_back());
+}
+
+// RAM that counts the writes made through the bus
+struct Device {
+    ram: Ram,
+    writes: usize,
+}
+
+impl Bus for Device {
+    fn read(&mut self, address: u16) -> u8 {
+        self.ram.read(address)
+    }
+
+    fn write(&mut self, address: u16, v: u8) {
+        self.writes += 1;
+        self.ram.write(address, v);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.ram.peek(address)
+    }
+
+    fn poke(&mut self, address: u16, v: u8) {
+        self.ram.poke(address, v);
+    }
+}
+
+#[test]
+fn step_back_pokes_memory() {
+    let mut ram = Ram::new();
+    ram[0x0400] = 0xE6; // INC $10
+    ram[0x0401] = 0x10;
+    let mut cpu = CPU::builder().history(1).build_with_bus(Device { ram, writes: 0 });
+    cpu.step().unwrap();
+    assert_eq!((1, 2), (cpu.peek(0x0010), cpu.mem.writes));
+    assert!(cpu.step_back());
+    assert_eq!((0, 2), (cpu.peek(0x0010), cpu.mem.writes));
+}
@@ -1,13 +1,14 @@
 use super::*;
 
+use testing::Snippet;
+
 #[test]
 fn lda_imm() {
     let mut cpu = CPU::new();
     cpu.mem[0x0400] = 0xA9; // LDX #$42
     cpu.mem[0x0401] = 0x42;
-    cpu.mem[0x0402] = 0x00;
-    cpu.mem[0x0403] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    cpu.mem[0x0402] = 0xFF; // So we exit with CPUError::IllegalInstruction
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x42, cpu.a);
 }
 
@@ -19,7 +20,7 @@ fn sta_zp() {
     cpu.mem[0x0401] = 0x07;
     cpu.mem[0x0402] = 0xFF;
     cpu.mem[0x0403] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x42, cpu.mem[0x07]);
 }
 
@@ -29,7 +30,7 @@ fn ldx_imm() {
     cpu.mem[0x0400] = 0xA2; // LDX #$65
     cpu.mem[0x0401] = 0x65;
     cpu.mem[0x0402] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x65, cpu.x);
 }
 
@@ -40,7 +41,7 @@ fn stx_zp() {
     cpu.mem[0x0400] = 0x86; // STX $07
     cpu.mem[0x0401] = 0x07;
     cpu.mem[0x0402] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x42, cpu.mem[0x07]);
 }
 
@@ -51,32 +52,34 @@ fn test_dex() {
     cpu.mem[0x0401] = 0x12;
     cpu.mem[0x0402] = 0xCA; // DEX
     cpu.mem[0x0403] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x11, cpu.x);
     assert!(cpu.p.is_empty());    
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_dex_z() {
     let mut cpu = CPU::new();
     cpu.mem[0x0400] = 0xA2; // LDX #$12
     cpu.mem[0x0401] = 0x01;
     cpu.mem[0x0402] = 0xCA; // DEX
     cpu.mem[0x0403] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x00, cpu.x);
     assert_eq!(cpu.p.contains(Status::Z), true);    
     assert_eq!(cpu.p.contains(Status::N), false);
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_dex_n() {
     let mut cpu = CPU::new();
     cpu.mem[0x0400] = 0xA2; // LDX #$12
     cpu.mem[0x0401] = 0x88;
     cpu.mem[0x0402] = 0xCA; // DEX
     cpu.mem[0x0403] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x87, cpu.x);
     assert_eq!(cpu.p.contains(Status::Z), false);
     assert_eq!(cpu.p.contains(Status::N), true);
@@ -89,37 +92,233 @@ fn test_dey() {
     cpu.mem[0x0401] = 0x12;
     cpu.mem[0x0402] = 0x88; // DEY
     cpu.mem[0x0403] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x11, cpu.y);
     assert!(cpu.p.is_empty());
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_dey_z() {
     let mut cpu = CPU::new();
     cpu.mem[0x0400] = 0xA0; // LDY #$12
     cpu.mem[0x0401] = 0x01;
     cpu.mem[0x0402] = 0x88; // DEX
     cpu.mem[0x0403] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x00, cpu.y);
     assert_eq!(cpu.p.contains(Status::Z), true);    
     assert_eq!(cpu.p.contains(Status::N), false);
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_dey_n() {
     let mut cpu = CPU::new();
     cpu.mem[0x0400] = 0xA0; // LDY #$12
     cpu.mem[0x0401] = 0x88;
     cpu.mem[0x0402] = 0x88; // DEY
     cpu.mem[0x0403] = 0xFF; // So we exit with CPUError::IllegalInstruction
-    assert_eq!(cpu.run(), Err(CPUError::IllegalInstruction));
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
     assert_eq!(0x87, cpu.y);
     assert_eq!(cpu.p.contains(Status::Z), false);
     assert_eq!(cpu.p.contains(Status::N), true);
 }
 
+#[test]
+fn asl_sets_carry() {
+    let run = asm!("lda #$81", "asl a").run();
+    assert_eq!(0x02, run.a);
+    assert_eq!(Status::C, run.p);
+}
+
+#[test]
+fn ror_shifts_right() {
+    let run = asm!("lda #$02", "sec", "ror a").run();
+    assert_eq!(0x81, run.a);
+    assert_eq!(Status::N, run.p);
+    let run = asm!("ror $10").poke(0x0010, &[0x01]).run();
+    assert_eq!(0x00, run.mem[0x0010]);
+    assert_eq!(Status::Z | Status::C, run.p);
+}
+
+#[test]
+fn bit_copies_bits_7_and_6() {
+    let run = asm!("lda #$01", "bit $10").poke(0x0010, &[0x40]).run();
+    assert_eq!(Status::V | Status::Z, run.p);
+    let run = asm!("lda #$80", "bit $10").poke(0x0010, &[0x80]).run();
+    assert_eq!(Status::N, run.p);
+}
+
+#[test]
+fn pla_sets_flags() {
+    let run = asm!("lda #$80", "pha", "lda #$01", "pla").run();
+    assert_eq!(0x80, run.a);
+    assert_eq!(Status::N, run.p);
+}
+
+#[test]
+fn jsr_pushes_the_address_of_its_last_byte() {
+    let run = asm!(
+        "       jsr sub",
+        "       jmp done",
+        "sub:   tsx",
+        "       rts",
+        "done:",
+    )
+    .run();
+    assert!(run.finished());
+    assert_eq!(0xFD, run.x);
+    assert_eq!(0xFF, run.s);
+    assert_eq!(0x04, run.mem[0x01FF]);
+    assert_eq!(0x02, run.mem[0x01FE]);
+}
+
+#[test]
+fn asl_memory_sets_carry() {
+    let run = asm!("asl $10").poke(0x0010, &[0x80]).run();
+    assert_eq!(0x00, run.mem[0x0010]);
+    assert_eq!(Status::Z | Status::C, run.p);
+}
+
+#[test]
+fn ror_memory_shifts_in_carry() {
+    let run = asm!("sec", "ror $10").poke(0x0010, &[0x02]).run();
+    assert_eq!(0x81, run.mem[0x0010]);
+    assert_eq!(Status::N, run.p);
+}
+
+#[test]
+fn bit_takes_v_from_bit_6_only() {
+    let run = asm!("lda #$FF", "bit $10").poke(0x0010, &[0x80]).run();
+    assert_eq!(Status::N, run.p);
+}
+
+#[test]
+fn pla_sets_zero() {
+    let run = asm!("lda #$00", "pha", "lda #$80", "pla").run();
+    assert_eq!(0x00, run.a);
+    assert_eq!(Status::Z, run.p);
+}
+
+#[test]
+fn rts_returns_after_the_jsr() {
+    let run = asm!(
+        "       jsr sub",
+        "       ldx #1",
+        "       jmp done",
+        "sub:   ldy #2",
+        "       rts",
+        "done:",
+    )
+    .run();
+    assert!(run.finished());
+    assert_eq!((1, 2), (run.x, run.y));
+}
+
+#[test]
+fn jmp_ind_page_wrap() {
+    let source = "jmp ($12FF)\n.org $0500\nlda #1\n.org $0600\nlda #2";
+    let pointer = |run: Snippet| run.poke(0x12FF, &[0x00, 0x06]).poke(0x1200, &[0x05]).limit(2).run();
+    assert_eq!(1, pointer(Snippet::new(source)).a);
+    assert_eq!(2, pointer(Snippet::new(source).variant(Variant::Cmos65C02)).a);
+}
+
+fn run_adc_sbc(variant: Variant, opcode: u8, p: Status, a: u8, m: u8) -> CPU {
+    let mut cpu = CPU::with_variant(Ram::new(), variant);
+    cpu.p = p;
+    cpu.a = a;
+    cpu.mem[0x0400] = opcode; // ADC/SBC #m
+    cpu.mem[0x0401] = m;
+    assert!(cpu.step().is_ok());
+    cpu
+}
+
+#[test]
+fn adc_imm() {
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0x69, Status::empty(), 0x50, 0x50);
+    assert_eq!(0xA0, cpu.a);
+    assert_eq!(Status::N | Status::V, cpu.p);
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0x69, Status::C, 0xFF, 0x00);
+    assert_eq!(0x00, cpu.a);
+    assert_eq!(Status::Z | Status::C, cpu.p);
+}
+
+#[test]
+fn sbc_imm() {
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0xE9, Status::C, 0x50, 0xB0);
+    assert_eq!(0xA0, cpu.a);
+    assert_eq!(Status::N | Status::V, cpu.p);
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0xE9, Status::empty(), 0x01, 0x00);
+    assert_eq!(0x00, cpu.a);
+    assert_eq!(Status::Z | Status::C, cpu.p);
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0xE9, Status::C, 0x00, 0x01);
+    assert_eq!(0xFF, cpu.a);
+    assert_eq!(Status::N, cpu.p);
+}
+
+#[test]
+fn adc_decimal() {
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0x69, Status::D, 0x09, 0x01);
+    assert_eq!(0x10, cpu.a);
+    assert!(!cpu.p.contains(Status::C));
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0x69, Status::D | Status::C, 0x58, 0x46);
+    assert_eq!(0x05, cpu.a);
+    assert!(cpu.p.contains(Status::C));
+}
+
+#[test]
+fn adc_decimal_nmos_flags() {
+    // Z comes from the binary sum $9A
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0x69, Status::D, 0x99, 0x01);
+    assert_eq!(0x00, cpu.a);
+    assert_eq!(Status::D | Status::N | Status::C, cpu.p);
+    // N and V come from the intermediate result $80
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0x69, Status::D | Status::C, 0x79, 0x00);
+    assert_eq!(0x80, cpu.a);
+    assert_eq!(Status::D | Status::N | Status::V, cpu.p);
+}
+
+#[test]
+fn adc_decimal_cmos_flags() {
+    let cpu = run_adc_sbc(Variant::Cmos65C02, 0x69, Status::D, 0x99, 0x01);
+    assert_eq!(0x00, cpu.a);
+    assert_eq!(Status::D | Status::Z | Status::C, cpu.p);
+}
+
+#[test]
+fn sbc_decimal() {
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0xE9, Status::D | Status::C, 0x46, 0x12);
+    assert_eq!(0x34, cpu.a);
+    assert!(cpu.p.contains(Status::C));
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0xE9, Status::D | Status::C, 0x00, 0x01);
+    assert_eq!(0x99, cpu.a);
+    assert!(!cpu.p.contains(Status::C));
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0xE9, Status::D, 0x40, 0x13);
+    assert_eq!(0x26, cpu.a);
+}
+
+#[test]
+fn sbc_decimal_nmos_flags() {
+    // N and Z come from the binary difference $00
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0xE9, Status::D | Status::C, 0x12, 0x12);
+    assert_eq!(0x00, cpu.a);
+    assert_eq!(Status::D | Status::Z | Status::C, cpu.p);
+    let cpu = run_adc_sbc(Variant::Nmos6502, 0xE9, Status::D, 0x00, 0x00);
+    assert_eq!(0x99, cpu.a);
+    assert_eq!(Status::D | Status::N, cpu.p);
+}
+
+#[test]
+fn sbc_decimal_cmos_flags() {
+    let cpu = run_adc_sbc(Variant::Cmos65C02, 0xE9, Status::D, 0x00, 0x00);
+    assert_eq!(0x99, cpu.a);
+    assert_eq!(Status::D | Status::N, cpu.p);
+    let cpu = run_adc_sbc(Variant::Cmos65C02, 0xE9, Status::D | Status::C, 0x20, 0x01);
+    assert_eq!(0x19, cpu.a);
+    assert_eq!(Status::D | Status::C, cpu.p);
+}
+
 // #[test]
 // fn casting_u8_to_i16() {
 //     let a: u8 = 0xFE; // -2
@@ -130,3 +329,314 @@ fn test_dey_n() {
 //     pc = t.wrapping_add(b) as u16;
 //     assert_eq!(pc, 0x03FE);
 // }
+
+fn illegal(lines: &[&str]) -> testing::Snippet {
+    testing::Snippet::new(&lines.join("\n")).illegal_opcodes(true)
+}
+
+#[test]
+fn lax_and_sax() {
+    let run = illegal(&["lax $10", "sax $11"]).poke(0x0010, &[0xF3]).run();
+    assert!(run.finished());
+    assert_eq!((0xF3, 0xF3), (run.a, run.x));
+    assert_eq!(0xF3, run.mem[0x0011]);
+    assert!(run.p.contains(Status::N));
+
+    let run = illegal(&["sax $10"]).a(0x3C).x(0x0F).p(Status::Z).run();
+    assert_eq!(0x0C, run.mem[0x0010]);
+    assert_eq!(Status::Z, run.p);
+}
+
+#[test]
+fn read_modify_write_combinations() {
+    // DCP decrements then compares
+    let run = illegal(&["dcp $10"]).a(0x41).poke(0x0010, &[0x42]).run();
+    assert_eq!(0x41, run.mem[0x0010]);
+    assert_eq!(Status::Z | Status::C, run.p);
+    // ISC increments then subtracts
+    let run = illegal(&["isc $10"]).a(0x50).p(Status::C).poke(0x0010, &[0x0F]).run();
+    assert_eq!(0x10, run.mem[0x0010]);
+    assert_eq!(0x40, run.a);
+    // SLO shifts left then ORs
+    let run = illegal(&["slo $10"]).a(0x01).poke(0x0010, &[0x81]).run();
+    assert_eq!(0x02, run.mem[0x0010]);
+    assert_eq!(0x03, run.a);
+    assert!(run.p.contains(Status::C));
+    // SRE shifts right then EORs
+    let run = illegal(&["sre $10"]).a(0xFF).poke(0x0010, &[0x03]).run();
+    assert_eq!(0x01, run.mem[0x0010]);
+    assert_eq!(0xFE, run.a);
+    assert!(run.p.contains(Status::C | Status::N));
+    // RLA rotates left then ANDs, RRA rotates right then adds
+    let run = illegal(&["rla $10"]).a(0x0F).p(Status::C).poke(0x0010, &[0x04]).run();
+    assert_eq!((0x09, 0x09), (run.mem[0x0010], run.a));
+    let run = illegal(&["rra $10"]).a(0x10).poke(0x0010, &[0x03]).run();
+    assert_eq!(0x01, run.mem[0x0010]);
+    assert_eq!(0x12, run.a);
+}
+
+#[test]
+fn immediate_combinations() {
+    let run = illegal(&["anc #$80"]).a(0xC0).run();
+    assert_eq!(0x80, run.a);
+    assert_eq!(Status::N | Status::C, run.p);
+    let run = illegal(&["alr #$03"]).a(0xFF).run();
+    assert_eq!(0x01, run.a);
+    assert_eq!(Status::C, run.p);
+    // ARR: C from bit 6, V from bit 6 xor bit 5
+    let run = illegal(&["arr #$FF"]).a(0x40).p(Status::C).run();
+    assert_eq!(0xA0, run.a);
+    assert_eq!(Status::N | Status::V, run.p);
+    // SBX: X = (A & X) - operand, without borrow
+    let run = illegal(&["sbx #$02"]).a(0x0F).x(0xFC).run();
+    assert_eq!(0x0A, run.x);
+    assert!(run.p.contains(Status::C));
+    let run = illegal(&["las $1000,y"]).poke(0x1000, &[0xF0]).run();
+    assert_eq!((0xF0, 0xF0, 0xF0), (run.a, run.x, run.s()));
+}
+
+#[test]
+fn ane_and_lxa_use_the_magic_constant() {
+    let run = illegal(&["ane #$FF"]).a(0x0F).x(0x3C).run();
+    assert_eq!((DEFAULT_MAGIC | 0x0F) & 0x3C, run.a);
+    let run = illegal(&["lxa #$F0"]).a(0x0F).run();
+    assert_eq!((DEFAULT_MAGIC | 0x0F) & 0xF0, run.a);
+    assert_eq!(run.a, run.x);
+
+    let mut cpu = CPU::builder().illegal_opcodes(true).magic(0x00).build();
+    cpu.a = 0x0F;
+    cpu.load(0x0400, &[0xAB, 0xFF]); // LXA #$FF
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!((0x0F, 0x0F), (cpu.a, cpu.x));
+}
+
+#[test]
+fn shx_page_cross_corrupts_the_address() {
+    // Stores X & (high byte + 1), and the high byte of the address
+    // becomes that value when the index crosses a page
+    let run = illegal(&["shx $10F0,y"]).x(0xFF).y(0x20).run();
+    assert_eq!(0x11, run.mem[0x1110]);
+    let run = illegal(&["shx $1000,y"]).x(0xFF).y(0x20).run();
+    assert_eq!(0x11, run.mem[0x1020]);
+}
+
+#[test]
+fn multi_byte_nops() {
+    let run = illegal(&["nop #$12", "nop $12", "nop $1234,x"]).run();
+    assert!(run.finished());
+    assert_eq!(2 + 3 + 4, run.cycles());
+}
+
+#[test]
+fn jam_halts_until_reset() {
+    let mut cpu = CPU::builder().illegal_opcodes(true).build();
+    cpu.load(0x0400, &[0x02, 0xEA]); // JAM, NOP
+    cpu.load(RESET_VECTOR, &[0x01, 0x04]);
+    assert_eq!(cpu.step(), Err(CPUError::Jam { opcode: 0x02, pc: 0x0400 }));
+    cpu.trigger_nmi();
+    assert_eq!(cpu.step(), Err(CPUError::Jam { opcode: 0x02, pc: 0x0400 }));
+    cpu.reset();
+    assert_eq!(cpu.step(), Ok(2));
+    assert_eq!(0x0402, cpu.pc);
+}
+
+fn cmos(lines: &[&str]) -> Snippet {
+    Snippet::new(&lines.join("\n")).variant(Variant::Cmos65C02)
+}
+
+#[test]
+fn bra_always_branches() {
+    let run = cmos(&["bra skip", "ldx #1", "skip: ldy #1"]).run();
+    assert!(run.finished());
+    assert_eq!((0, 1), (run.x, run.y));
+}
+
+#[test]
+fn push_and_pull_x_and_y() {
+    let run = cmos(&["phx", "phy", "plx", "ply"]).x(0x12).y(0x80).run();
+    assert!(run.finished());
+    assert_eq!((0x80, 0x12), (run.x, run.y));
+    assert!(!run.p.contains(Status::N));
+    assert_eq!(0xFF, run.s);
+}
+
+#[test]
+fn stz_stores_zero() {
+    let run = cmos(&["stz $10", "stz $10,x", "stz $1234", "stz $1234,x"]).x(1).poke(0x0010, &[1, 1]).poke(0x1234, &[1, 1]).run();
+    assert!(run.finished());
+    assert_eq!([0, 0, 0, 0], [run.mem[0x0010], run.mem[0x0011], run.mem[0x1234], run.mem[0x1235]]);
+}
+
+#[test]
+fn trb_and_tsb() {
+    let run = cmos(&["tsb $10", "trb $11"]).a(0x0F).poke(0x0010, &[0x30, 0x3C]).run();
+    assert_eq!(0x3F, run.mem[0x0010]);
+    assert_eq!(0x30, run.mem[0x0011]);
+    // Z is set from A & M before the change
+    assert!(!run.p.contains(Status::Z));
+    let run = cmos(&["tsb $10"]).a(0x0F).poke(0x0010, &[0x30]).run();
+    assert!(run.p.contains(Status::Z));
+}
+
+#[test]
+fn inc_and_dec_a() {
+    let run = cmos(&["inc a", "inc a"]).a(0xFF).run();
+    assert_eq!(0x01, run.a);
+    let run = cmos(&["dec"]).a(0x00).run();
+    assert_eq!(0xFF, run.a);
+    assert!(run.p.contains(Status::N));
+}
+
+#[test]
+fn zero_page_indirect() {
+    let run = cmos(&["lda ($10)", "sta ($12)"]).poke(0x0010, &[0x00, 0x20, 0x00, 0x21]).poke(0x2000, &[0x42]).run();
+    assert!(run.finished());
+    assert_eq!(0x42, run.a);
+    assert_eq!(0x42, run.mem[0x2100]);
+    assert_eq!(5 + 5, run.cycles());
+}
+
+#[test]
+fn jmp_absolute_indexed_indirect() {
+    let run = cmos(&["jmp ($1000,x)", ".org $0500", "lda #1"]).x(2).poke(0x1002, &[0x00, 0x05]).limit(2).run();
+    assert_eq!(1, run.a);
+}
+
+#[test]
+fn bit_immediate_only_changes_z() {
+    let run = cmos(&["bit #$C0"]).a(0x01).run();
+    assert_eq!(Status::Z, run.p);
+}
+
+#[test]
+fn cmos_interrupts_clear_d() {
+    let run = cmos(&["sed", "brk", "nop"]).poke(IRQ_VECTOR, &[0x00, 0x05]).limit(2).run();
+    assert_eq!(0x0500, run.pc);
+    assert!(!run.p.contains(Status::D));
+    assert!(run.mem[0x01FD] & Status::D.bits() != 0);
+
+    let run = asm!("sed", "brk", "nop").poke(IRQ_VECTOR, &[0x00, 0x05]).limit(2).run();
+    assert!(run.p.contains(Status::D));
+}
+
+#[test]
+fn cmos_unused_opcodes_are_nops() {
+    let mut cpu = CPU::builder().variant(Variant::Cmos65C02).build();
+    cpu.load(0x0400, &[0x03, 0x02, 0xFF, 0x5C, 0x34, 0x12]);
+    assert_eq!(Ok(1), cpu.step());
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(0x0403, cpu.pc);
+    assert_eq!(Ok(8), cpu.step());
+    assert_eq!(0x0406, cpu.pc);
+}
+
+#[test]
+fn cmos_decimal_takes_a_cycle() {
+    let run = cmos(&["adc #1", "sed", "adc #1", "sbc #1"]).run();
+    assert_eq!(2 + 2 + 3 + 3, run.cycles());
+    let run = asm!("adc #1", "sed", "adc #1", "sbc #1").run();
+    assert_eq!(2 + 2 + 2 + 2, run.cycles());
+}
+
+#[test]
+fn rmb_and_smb() {
+    let run = Snippet::new("rmb0 $10\nsmb7 $11").variant(Variant::Rockwell65C02).poke(0x0010, &[0xFF, 0x00]).run();
+    assert!(run.finished());
+    assert_eq!(0xFE, run.mem[0x0010]);
+    assert_eq!(0x80, run.mem[0x0011]);
+    assert_eq!(Status::empty(), run.p);
+}
+
+#[test]
+fn bbr_and_bbs() {
+    let source = "bbs1 $10,one\nbbr1 $10,zero\none: ldx #1\nzero: nop";
+    let run = Snippet::new(source).variant(Variant::Rockwell65C02).poke(0x0010, &[0xFD]).run();
+    assert!(run.finished());
+    assert_eq!(0, run.x);
+    // 5 cycles not taken, 6 taken
+    assert_eq!(5 + 6 + 2, run.cycles());
+    let run = Snippet::new(source).variant(Variant::Rockwell65C02).poke(0x0010, &[0x02]).run();
+    assert_eq!(1, run.x);
+}
+
+#[test]
+fn bit_instructions_need_rockwell_or_wdc() {
+    let mut cpu = CPU::builder().variant(Variant::Cmos65C02).build();
+    cpu.load(0x0400, &[0x87, 0x10]); // SMB0 $10 on Rockwell
+    assert_eq!(Ok(1), cpu.step());
+    assert_eq!(0, cpu.peek(0x0010));
+}
+
+#[test]
+fn wai_waits_for_an_interrupt() {
+    let mut cpu = CPU::builder().variant(Variant::Wdc65C02).build();
+    cpu.load(0x0400, &[0xCB, 0xE8]); // WAI, INX
+    cpu.load(IRQ_VECTOR, &[0x00, 0x05]);
+    cpu.load(0x0500, &[0x40]); // RTI
+    assert_eq!(Ok(StopReason::Waiting), cpu.run());
+    assert!(cpu.is_waiting());
+    assert_eq!(0x0401, cpu.pc);
+    assert_eq!(Ok(1), cpu.step());
+    assert_eq!(Ok(StopReason::Waiting), cpu.run());
+
+    cpu.raise_irq();
+    assert_eq!(Ok(7), cpu.step());
+    assert_eq!(0x0500, cpu.pc);
+    cpu.clear_irq();
+    assert_eq!(Ok(6), cpu.step());
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(1, cpu.x);
+}
+
+#[test]
+fn wai_with_interrupts_disabled_continues() {
+    let mut cpu = CPU::builder().variant(Variant::Wdc65C02).build();
+    cpu.load(0x0400, &[0xCB, 0xE8]); // WAI, INX
+    cpu.p = Status::I;
+    assert_eq!(Ok(3), cpu.step());
+    cpu.raise_irq();
+    assert_eq!(Ok(2), cpu.step());
+    assert!(!cpu.is_waiting());
+    assert_eq!(1, cpu.x);
+}
+
+#[test]
+fn stp_stops_until_reset() {
+    let mut cpu = CPU::builder().variant(Variant::Wdc65C02).build();
+    cpu.load(0x0400, &[0xDB, 0xE8]); // STP, INX
+    cpu.load(RESET_VECTOR, &[0x01, 0x04]);
+    assert_eq!(Ok(StopReason::Stopped), cpu.run());
+    assert!(cpu.is_stopped());
+    cpu.trigger_nmi();
+    assert_eq!(Ok(0), cpu.step());
+    assert_eq!(Ok(StopReason::Stopped), cpu.run());
+    cpu.reset();
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(1, cpu.x);
+}
+
+#[test]
+fn ricoh_2a03_ignores_decimal_mode() {
+    let run = run_adc_sbc(Variant::Ricoh2A03, 0x69, Status::D, 0x09, 0x01);
+    assert_eq!(0x0A, run.a);
+    assert_eq!(Status::D, run.p);
+    let run = run_adc_sbc(Variant::Ricoh2A03, 0xE9, Status::D | Status::C, 0x10, 0x01);
+    assert_eq!(0x0F, run.a);
+
+    let run = Snippet::new("sed").variant(Variant::Ricoh2A03).run();
+    assert_eq!(Status::D, run.p);
+}
+
+#[test]
+fn ricoh_2a03_undocumented_opcodes() {
+    let run = Snippet::new("sed\nrra $10\narr #$FF")
+        .variant(Variant::Ricoh2A03)
+        .illegal_opcodes(true)
+        .a(0x09)
+        .poke(0x0010, &[0x02])
+        .run();
+    assert!(run.finished());
+    assert_eq!(0x01, run.mem[0x0010]);
+    // RRA adds in binary, ARR skips the decimal fix up
+    assert_eq!(0x05, run.a);
+}
@@ -0,0 +1,163 @@
+use super::*;
+
+fn new_test_cpu() -> CPU {
+    let mut cpu = CPU::new();
+    cpu.mem[0xFFFA] = 0x00; // NMI -> 0x0600
+    cpu.mem[0xFFFB] = 0x06;
+    cpu.mem[0xFFFC] = 0x00; // RESET -> 0x0400
+    cpu.mem[0xFFFD] = 0x04;
+    cpu.mem[0xFFFE] = 0x00; // IRQ -> 0x0500
+    cpu.mem[0xFFFF] = 0x05;
+    cpu.mem[0x0400] = 0xEA; // NOP
+    cpu.mem[0x0401] = 0xEA; // NOP
+    cpu.mem[0x0402] = 0xEA; // NOP
+    cpu.mem[0x0403] = 0xFF; // So we exit with CPUError::IllegalInstruction
+    cpu
+}
+
+#[test]
+fn reset_loads_vector() {
+    let mut cpu = new_test_cpu();
+    cpu.pc = 0x1234;
+    cpu.s = 0x00;
+    cpu.reset();
+    assert_eq!(0x0400, cpu.pc);
+    assert_eq!(0xfd, cpu.s);
+    assert!(cpu.p.contains(Status::I));
+}
+
+#[test]
+fn reset_clears_d_on_the_65c02() {
+    let mut cpu = new_test_cpu();
+    cpu.p.insert(Status::D);
+    cpu.reset();
+    assert!(cpu.p.contains(Status::D));
+
+    cpu.variant = Variant::Cmos65C02;
+    cpu.reset();
+    assert!(!cpu.p.contains(Status::D));
+}
+
+#[test]
+fn irq_is_masked_by_i() {
+    let mut cpu = new_test_cpu();
+    cpu.p.insert(Status::I);
+    cpu.raise_irq();
+    assert_eq!(cpu.step(), Ok(2));
+    assert_eq!(0x0401, cpu.pc);
+}
+
+#[test]
+fn irq_pushes_state_and_jumps_to_vector() {
+    let mut cpu = new_test_cpu();
+    cpu.p.insert(Status::C);
+    assert_eq!(cpu.step(), Ok(2));
+    cpu.raise_irq();
+    assert_eq!(cpu.step(), Ok(7));
+    assert_eq!(0x0500, cpu.pc);
+    assert!(cpu.p.contains(Status::I));
+    assert_eq!(0xfc, cpu.s);
+    assert_eq!(0x04, cpu.mem[0x01ff]);
+    assert_eq!(0x01, cpu.mem[0x01fe]);
+    assert_eq!((Status::U | Status::C).bits(), cpu.mem[0x01fd]);
+}
+
+#[test]
+fn irq_is_level_triggered() {
+    let mut cpu = new_test_cpu();
+    cpu.mem[0x0500] = 0x58; // CLI
+    cpu.raise_irq();
+    assert_eq!(cpu.step(), Ok(7));
+    assert_eq!(0x0500, cpu.pc);
+    assert_eq!(cpu.step(), Ok(2)); // CLI
+    assert_eq!(cpu.step(), Ok(7));
+    assert_eq!(0x0500, cpu.pc);
+    assert_eq!(0xf9, cpu.s);
+    cpu.clear_irq();
+    cpu.p.remove(Status::I);
+    cpu.mem[0x0500] = 0xEA; // NOP
+    assert_eq!(cpu.step(), Ok(2));
+    assert_eq!(0x0501, cpu.pc);
+}
+
+#[test]
+fn nmi_ignores_i_and_is_edge_triggered() {
+    let mut cpu = new_test_cpu();
+    cpu.mem[0x0600] = 0xEA; // NOP
+    cpu.p.insert(Status::I);
+    cpu.trigger_nmi();
+    assert_eq!(cpu.step(), Ok(7));
+    assert_eq!(0x0600, cpu.pc);
+    assert_eq!(cpu.step(), Ok(2));
+    assert_eq!(0x0601, cpu.pc);
+}
+
+#[test]
+fn nmi_wins_over_irq() {
+    let mut cpu = new_test_cpu();
+    cpu.raise_irq();
+    cpu.trigger_nmi();
+    assert_eq!(cpu.step(), Ok(7));
+    assert_eq!(0x0600, cpu.pc);
+}
+
+#[test]
+fn brk_pushes_state_and_jumps_to_vector() {
+    let mut cpu = new_test_cpu();
+    cpu.mem[0x0400] = 0x00; // BRK $42
+    cpu.mem[0x0401] = 0x42;
+    cpu.p.insert(Status::C);
+    assert_eq!(cpu.step(), Ok(7));
+    assert_eq!(0x0500, cpu.pc);
+    assert!(cpu.p.contains(Status::I));
+    assert_eq!(0x04, cpu.mem[0x01ff]);
+    assert_eq!(0x02, cpu.mem[0x01fe]);
+    assert_eq!((Status::U | Status::B | Status::C).bits(), cpu.mem[0x01fd]);
+}
+
+#[test]
+fn brk_and_rti_round_trip() {
+    let mut cpu = new_test_cpu();
+    cpu.mem[0x0400] = 0x00; // BRK $42
+    cpu.mem[0x0401] = 0x42;
+    cpu.mem[0x0402] = 0xA9; // LDA #$01
+    cpu.mem[0x0403] = 0x01;
+    cpu.mem[0x0404] = 0xFF; // So we exit with CPUError::IllegalInstruction
+    cpu.mem[0x0500] = 0xA2; // LDX #$80
+    cpu.mem[0x0501] = 0x80;
+    cpu.mem[0x0502] = 0x40; // RTI
+    cpu.p.insert(Status::C);
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
+    assert_eq!(0x01, cpu.a);
+    assert_eq!(0x80, cpu.x);
+    assert_eq!(0xff, cpu.s);
+    assert_eq!(Status::C, cpu.p);
+}
+
+#[test]
+fn irq_and_rti_round_trip() {
+    let mut cpu = new_test_cpu();
+    cpu.mem[0x0500] = 0xA2; // LDX #$80
+    cpu.mem[0x0501] = 0x80;
+    cpu.mem[0x0502] = 0x40; // RTI
+    assert_eq!(cpu.step(), Ok(2));
+    cpu.raise_irq();
+    assert_eq!(cpu.step(), Ok(7));
+    cpu.clear_irq();
+    assert!(matches!(cpu.run(), Err(CPUError::IllegalInstruction { .. })));
+    assert_eq!(0x0404, cpu.pc);
+    assert_eq!(0x80, cpu.x);
+    assert!(!cpu.p.contains(Status::I));
+    assert!(!cpu.p.contains(Status::N));
+}
+
+#[test]
+fn php_pushes_b_and_u() {
+    let mut cpu = new_test_cpu();
+    cpu.mem[0x0400] = 0x08; // PHP
+    cpu.mem[0x0401] = 0x28; // PLP
+    assert_eq!(cpu.step(), Ok(3));
+    assert_eq!((Status::U | Status::B).bits(), cpu.mem[0x01ff]);
+    assert_eq!(cpu.step(), Ok(4));
+    assert!(cpu.p.is_empty());
+}
@@ -2,53 +2,392 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/
 
+//! A 6502 emulator.
+//!
+//! ```
+//! use cpu::{CPU, StopReason};
+//!
+//! let mut cpu = CPU::new();
+//! cpu.load(0x0400, &[
+//!     0xA9, 0x42, // LDA #$42
+//!     0x85, 0x10, // STA $10
+//! ]);
+//! cpu.set_pc(0x0400);
+//! cpu.add_breakpoint(0x0404);
+//! assert_eq!(cpu.run(), Ok(StopReason::Breakpoint(0x0404)));
+//! assert_eq!(0x42, cpu.a());
+//! assert_eq!(0x42, cpu.peek(0x0010));
+//! ```
+//!
+//! The CPU can be attached to any memory that implements `bus::Bus`,
+//! for example a `memory_map::MemoryMap` with ROM and mirrored regions.
+
+#![allow(clippy::upper_case_acronyms)]
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+
 use bitflags::bitflags;
 
+pub mod asm;
+pub mod bus;
+pub mod disasm;
+mod history;
+pub mod memory_map;
+pub mod opcodes;
+pub mod savestate;
+pub mod testing;
+pub mod w65c816;
+pub mod watch;
+
+use bus::{Bus, BusCycle, BusFault, Ram};
+use history::History;
+use opcodes::{Mnemonic, Mode, Opcode, OpcodeTable, CMOS, NMOS_ILLEGAL, OPCODES, ROCKWELL, WDC};
+use watch::{WatchHit, Watchpoint};
+
+/// Why `step` failed. `pc` is always the address of the instruction
+/// that was executing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CPUError {
+    /// The opcode is not supported.
+    IllegalInstruction { opcode: u8, pc: u16 },
+    /// One of the NMOS opcodes that lock up the CPU.
+    Jam { opcode: u8, pc: u16 },
+    /// A push with the stack pointer at 0x00. Only reported with
+    /// `CPUBuilder::strict_stack`, otherwise the stack wraps around.
+    StackOverflow { pc: u16 },
+    /// A pull with the stack pointer at 0xFF. Only reported with
+    /// `CPUBuilder::strict_stack`, otherwise the stack wraps around.
+    StackUnderflow { pc: u16 },
+    /// The bus reported an access to an unmapped address.
+    UnmappedAccess { address: u16, write: bool, pc: u16 },
+    /// The bus rejected a write to ROM.
+    RomWrite { address: u16, v: u8, pc: u16 },
+}
+
+impl CPUError {
+    fn from_bus_fault(fault: BusFault, pc: u16) -> Self {
+        match fault {
+            BusFault::RomWrite { address, v } => CPUError::RomWrite { address, v, pc },
+            BusFault::UnmappedRead { address } => CPUError::UnmappedAccess { address, write: false, pc },
+            BusFault::UnmappedWrite { address, .. } => CPUError::UnmappedAccess { address, write: true, pc },
+        }
+    }
+}
+
+impl fmt::Display for CPUError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CPUError::IllegalInstruction { opcode, pc } => write!(f, "illegal instruction {:02X} at {:04X}", opcode, pc),
+            CPUError::Jam { opcode, pc } => write!(f, "CPU jammed by {:02X} at {:04X}", opcode, pc),
+            CPUError::StackOverflow { pc } => write!(f, "stack overflow at {:04X}", pc),
+            CPUError::StackUnderflow { pc } => write!(f, "stack underflow at {:04X}", pc),
+            CPUError::UnmappedAccess { address, write: false, pc } => write!(f, "read from unmapped address {:04X} at {:04X}", address, pc),
+            CPUError::UnmappedAccess { address, write: true, pc } => write!(f, "write to unmapped address {:04X} at {:04X}", address, pc),
+            CPUError::RomWrite { address, v, pc } => write!(f, "write of {:02X} to ROM at {:04X} at {:04X}", v, address, pc),
+        }
+    }
+}
+
+impl std::error::Error for CPUError {}
+
+/// Why one of the `run` functions returned.
 #[derive(Debug, PartialEq)]
-enum CPUError {
-    IllegalInstruction,
+pub enum StopReason {
+    /// The next instruction to execute is at a breakpoint.
+    Breakpoint(u16),
+    /// The `run_until` predicate returned true.
+    Condition,
+    /// The requested number of cycles has been executed.
+    CycleLimit,
+    /// The requested number of instructions has been executed.
+    InstructionLimit,
+    /// The last instruction triggered a watchpoint.
+    Watchpoint(WatchHit),
+    /// WAI is waiting for an interrupt. Raise one and run again.
+    Waiting,
+    /// STP stopped the clock. Only a reset starts it again.
+    Stopped,
 }
 
 //
-// This is a 6502 emulator with the following memory layout:
+// This is a 6502 emulator. The CPU talks to memory through a Bus, by
+// default a flat 64 KiB Ram. A MemoryMap can be used instead to lay out
+// RAM, ROM and mirrored regions. The tests use the following layout:
 //
 //  0x0000 - 0x00ff RAM Zero Page
 //  0x0100 - 0x01ff RAM Stack
 //  0x0200 - 0x03ff RAM General Use
-//  0x0400 - 0x07ff ROM
+//  0x0400 - 0x07ff Program
 //
 
 bitflags! {
-    #[derive(Clone)]
+    /// The processor status register P.
+    #[derive(Clone, Copy, Debug, PartialEq)]
     pub struct Status: u8 {
+        /// Negative, bit 7 of the last result.
         const N = 0b10000000;
+        /// Overflow from signed arithmetic, or bit 6 for BIT.
         const V = 0b01000000;
+        /// Unused. It reads as set when P is pushed.
+        const U = 0b00100000;
+        /// Break. Only exists in the copy of P that BRK and PHP push.
         const B = 0b00010000;
+        /// Decimal mode for ADC and SBC.
         const D = 0b00001000;
+        /// Interrupt disable, masks IRQ but not NMI.
         const I = 0b00000100;
+        /// Zero, set when the last result was 0.
         const Z = 0b00000010;
+        /// Carry.
         const C = 0b00000001;
     }
 }
 
-#[allow(dead_code)]
-struct CPU {
+/// The flavour of 6502 being emulated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Variant {
+    /// The original NMOS 6502. In decimal mode N, V and Z are computed from
+    /// intermediate results and do not match the accumulator.
+    Nmos6502,
+    /// The CMOS 65C02. It adds instructions and the `(zp)` addressing
+    /// mode, fixes the `JMP (ind)` page wrap and clears D on interrupts.
+    /// In decimal mode N and Z match the accumulator, at the cost of a
+    /// cycle. Opcodes it does not use are NOPs.
+    Cmos65C02,
+    /// The Rockwell 65C02, which adds the bit instructions RMB, SMB, BBR
+    /// and BBS.
+    Rockwell65C02,
+    /// The WDC 65C02, which adds WAI and STP to the Rockwell instructions.
+    Wdc65C02,
+    /// The Ricoh 2A03 in the NES, an NMOS 6502 without decimal mode. SED
+    /// and CLD still change D, but ADC and SBC ignore it.
+    Ricoh2A03,
+}
+
+impl Variant {
+    /// True for the 65C02 and its relatives.
+    pub fn is_cmos(self) -> bool {
+        matches!(self, Variant::Cmos65C02 | Variant::Rockwell65C02 | Variant::Wdc65C02)
+    }
+}
+
+/// The ANE and LXA constant used unless `CPUBuilder::magic` says otherwise.
+pub const DEFAULT_MAGIC: u8 = 0xEE;
+
+pub const NMI_VECTOR: u16 = 0xFFFA;
+pub const RESET_VECTOR: u16 = 0xFFFC;
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+
+fn opcode_table(variant: Variant, illegal_opcodes: bool) -> &'static OpcodeTable {
+    match variant {
+        Variant::Nmos6502 | Variant::Ricoh2A03 if illegal_opcodes => &NMOS_ILLEGAL,
+        Variant::Nmos6502 | Variant::Ricoh2A03 => &OPCODES,
+        Variant::Cmos65C02 => &CMOS,
+        Variant::Rockwell65C02 => &ROCKWELL,
+        Variant::Wdc65C02 => &WDC,
+    }
+}
+
+fn page_crossed(a: u16, b: u16) -> bool {
+    a & 0xff00 != b & 0xff00
+}
+
+/// A 6502 attached to the memory `B`, with its registers, interrupt
+/// lines, cycle counter and debugging state. Create one with `new`,
+/// `with_bus` or `builder`.
+pub struct CPU<B: Bus = Ram> {
     pc: u16,
     a: u8,
     x: u8,
     y: u8,
     s: u8,
     p: Status,
-    mem: [u8; 2048],
+    mem: B,
+    variant: Variant,
+    opcodes: &'static OpcodeTable,
+    magic: u8,
+    jammed: Option<u8>,
+    waiting: bool,
+    stopped: bool,
+    irq: bool,
+    nmi: bool,
+    cycles: u64,
+    extra_cycles: u8,
+    page_crossed: bool,
+    breakpoints: HashSet<u16>,
+    // The breakpoint the last run stopped at, until the next instruction
+    resume_breakpoint: Option<u16>,
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
+    instruction_pc: u16,
+    strict_stack: bool,
+    fault: Option<CPUError>,
+    bus_log: Option<Vec<BusCycle>>,
+    tick: Option<Tick>,
+    replay: Option<Replay>,
+    history: Option<History>,
+}
+
+// An instruction that tick is part way through. Every tick runs the
+// instruction again from `start`, replaying the bus cycles that already
+// happened and doing the next one for real. The registers are only
+// updated by the tick that does the last cycle.
+struct Tick {
+    start: Registers,
+    cycles: Vec<BusCycle>,
+    fault: Option<CPUError>,
+}
+
+// The registers and interrupt state between instructions, which tick and
+// the history go back to
+#[derive(Clone, Copy)]
+struct Registers {
+    pc: u16,
+    a: u8,
+    x: u8,
+    y: u8,
+    s: u8,
+    p: Status,
+    irq: bool,
+    nmi: bool,
+    waiting: bool,
+    stopped: bool,
+    jammed: Option<u8>,
+    cycles: u64,
+}
+
+struct Replay {
+    cycles: Vec<BusCycle>,
+    // Cycles that happened in earlier ticks
+    done: usize,
+    next: usize,
+}
+
+type RegOp<B> = fn(&mut CPU<B>, u8);
+type MemOp<B> = fn(&mut CPU<B>, u8) -> u8;
+type SetOp<B> = fn(&mut CPU<B>) -> u8;
+
+/// Configures a `CPU` before it is created.
+pub struct CPUBuilder {
+    variant: Variant,
+    illegal_opcodes: bool,
+    magic: u8,
+    pc: u16,
+    strict_stack: bool,
+    bus_log: bool,
+    history: usize,
+}
+
+impl CPUBuilder {
+    pub fn new() -> Self {
+        CPUBuilder {
+            variant: Variant::Nmos6502,
+            illegal_opcodes: false,
+            magic: DEFAULT_MAGIC,
+            pc: 0x0400,
+            strict_stack: false,
+            bus_log: false,
+            history: 0,
+        }
+    }
+
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    /// Run the undocumented NMOS opcodes, see `opcodes::NMOS_ILLEGAL`,
+    /// instead of stopping with `CPUError::IllegalInstruction`. Only
+    /// affects `Variant::Nmos6502` and `Variant::Ricoh2A03`.
+    pub fn illegal_opcodes(mut self, illegal_opcodes: bool) -> Self {
+        self.illegal_opcodes = illegal_opcodes;
+        self
+    }
+
+    /// The constant that ANE and LXA OR into the accumulator. It differs
+    /// between chips and even with temperature, so programs should not
+    /// depend on it.
+    pub fn magic(mut self, magic: u8) -> Self {
+        self.magic = magic;
+        self
+    }
+
+    /// Where execution starts. Call `CPU::reset` instead to start at the
+    /// address in the reset vector.
+    pub fn pc(mut self, pc: u16) -> Self {
+        self.pc = pc;
+        self
+    }
+
+    /// Report stack overflow and underflow as errors instead of letting
+    /// the stack pointer wrap around like the hardware does.
+    pub fn strict_stack(mut self, strict_stack: bool) -> Self {
+        self.strict_stack = strict_stack;
+        self
+    }
+
+    /// See `CPU::set_bus_log`.
+    pub fn bus_log(mut self, bus_log: bool) -> Self {
+        self.bus_log = bus_log;
+        self
+    }
+
+    /// See `CPU::set_history`.
+    pub fn history(mut self, steps: usize) -> Self {
+        self.history = steps;
+        self
+    }
+
+    /// Create a CPU with 64 KiB of RAM.
+    pub fn build(self) -> CPU {
+        self.build_with_bus(Ram::new())
+    }
+
+    pub fn build_with_bus<B: Bus>(self, mem: B) -> CPU<B> {
+        let mut cpu = CPU::with_variant(mem, self.variant);
+        cpu.opcodes = opcode_table(self.variant, self.illegal_opcodes);
+        cpu.magic = self.magic;
+        cpu.pc = self.pc;
+        cpu.strict_stack = self.strict_stack;
+        cpu.set_bus_log(self.bus_log);
+        cpu.set_history(self.history);
+        cpu
+    }
 }
 
-type RegOp = fn(&mut CPU, u8);
-type MemOp = fn(&mut CPU, u8) -> u8;
-type SetOp = fn(&mut CPU) -> u8;
+impl Default for CPUBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CPU<Ram> {
+    /// Create an NMOS 6502 with 64 KiB of RAM, starting at 0x0400.
+    pub fn new() -> Self {
+        CPU::with_bus(Ram::new())
+    }
 
-#[allow(dead_code)]
-impl CPU {
-    fn new() -> Self {
+    pub fn builder() -> CPUBuilder {
+        CPUBuilder::new()
+    }
+}
+
+impl Default for CPU<Ram> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: Bus> CPU<B> {
+    /// Create an NMOS 6502 attached to `mem`, starting at 0x0400.
+    pub fn with_bus(mem: B) -> Self {
+        CPU::with_variant(mem, Variant::Nmos6502)
+    }
+
+    fn with_variant(mem: B, variant: Variant) -> Self {
         CPU {
             a: 0,
             x: 0,
@@ -56,14 +395,199 @@ impl CPU {
             pc: 0x0400,
             s: 0xff,
             p: Status::empty(),
-            mem: [0; 2048],
+            mem,
+            variant,
+            opcodes: opcode_table(variant, false),
+            magic: DEFAULT_MAGIC,
+            jammed: None,
+            waiting: false,
+            stopped: false,
+            irq: false,
+            nmi: false,
+            cycles: 0,
+            extra_cycles: 0,
+            page_crossed: false,
+            breakpoints: HashSet::new(),
+            resume_breakpoint: None,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            instruction_pc: 0x0400,
+            strict_stack: false,
+            fault: None,
+            bus_log: None,
+            tick: None,
+            replay: None,
+            history: None,
         }
     }
 
+    // Registers
+
+    /// The accumulator.
+    pub fn a(&self) -> u8 {
+        self.a
+    }
+
+    pub fn set_a(&mut self, a: u8) {
+        self.a = a;
+    }
+
+    /// The X index register.
+    pub fn x(&self) -> u8 {
+        self.x
+    }
+
+    pub fn set_x(&mut self, x: u8) {
+        self.x = x;
+    }
+
+    /// The Y index register.
+    pub fn y(&self) -> u8 {
+        self.y
+    }
+
+    pub fn set_y(&mut self, y: u8) {
+        self.y = y;
+    }
+
+    /// The stack pointer. The stack lives at 0x0100 + s.
+    pub fn s(&self) -> u8 {
+        self.s
+    }
+
+    pub fn set_s(&mut self, s: u8) {
+        self.s = s;
+    }
+
+    /// The address of the next instruction.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// The status register.
+    pub fn p(&self) -> Status {
+        self.p
+    }
+
+    pub fn set_p(&mut self, p: Status) {
+        self.p = p;
+    }
+
+    /// The flavour of 6502 this CPU emulates.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// The opcodes this CPU runs. Anything else is an illegal instruction.
+    pub fn opcodes(&self) -> &'static OpcodeTable {
+        self.opcodes
+    }
+
+    // Memory
+
+    pub fn mem(&self) -> &B {
+        &self.mem
+    }
+
+    pub fn mem_mut(&mut self) -> &mut B {
+        &mut self.mem
+    }
+
+    /// Read memory without side effects and without triggering watchpoints.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.mem.peek(address)
+    }
+
+    /// Write `data` to memory starting at `address` with `Bus::poke`, so
+    /// ROM can be loaded and nothing faults or triggers watchpoints.
+    pub fn load(&mut self, address: u16, data: &[u8]) {
+        for (i, b) in data.iter().enumerate() {
+            self.mem.poke(address.wrapping_add(i as u16), *b);
+        }
+    }
+
+    /// Record every bus cycle of the next instruction, including the
+    /// dummy reads and writes the NMOS part does while it indexes, fixes
+    /// up page crossings and modifies memory. Those extra accesses always
+    /// reach the bus, the log only decides whether they are recorded.
+    pub fn set_bus_log(&mut self, enabled: bool) {
+        self.bus_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// The bus cycles of the last instruction, oldest first. Empty unless
+    /// the bus log is enabled.
+    pub fn bus_log(&self) -> &[BusCycle] {
+        self.bus_log.as_deref().unwrap_or(&[])
+    }
+
+    // While tick replays an instruction, cycles that already happened
+    // return what was read then, and cycles after the one this tick is for
+    // do nothing
+    fn bus_read(&mut self, address: u16) -> u8 {
+        let data = match &mut self.replay {
+            None => self.mem.read(address),
+            Some(replay) => {
+                let i = replay.next;
+                replay.next += 1;
+                match i.cmp(&replay.done) {
+                    Ordering::Less => {
+                        debug_assert_eq!(address, replay.cycles[i].address);
+                        replay.cycles[i].data
+                    }
+                    Ordering::Equal => {
+                        let data = self.mem.read(address);
+                        replay.cycles.push(BusCycle { address, data, write: false });
+                        data
+                    }
+                    Ordering::Greater => return 0,
+                }
+            }
+        };
+        if let Some(log) = &mut self.bus_log {
+            log.push(BusCycle { address, data, write: false });
+        }
+        data
+    }
+
+    fn bus_write(&mut self, address: u16, data: u8) {
+        if let Some(replay) = &mut self.replay {
+            let i = replay.next;
+            replay.next += 1;
+            match i.cmp(&replay.done) {
+                Ordering::Less => {
+                    if let Some(log) = &mut self.bus_log {
+                        log.push(BusCycle { address, data, write: true });
+                    }
+                    return;
+                }
+                Ordering::Equal => replay.cycles.push(BusCycle { address, data, write: true }),
+                Ordering::Greater => return,
+            }
+        }
+        if let Some(log) = &mut self.bus_log {
+            log.push(BusCycle { address, data, write: true });
+        }
+        self.mem.write(address, data);
+    }
+
+    // Dummy cycles go to the bus like any other, since reading or writing
+    // a memory mapped device can have side effects
+    fn dummy_read(&mut self, address: u16) {
+        self.bus_read(address);
+    }
+
+    fn dummy_write(&mut self, address: u16, data: u8) {
+        self.bus_write(address, data);
+    }
+
     // TODO Needs test
     fn read_byte(&mut self) -> u8 {
-        let b = self.mem[self.pc as usize];
-        self.pc += 1;
+        let b = self.bus_read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
         b
     }
 
@@ -73,14 +597,33 @@ impl CPU {
     }
 
     fn push_byte(&mut self, b: u8) {
-        self.mem[(0x0100 + self.s as u16) as usize] = b;
-        self.s -= 1;
+        if self.strict_stack && self.s == 0x00 {
+            self.report(CPUError::StackOverflow { pc: self.instruction_pc });
+        }
+        self.set_byte(0x0100 + self.s as u16, b);
+        self.s = self.s.wrapping_sub(1);
     }
 
     fn pop_byte(&mut self) -> u8 {
-        self.s += 1;
-        let b = self.mem[(0x0100 + self.s as u16) as usize];
-        b
+        if self.strict_stack && self.s == 0xff {
+            self.report(CPUError::StackUnderflow { pc: self.instruction_pc });
+        }
+        self.s = self.s.wrapping_add(1);
+        self.get_byte(0x0100 + self.s as u16)
+    }
+
+    // Remember the first error in this step, step returns it when the
+    // instruction is done
+    fn report(&mut self, e: CPUError) {
+        if self.fault.is_none() {
+            self.fault = Some(e);
+        }
+    }
+
+    // Instructions that pull from the stack read it once before they
+    // increment the stack pointer
+    fn stack_dummy_read(&mut self) {
+        self.dummy_read(0x0100 + self.s as u16);
     }
 
     fn push_word(&mut self, w: u16) {
@@ -102,47 +645,102 @@ impl CPU {
     // Memory Getters
 
     fn get_byte(&mut self, address: u16) -> u8 {
-        self.mem[address as usize]
+        let v = self.bus_read(address);
+        if !self.watchpoints.is_empty() {
+            self.watch(address, false, v, v);
+        }
+        v
     }
 
     fn get_byte_zpg(&mut self, address: u8) -> u8 {
-        self.mem[address as usize]
+        self.get_byte(address as u16)
     }
 
+    // Indexed zero page reads the base address while it adds the index
     fn get_byte_zpgx(&mut self, address: u8) -> u8 {
-        self.mem[address.wrapping_add(self.x) as usize]
+        self.dummy_read(address as u16);
+        self.get_byte(address.wrapping_add(self.x) as u16)
     }
 
     fn get_byte_zpgy(&mut self, address: u8) -> u8 {
-        self.mem[address.wrapping_add(self.y) as usize]
+        self.dummy_read(address as u16);
+        self.get_byte(address.wrapping_add(self.y) as u16)
     }
 
     fn get_byte_abs(&mut self, address: u16) -> u8 {
-        self.mem[address as usize]
+        self.get_byte(address)
     }
 
     fn get_byte_absx(&mut self, address: u16) -> u8 {
-        self.mem[address.wrapping_add(self.x as u16) as usize]
+        self.get_byte_indexed(address, self.x)
     }
 
     fn get_byte_absy(&mut self, address: u16) -> u8 {
-        self.mem[address.wrapping_add(self.y as u16) as usize]
+        self.get_byte_indexed(address, self.y)
+    }
+
+    fn get_byte_indexed(&mut self, address: u16, index: u8) -> u8 {
+        let effective = self.index(address, index, false);
+        self.get_byte(effective)
+    }
+
+    // Remembers if indexing crossed a page. Whether that costs a cycle
+    // depends on the instruction, see Opcode::page_penalty. The NMOS part
+    // adds the index to the low byte first and reads from that address
+    // while it fixes the high byte, the 65C02 reads the last operand byte
+    // again instead. Reads skip that when there is nothing to fix, writes
+    // and read-modify-writes always do it, apart from the 65C02 shifts and
+    // rotates which have a page penalty like the reads.
+    fn index(&mut self, address: u16, index: u8, always: bool) -> u16 {
+        let effective = address.wrapping_add(index as u16);
+        let crossed = page_crossed(address, effective);
+        self.page_crossed |= crossed;
+        if always || crossed {
+            if self.variant.is_cmos() {
+                self.dummy_read(self.pc.wrapping_sub(1));
+            } else {
+                self.dummy_read(address & 0xFF00 | effective & 0x00FF);
+            }
+        }
+        effective
+    }
+
+    // The pointer for (zp,X) and (zp),Y wraps around in the zero page
+    fn get_pointer(&mut self, address: u8) -> u16 {
+        let low = self.get_byte_zpg(address) as u16;
+        (self.get_byte_zpg(address.wrapping_add(1)) as u16) << 8 | low
     }
 
     fn get_byte_xind(&mut self, address: u8) -> u8 {
-        let address = (self.get_byte_zpg(address.wrapping_add(1).wrapping_add(self.x)) as u16) << 8 | self.get_byte_zpg(address.wrapping_add(self.x)) as u16;
-        self.mem[address as usize]
+        self.dummy_read(address as u16);
+        let address = self.get_pointer(address.wrapping_add(self.x));
+        self.get_byte(address)
     }
 
     fn get_byte_indy(&mut self, address: u8) -> u8 {
-        let address = (self.get_byte_zpg(address.wrapping_add(1)) as u16) << 8 | self.get_byte_zpg(address) as u16;
-        self.mem[address.wrapping_add(self.y as u16) as usize]
+        let address = self.get_pointer(address);
+        self.get_byte_indexed(address, self.y)
+    }
+
+    fn get_byte_zpi(&mut self, address: u8) -> u8 {
+        let address = self.get_pointer(address);
+        self.get_byte(address)
     }
 
     // Memory Setters
 
     fn set_byte(&mut self, address: u16, v: u8) {
-        self.mem[address as usize] = v;
+        if !self.watchpoints.is_empty() {
+            let old = self.mem.peek(address);
+            self.watch(address, true, old, v);
+        }
+        // A write tick replays has already happened
+        if let Some(history) = &mut self.history {
+            if self.replay.as_ref().is_none_or(|replay| replay.next == replay.done) {
+                history.write(address, self.mem.peek(address));
+            }
+        }
+        self.bus_write(address, v);
     }
 
     fn set_byte_zpg(&mut self, address: u8, v: u8) {
@@ -150,10 +748,12 @@ impl CPU {
     }
 
     fn set_byte_zpgx(&mut self, address: u8, v: u8) {
+        self.dummy_read(address as u16);
         self.set_byte((address.wrapping_add(self.x)) as u16, v);
     }
 
     fn set_byte_zpgy(&mut self, address: u8, v: u8) {
+        self.dummy_read(address as u16);
         self.set_byte((address.wrapping_add(self.y)) as u16, v);
     }
 
@@ -162,50 +762,105 @@ impl CPU {
     }
 
     fn set_byte_absx(&mut self, address: u16, v: u8) {
-        self.set_byte(address.wrapping_add(self.x as u16), v);
+        let address = self.index(address, self.x, true);
+        self.set_byte(address, v);
     }
 
     fn set_byte_absy(&mut self, address: u16, v: u8) {
-        self.set_byte(address.wrapping_add(self.y as u16), v);
+        let address = self.index(address, self.y, true);
+        self.set_byte(address, v);
     }
 
     fn set_byte_xind(&mut self, address: u8, v: u8) {
-        let address = (self.get_byte_zpg(address.wrapping_add(1).wrapping_add(self.x)) as u16) << 8 | self.get_byte_zpg(address.wrapping_add(self.x)) as u16;
+        self.dummy_read(address as u16);
+        let address = self.get_pointer(address.wrapping_add(self.x));
         self.set_byte(address, v);
     }
 
     fn set_byte_indy(&mut self, address: u8, v: u8) {
-        let address: u16 = (self.get_byte_zpg(address.wrapping_add(1)) as u16) << 8 | self.get_byte_zpg(address) as u16;
-        self.set_byte(address.wrapping_add(self.y as u16), v);
+        let address = self.get_pointer(address);
+        let address = self.index(address, self.y, true);
+        self.set_byte(address, v);
+    }
+
+    fn set_byte_zpi(&mut self, address: u8, v: u8) {
+        let address = self.get_pointer(address);
+        self.set_byte(address, v);
     }
 
     // Word Shortcuts
 
     fn get_word(&mut self, address: u16) -> u16 {
-        (self.get_byte(address.wrapping_add(1)) as u16) << 8 | self.get_byte(address) as u16
-    }
-      
-    fn set_word(&mut self, address: u16, v: u16) {
-        self.set_byte(address+0, v as u8);
-        self.set_byte(address+1, (v >> 8) as u8);
+        let low = self.get_byte(address) as u16;
+        (self.get_byte(address.wrapping_add(1)) as u16) << 8 | low
     }
 
     // Register Operations
 
     fn adc(&mut self, m: u8) {
-        if self.p.contains(Status::D) {
-            todo!();
+        if self.decimal_mode() {
+            self.decimal_cycle();
+            self.adc_decimal(m);
         } else {
-            let mut t = self.a as u16 + m as u16;
-            if self.p.contains(Status::C) {
-                t += 1;
-            }
-            let r = t as u8;
-            self.p.set(Status::C, (t & 0x0100) != 0);
-            self.p.set(Status::C, ((self.a^r) & (m^r) & 0x80) != 0);
-            self.a = r;
-            self.update_zn(self.a);
+            self.adc_binary(m);
+        }
+    }
+
+    fn adc_binary(&mut self, m: u8) {
+        let mut t = self.a as u16 + m as u16;
+        if self.p.contains(Status::C) {
+            t += 1;
         }
+        let r = t as u8;
+        self.p.set(Status::C, (t & 0x0100) != 0);
+        self.p.set(Status::V, ((self.a^r) & (m^r) & 0x80) != 0);
+        self.a = r;
+        self.update_zn(self.a);
+    }
+
+    // The 2A03 has D but no decimal mode
+    fn decimal_mode(&self) -> bool {
+        self.p.contains(Status::D) && self.variant != Variant::Ricoh2A03
+    }
+
+    // The 65C02 takes an extra cycle to get the flags right in decimal
+    // mode
+    fn decimal_cycle(&mut self) {
+        if self.variant.is_cmos() {
+            self.dummy_read(self.pc);
+            self.extra_cycles += 1;
+        }
+    }
+
+    // See http://www.6502.org/tutorials/decimal_mode.html for how the
+    // NMOS and CMOS parts compute the flags in decimal mode.
+    fn adc_decimal(&mut self, m: u8) {
+        let c = self.p.contains(Status::C) as i16;
+        let (a, b) = (self.a as i16, m as i16);
+
+        let mut al = (a & 0x0f) + (b & 0x0f) + c;
+        if al >= 0x0a {
+            al = ((al + 0x06) & 0x0f) + 0x10;
+        }
+
+        // N and V come from the signed sum before the high nibble is adjusted
+        let s = (self.a as i8 as i16 & -0x10) + (m as i8 as i16 & -0x10) + al;
+        self.p.set(Status::V, !(-128..=127).contains(&s));
+
+        let mut t = (a & 0xf0) + (b & 0xf0) + al;
+        if t >= 0xa0 {
+            t += 0x60;
+        }
+        self.p.set(Status::C, t >= 0x100);
+
+        let r = t as u8;
+        if self.variant.is_cmos() {
+            self.update_zn(r);
+        } else {
+            self.p.set(Status::N, s & 0x80 != 0);
+            self.p.set(Status::Z, self.a.wrapping_add(m).wrapping_add(c as u8) == 0);
+        }
+        self.a = r;
     }
 
     fn and(&mut self, m: u8) {
@@ -216,25 +871,30 @@ impl CPU {
     fn bit(&mut self, m: u8) {
         let t = self.a & m;
         self.p.set(Status::N, m & 0x80 != 0);
-        self.p.set(Status::V, m & 0x80 != 0);
+        self.p.set(Status::V, m & 0x40 != 0);
         self.p.set(Status::Z, t == 0);
     }
 
+    // BIT #imm only changes Z
+    fn bit_imm(&mut self, m: u8) {
+        self.p.set(Status::Z, self.a & m == 0);
+    }
+
     fn cmp(&mut self, m: u8) {
-        let t = self.a - m;
+        let t = self.a.wrapping_sub(m);
         self.p.set(Status::C, self.a >= m);
         self.p.set(Status::N, t & 0x80 != 0);
         self.p.set(Status::Z, t == 0);      
     }
 
     fn cpx(&mut self, m: u8) {
-        let t = self.x - m;
+        let t = self.x.wrapping_sub(m);
         self.p.set(Status::C, self.x >= m);
         self.update_zn(t);
     }
 
     fn cpy(&mut self, m: u8) {
-        let t = self.y - m;
+        let t = self.y.wrapping_sub(m);
         self.p.set(Status::C, self.y >= m);
         self.update_zn(t);
     }
@@ -264,14 +924,121 @@ impl CPU {
         self.update_zn(self.a);
     }
 
-    fn sbc(&mut self, _m: u8) {
-        todo!();
+    fn sbc(&mut self, m: u8) {
+        if self.decimal_mode() {
+            self.decimal_cycle();
+            self.sbc_decimal(m);
+        } else {
+            self.adc_binary(!m);
+        }
+    }
+
+    // C and V always match binary mode. The NMOS part also takes N and Z
+    // from the binary result, the CMOS part from the accumulator.
+    fn sbc_decimal(&mut self, m: u8) {
+        let borrow = 1 - self.p.contains(Status::C) as i16;
+        let (a, b) = (self.a as i16, m as i16);
+
+        let r = if self.variant.is_cmos() {
+            let al = (a & 0x0f) - (b & 0x0f) - borrow;
+            let mut t = a - b - borrow;
+            if t < 0 {
+                t -= 0x60;
+            }
+            if al < 0 {
+                t -= 0x06;
+            }
+            t as u8
+        } else {
+            let mut al = (a & 0x0f) - (b & 0x0f) - borrow;
+            if al < 0 {
+                al = ((al - 0x06) & 0x0f) - 0x10;
+            }
+            let mut t = (a & 0xf0) - (b & 0xf0) + al;
+            if t < 0 {
+                t -= 0x60;
+            }
+            t as u8
+        };
+
+        self.adc_binary(!m);
+        if self.variant.is_cmos() {
+            self.update_zn(r);
+        }
+        self.a = r;
+    }
+
+    // Undocumented Register Operations
+
+    fn alr(&mut self, m: u8) {
+        self.and(m);
+        self.a = self.lsr(self.a);
+    }
+
+    fn anc(&mut self, m: u8) {
+        self.and(m);
+        self.p.set(Status::C, self.a & 0x80 != 0);
+    }
+
+    fn ane(&mut self, m: u8) {
+        self.a = (self.a | self.magic) & self.x & m;
+        self.update_zn(self.a);
+    }
+
+    // AND then ROR, with C and V from bits 6 and 5 of the result. In
+    // decimal mode the result is adjusted like ADC would.
+    fn arr(&mut self, m: u8) {
+        let t = self.a & m;
+        let carry = self.p.contains(Status::C);
+        let mut r = (t >> 1) | (carry as u8) << 7;
+        if !self.decimal_mode() {
+            self.update_zn(r);
+            self.p.set(Status::C, r & 0x40 != 0);
+            self.p.set(Status::V, ((r >> 6) ^ (r >> 5)) & 0x01 != 0);
+        } else {
+            self.p.set(Status::N, carry);
+            self.p.set(Status::Z, r == 0);
+            self.p.set(Status::V, (t ^ r) & 0x40 != 0);
+            if (t & 0x0f) + (t & 0x01) > 0x05 {
+                r = (r & 0xf0) | (r.wrapping_add(0x06) & 0x0f);
+            }
+            let high = (t & 0xf0) as u16 + (t & 0x10) as u16 > 0x50;
+            if high {
+                r = r.wrapping_add(0x60);
+            }
+            self.p.set(Status::C, high);
+        }
+        self.a = r;
+    }
+
+    fn las(&mut self, m: u8) {
+        self.s &= m;
+        self.a = self.s;
+        self.x = self.s;
+        self.update_zn(self.s);
+    }
+
+    fn lax(&mut self, m: u8) {
+        self.a = m;
+        self.x = m;
+        self.update_zn(m);
+    }
+
+    fn lxa(&mut self, m: u8) {
+        self.lax((self.a | self.magic) & m);
+    }
+
+    fn sbx(&mut self, m: u8) {
+        let t = self.a & self.x;
+        self.p.set(Status::C, t >= m);
+        self.x = t.wrapping_sub(m);
+        self.update_zn(self.x);
     }
 
     // Memory Operations
 
     fn asl(&mut self, m: u8) -> u8 {
-        self.p.set(Status::B, m & 0x80 != 0);
+        self.p.set(Status::C, m & 0x80 != 0);
         let m = m << 1;
         self.p.set(Status::N, m & 0x80 != 0);
         self.p.set(Status::Z, m == 0);
@@ -308,11 +1075,60 @@ impl CPU {
     fn ror(&mut self, m: u8) -> u8 {
         let carry = if self.p.contains(Status::C) { 0x01 } else { 0x00 };
         self.p.set(Status::C, m & 0x01 != 0);
-        let m = (m << 1) | (carry << 7);
+        let m = (m >> 1) | (carry << 7);
         self.update_zn(m);
         m
     }
 
+    fn trb(&mut self, m: u8) -> u8 {
+        self.p.set(Status::Z, self.a & m == 0);
+        m & !self.a
+    }
+
+    fn tsb(&mut self, m: u8) -> u8 {
+        self.p.set(Status::Z, self.a & m == 0);
+        m | self.a
+    }
+
+    // Undocumented read-modify-write operations also do an ALU operation
+    // with the result
+
+    fn dcp(&mut self, m: u8) -> u8 {
+        let r = self.dec(m);
+        self.cmp(r);
+        r
+    }
+
+    fn isc(&mut self, m: u8) -> u8 {
+        let r = self.inc(m);
+        self.sbc(r);
+        r
+    }
+
+    fn rla(&mut self, m: u8) -> u8 {
+        let r = self.rol(m);
+        self.and(r);
+        r
+    }
+
+    fn rra(&mut self, m: u8) -> u8 {
+        let r = self.ror(m);
+        self.adc(r);
+        r
+    }
+
+    fn slo(&mut self, m: u8) -> u8 {
+        let r = self.asl(m);
+        self.ora(r);
+        r
+    }
+
+    fn sre(&mut self, m: u8) -> u8 {
+        let r = self.lsr(m);
+        self.eor(r);
+        r
+    }
+
     // Memory Update Operations
 
     fn sta(&mut self) -> u8 {
@@ -327,150 +1143,271 @@ impl CPU {
         self.y
     }
 
-    fn set_mem_zpg(&mut self, op: SetOp) {
+    fn stz(&mut self) -> u8 {
+        0
+    }
+
+    fn sax(&mut self) -> u8 {
+        self.a & self.x
+    }
+
+    // SHA, SHX, SHY and TAS store the value ANDed with the high byte of
+    // the address plus one. When indexing crosses a page, the value also
+    // replaces the high byte of the address.
+    fn store_high(&mut self, mnemonic: Mnemonic, mode: Mode) {
+        let (address, index) = match mode {
+            Mode::Indy => {
+                let pointer = self.read_byte();
+                (self.get_pointer(pointer), self.y)
+            }
+            Mode::Absx => (self.read_word(), self.x),
+            _ => (self.read_word(), self.y),
+        };
+        let v = match mnemonic {
+            Mnemonic::SHX => self.x,
+            Mnemonic::SHY => self.y,
+            Mnemonic::TAS => {
+                self.s = self.a & self.x;
+                self.s
+            }
+            _ => self.a & self.x,
+        };
+        let effective = self.index(address, index, true);
+        let v = v & ((address >> 8) as u8).wrapping_add(1);
+        let effective = if page_crossed(address, effective) { (v as u16) << 8 | effective & 0x00ff } else { effective };
+        self.set_byte(effective, v);
+    }
+
+    fn set_mem_zpg(&mut self, op: SetOp<B>) {
         let a = self.read_byte();
         let r = op(self);
         self.set_byte_zpg(a, r);
     }
 
-    fn set_mem_zpgx(&mut self, op: SetOp) {
+    fn set_mem_zpgx(&mut self, op: SetOp<B>) {
         let a = self.read_byte();
         let r = op(self);
         self.set_byte_zpgx(a, r);
     }
 
-    fn set_mem_zpgy(&mut self, op: SetOp) {
+    fn set_mem_zpgy(&mut self, op: SetOp<B>) {
         let a = self.read_byte();
         let r = op(self);
         self.set_byte_zpgy(a, r);
     }
 
-    fn set_mem_abs(&mut self, op: SetOp) {
+    fn set_mem_abs(&mut self, op: SetOp<B>) {
         let a = self.read_word();
         let r = op(self);
         self.set_byte_abs(a, r);
     }
 
-    fn set_mem_absx(&mut self, op: SetOp) {
+    fn set_mem_absx(&mut self, op: SetOp<B>) {
         let a = self.read_word();
         let r = op(self);
         self.set_byte_absx(a, r);
     }
 
-    fn set_mem_absy(&mut self, op: SetOp) {
+    fn set_mem_absy(&mut self, op: SetOp<B>) {
         let a = self.read_word();
         let r = op(self);
         self.set_byte_absy(a, r);
     }
 
-    fn set_mem_xind(&mut self, op: SetOp) {
+    fn set_mem_xind(&mut self, op: SetOp<B>) {
         let a = self.read_byte();
         let r = op(self);
         self.set_byte_xind(a, r);
     }
 
-    fn set_mem_indy(&mut self, op: SetOp) {
+    fn set_mem_indy(&mut self, op: SetOp<B>) {
         let a = self.read_byte();
         let r = op(self);
         self.set_byte_indy(a, r);
     }
 
+    fn set_mem_zpi(&mut self, op: SetOp<B>) {
+        let a = self.read_byte();
+        let r = op(self);
+        self.set_byte_zpi(a, r);
+    }
+
     //
 
-    fn mod_acc(&mut self, op: MemOp) {
+    fn mod_acc(&mut self, op: MemOp<B>) {
         self.a = op(self, self.a);
     }
 
-    fn mod_zpg(&mut self, op: MemOp) {
-        let a = self.read_byte();
-        let m = self.get_byte_zpg(a);
+    // Read-modify-write instructions write the unmodified value back
+    // while they compute the new one. The 65C02 reads it again instead.
+    fn modify(&mut self, address: u16, op: MemOp<B>) {
+        let m = self.get_byte(address);
+        if self.variant.is_cmos() {
+            self.dummy_read(address);
+        } else {
+            self.dummy_write(address, m);
+        }
         let r = op(self, m);
-        self.set_byte_zpg(a, r);
+        self.set_byte(address, r);
     }
 
-    fn mod_zpgx(&mut self, op: MemOp) {
+    fn mod_zpg(&mut self, op: MemOp<B>) {
         let a = self.read_byte();
-        let m = self.get_byte_zpgx(a);
-        let r = op(self, m);
-        self.set_byte_zpgx(a, r);
+        self.modify(a as u16, op);
     }
 
-    fn mod_zpgy(&mut self, op: MemOp) {
+    fn mod_zpgx(&mut self, op: MemOp<B>) {
         let a = self.read_byte();
-        let m = self.get_byte_zpgy(a);
-        let r = op(self, m);
-        self.set_byte_zpgy(a, r);
+        self.dummy_read(a as u16);
+        self.modify(a.wrapping_add(self.x) as u16, op);
     }
 
-    fn mod_abs(&mut self, op: MemOp) {
+    fn mod_zpgy(&mut self, op: MemOp<B>) {
+        let a = self.read_byte();
+        self.dummy_read(a as u16);
+        self.modify(a.wrapping_add(self.y) as u16, op);
+    }
+
+    fn mod_abs(&mut self, op: MemOp<B>) {
         let a = self.read_word();
-        let m = self.get_byte_abs(a);
-        let r = op(self, m);
-        self.set_byte_abs(a, r);
+        self.modify(a, op);
     }
 
-    fn mod_absx(&mut self, op: MemOp) {
+    fn mod_absx(&mut self, op: MemOp<B>, page_penalty: bool) {
         let a = self.read_word();
-        let m = self.get_byte_absx(a);
-        let r = op(self, m);
-        self.set_byte_absx(a, r);
+        let a = self.index(a, self.x, !page_penalty);
+        self.modify(a, op);
+    }
+
+    fn mod_absy(&mut self, op: MemOp<B>) {
+        let a = self.read_word();
+        let a = self.index(a, self.y, true);
+        self.modify(a, op);
+    }
+
+    fn mod_xind(&mut self, op: MemOp<B>) {
+        let a = self.read_byte();
+        self.dummy_read(a as u16);
+        let a = self.get_pointer(a.wrapping_add(self.x));
+        self.modify(a, op);
+    }
+
+    fn mod_indy(&mut self, op: MemOp<B>) {
+        let a = self.read_byte();
+        let a = self.get_pointer(a);
+        let a = self.index(a, self.y, true);
+        self.modify(a, op);
     }
     
     //
 
-    fn mod_acc_imm(&mut self, op: RegOp) {
+    fn mod_acc_imm(&mut self, op: RegOp<B>) {
         let m = self.read_byte();
         op(self, m);
     }
 
-    fn mod_acc_zpg(&mut self, op: RegOp) {
+    fn mod_acc_zpg(&mut self, op: RegOp<B>) {
         let a = self.read_byte();
         let m = self.get_byte_zpg(a);
         op(self, m);
     }
 
-    fn mod_acc_zpgx(&mut self, op: RegOp) {
+    fn mod_acc_zpgx(&mut self, op: RegOp<B>) {
         let operand = self.read_byte();
         let m = self.get_byte_zpgx(operand);
         op(self, m);
     }
 
-    fn mod_acc_zpgy(&mut self, op: RegOp) {
+    fn mod_acc_zpgy(&mut self, op: RegOp<B>) {
         let operand = self.read_byte();
-        let m = self.get_byte_zpgx(operand);
+        let m = self.get_byte_zpgy(operand);
         op(self, m);
     }
 
-    fn mod_acc_abs(&mut self, op: RegOp) {
+    fn mod_acc_abs(&mut self, op: RegOp<B>) {
         let operand = self.read_word();
         let m = self.get_byte_abs(operand);
         op(self, m);
     }
 
-    fn mod_acc_absx(&mut self, op: RegOp) {
+    fn mod_acc_absx(&mut self, op: RegOp<B>) {
         let operand = self.read_word();
         let m = self.get_byte_absx(operand);
         op(self, m);
     }
 
-    fn mod_acc_absy(&mut self, op: RegOp) {
+    fn mod_acc_absy(&mut self, op: RegOp<B>) {
         let operand = self.read_word();
         let m = self.get_byte_absy(operand);
         op(self, m);
     }
 
-    fn mod_acc_xind(&mut self, op: RegOp) {
+    fn mod_acc_xind(&mut self, op: RegOp<B>) {
         let operand: u8 = self.read_byte();
         let m = self.get_byte_xind(operand);
         op(self, m);
     }
 
-    fn mod_acc_indy(&mut self, op: RegOp) {
+    fn mod_acc_indy(&mut self, op: RegOp<B>) {
         let operand = self.read_byte();
         let m = self.get_byte_indy(operand);
         op(self, m);
     }
 
+    fn mod_acc_zpi(&mut self, op: RegOp<B>) {
+        let operand = self.read_byte();
+        let m = self.get_byte_zpi(operand);
+        op(self, m);
+    }
+
+    // Dispatch on addressing mode
+
+    fn read_mode(&mut self, mode: Mode, op: RegOp<B>) {
+        match mode {
+            Mode::Imm => self.mod_acc_imm(op),
+            Mode::Zpg => self.mod_acc_zpg(op),
+            Mode::Zpgx => self.mod_acc_zpgx(op),
+            Mode::Zpgy => self.mod_acc_zpgy(op),
+            Mode::Abs => self.mod_acc_abs(op),
+            Mode::Absx => self.mod_acc_absx(op),
+            Mode::Absy => self.mod_acc_absy(op),
+            Mode::Xind => self.mod_acc_xind(op),
+            Mode::Indy => self.mod_acc_indy(op),
+            Mode::Zpi => self.mod_acc_zpi(op),
+            _ => unreachable!("{:?} is not a read mode", mode),
+        }
+    }
+
+    fn modify_mode(&mut self, opcode: Opcode, op: MemOp<B>) {
+        match opcode.mode {
+            Mode::Acc => self.mod_acc(op),
+            Mode::Zpg => self.mod_zpg(op),
+            Mode::Zpgx => self.mod_zpgx(op),
+            Mode::Zpgy => self.mod_zpgy(op),
+            Mode::Abs => self.mod_abs(op),
+            Mode::Absx => self.mod_absx(op, opcode.page_penalty),
+            Mode::Absy => self.mod_absy(op),
+            Mode::Xind => self.mod_xind(op),
+            Mode::Indy => self.mod_indy(op),
+            _ => unreachable!("{:?} is not a read-modify-write mode", opcode.mode),
+        }
+    }
+
+    fn store_mode(&mut self, mode: Mode, op: SetOp<B>) {
+        match mode {
+            Mode::Zpg => self.set_mem_zpg(op),
+            Mode::Zpgx => self.set_mem_zpgx(op),
+            Mode::Zpgy => self.set_mem_zpgy(op),
+            Mode::Abs => self.set_mem_abs(op),
+            Mode::Absx => self.set_mem_absx(op),
+            Mode::Absy => self.set_mem_absy(op),
+            Mode::Xind => self.set_mem_xind(op),
+            Mode::Indy => self.set_mem_indy(op),
+            Mode::Zpi => self.set_mem_zpi(op),
+            _ => unreachable!("{:?} is not a store mode", mode),
+        }
+    }
+
     //
 
     // fn test_mem_imm(&mut self, op: TestOp) {
@@ -486,244 +1423,614 @@ impl CPU {
     // }
 
     fn branch(&mut self, flag: Status, set: bool) {
+        self.branch_if(self.p.contains(flag) == set);
+    }
+
+    fn branch_if(&mut self, taken: bool) {
         let offset = (self.read_byte() as i8) as i16;
-        if self.p.contains(flag) == set {
-            let t = self.pc as i16;
-            self.pc = t.wrapping_add(offset) as u16;
+        if taken {
+            let t = (self.pc as i16).wrapping_add(offset) as u16;
+            self.dummy_read(self.pc);
+            if page_crossed(self.pc, t) {
+                self.dummy_read(self.pc & 0xFF00 | t & 0x00FF);
+                self.extra_cycles += 2;
+            } else {
+                self.extra_cycles += 1;
+            }
+            self.pc = t;
         }
     }
 
+    // RMB and SMB clear or set a bit in the zero page, BBR and BBS branch
+    // on it. Both read the byte twice.
+
+    fn change_bit(&mut self, bit: u8, set: bool) {
+        let address = self.read_byte() as u16;
+        let m = self.get_byte(address);
+        self.dummy_read(address);
+        self.set_byte(address, if set { m | bit } else { m & !bit });
+    }
+
+    fn branch_on_bit(&mut self, bit: u8, set: bool) {
+        let address = self.read_byte() as u16;
+        let m = self.get_byte(address);
+        self.dummy_read(address);
+        self.branch_if((m & bit != 0) == set);
+    }
+
+    // Interrupts
+
+    /// Assert the IRQ line. It stays asserted until `clear_irq` is called
+    /// and is serviced before every instruction while `Status::I` is clear.
+    pub fn raise_irq(&mut self) {
+        self.irq = true;
+    }
+
+    pub fn clear_irq(&mut self) {
+        self.irq = false;
+    }
+
+    /// Signal an NMI edge. The NMI is serviced once, before the next
+    /// instruction, regardless of `Status::I`.
+    pub fn trigger_nmi(&mut self) {
+        self.nmi = true;
+    }
+
+    /// Perform the reset sequence: the stack pointer goes down by three
+    /// without writing, interrupts are disabled and execution continues at
+    /// the address in the reset vector. The 65C02 also clears decimal mode.
+    /// The history is cleared.
+    pub fn reset(&mut self) {
+        self.s = self.s.wrapping_sub(3);
+        self.p.insert(Status::I);
+        if self.variant.is_cmos() {
+            self.p.remove(Status::D);
+        }
+        self.nmi = false;
+        self.jammed = None;
+        self.waiting = false;
+        self.stopped = false;
+        self.tick = None;
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        self.pc = self.get_word(RESET_VECTOR);
+        self.cycles += 7;
+    }
+
+    // The pushed status always has U set. B is only set when the
+    // interrupt comes from a BRK instruction. The 65C02 also clears D.
+    fn interrupt(&mut self, vector: u16, brk: bool) {
+        let mut p = self.p | Status::U;
+        p.set(Status::B, brk);
+        self.push_word(self.pc);
+        self.push_byte(p.bits());
+        self.p.insert(Status::I);
+        if self.variant.is_cmos() {
+            self.p.remove(Status::D);
+        }
+        self.pc = self.get_word(vector);
+    }
+
     //
 
+    /// True after WAI, until an interrupt is raised.
+    pub fn is_waiting(&self) -> bool {
+        self.waiting
+    }
+
+    /// True after STP, until a reset.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Total number of cycles executed since the CPU was created.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Breakpoints
+
+    /// Stop the `run` functions before executing the instruction at `pc`.
+    /// A run that starts at a breakpoint stops there straight away, unless
+    /// the previous run stopped at that same breakpoint and nothing has
+    /// been executed since, so that calling `run` again carries on.
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.remove(&pc);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    // Watchpoints
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    // Remember the first access in this step that hits a watchpoint
+    fn watch(&mut self, address: u16, write: bool, old: u8, new: u8) {
+        if self.watch_hit.is_none() && self.watchpoints.iter().any(|w| w.matches(address, write, new)) {
+            self.watch_hit = Some(WatchHit { address, write, old, new, pc: self.instruction_pc });
+        }
+    }
+
     /// Call step until it fails or hits a breakpoint.
-    pub fn run(&mut self) -> Result<(), CPUError> {
-        loop { self.step()?; }
+    pub fn run(&mut self) -> Result<StopReason, CPUError> {
+        self.run_with(|_, _, _| None)
     }
 
-    /// Step one instruction.
-    pub fn step(&mut self) -> Result<(), CPUError> {
-        let opcode = self.read_byte();
-        match opcode {
-            // BRK
-            0x00 => {
-                self.push_word(self.pc + 2);
-                self.p.set(Status::I, true);
-                self.push_byte(self.p.bits());
+    /// Call step until `pred` returns true, a breakpoint is hit or it
+    /// fails. `pred` is checked before every instruction, including the
+    /// first one.
+    pub fn run_until<F: FnMut(&Self) -> bool>(&mut self, mut pred: F) -> Result<StopReason, CPUError> {
+        self.run_with(|cpu, _, _| if pred(cpu) { Some(StopReason::Condition) } else { None })
+    }
+
+    /// Run for at least `n` cycles. Instructions are never split, so this
+    /// can overshoot by a few cycles.
+    pub fn run_for_cycles(&mut self, n: u64) -> Result<StopReason, CPUError> {
+        self.run_with(|_, _, cycles| if cycles >= n { Some(StopReason::CycleLimit) } else { None })
+    }
+
+    pub fn run_for_instructions(&mut self, n: u64) -> Result<StopReason, CPUError> {
+        self.run_with(|_, instructions, _| if instructions >= n { Some(StopReason::InstructionLimit) } else { None })
+    }
+
+    // Steps until done returns a reason to stop. It is called before the
+    // first step and after every step with the number of instructions and
+    // cycles executed so far.
+    fn run_with<F>(&mut self, mut done: F) -> Result<StopReason, CPUError>
+    where
+        F: FnMut(&Self, u64, u64) -> Option<StopReason>,
+    {
+        let start = self.cycles;
+        let mut instructions = 0;
+        loop {
+            if let Some(reason) = done(self, instructions, self.cycles - start) {
+                return Ok(reason);
             }
+            if self.stopped {
+                return Ok(StopReason::Stopped);
+            }
+            // Nothing can raise an interrupt while this runs
+            if self.waiting && !self.nmi && !self.irq {
+                return Ok(StopReason::Waiting);
+            }
+            if self.breakpoints.contains(&self.pc) && self.resume_breakpoint != Some(self.pc) {
+                self.resume_breakpoint = Some(self.pc);
+                return Ok(StopReason::Breakpoint(self.pc));
+            }
+            self.step()?;
+            instructions += 1;
+            if let Some(hit) = self.watch_hit.take() {
+                return Ok(StopReason::Watchpoint(hit));
+            }
+        }
+    }
 
-            // JSR ABS
-            0x20 => {
-                self.push_word(self.pc + 2);
-                self.pc = self.read_word();
+    /// Step one instruction, or service a pending interrupt. Returns the
+    /// number of cycles that took. If `tick` is part way through an
+    /// instruction, this finishes that instruction. After WAI this idles
+    /// for one cycle until an interrupt is raised, after STP it does
+    /// nothing and returns 0.
+    pub fn step(&mut self) -> Result<u8, CPUError> {
+        if self.tick.is_some() {
+            loop {
+                if let Some(cycles) = self.tick()? {
+                    return Ok(cycles);
+                }
             }
+        }
+
+        self.begin_instruction();
+        let result = self.execute();
+        self.end_instruction();
+        let cycles = result?;
+
+        if let Some(e) = self.fault.take() {
+            return Err(e);
+        }
+        if let Some(fault) = self.mem.take_fault() {
+            return Err(CPUError::from_bus_fault(fault, self.instruction_pc));
+        }
+        Ok(cycles)
+    }
+
+    /// Run one clock cycle, which is one bus cycle including the NMOS
+    /// dummy reads and writes. Returns the number of cycles of the
+    /// instruction when this cycle finishes it, and None otherwise.
+    ///
+    /// The registers keep their old values until the last cycle of an
+    /// instruction. Interrupts are checked when an instruction starts,
+    /// and errors are returned on its last cycle, like `step` does.
+    pub fn tick(&mut self) -> Result<Option<u8>, CPUError> {
+        let mut tick = self.tick.take().unwrap_or_else(|| Tick {
+            start: self.registers(),
+            cycles: Vec::new(),
+            fault: None,
+        });
+
+        // Run from the start with the interrupt lines as they were then
+        let (irq, nmi) = (self.irq, self.nmi);
+        self.restore(&tick.start);
+        let done = tick.cycles.len();
+        self.replay = Some(Replay { cycles: std::mem::take(&mut tick.cycles), done, next: 0 });
+        self.begin_instruction();
+        let result = self.execute();
+        let replay = self.replay.take().unwrap();
+        if let Some(fault) = self.mem.take_fault() {
+            tick.fault = tick.fault.or(Some(CPUError::from_bus_fault(fault, self.instruction_pc)));
+        }
+        self.irq = irq;
+
+        if replay.next > done + 1 {
+            self.restore(&tick.start);
+            self.irq = irq;
+            self.nmi = nmi;
+            self.cycles += replay.cycles.len() as u64;
+            self.watch_hit = None;
+            self.fault = None;
+            tick.cycles = replay.cycles;
+            self.tick = Some(tick);
+            return Ok(None);
+        }
+
+        // An NMI that came in while the instruction ran is still pending
+        self.nmi |= nmi && !tick.start.nmi;
+        self.end_instruction();
+        let cycles = result?;
+        if let Some(e) = self.fault.take().or(tick.fault) {
+            return Err(e);
+        }
+        Ok(Some(cycles))
+    }
+
+    fn registers(&self) -> Registers {
+        Registers {
+            pc: self.pc,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            s: self.s,
+            p: self.p,
+            irq: self.irq,
+            nmi: self.nmi,
+            waiting: self.waiting,
+            stopped: self.stopped,
+            jammed: self.jammed,
+            cycles: self.cycles,
+        }
+    }
+
+    fn restore(&mut self, start: &Registers) {
+        self.pc = start.pc;
+        self.a = start.a;
+        self.x = start.x;
+        self.y = start.y;
+        self.s = start.s;
+        self.p = start.p;
+        self.irq = start.irq;
+        self.nmi = start.nmi;
+        self.waiting = start.waiting;
+        self.stopped = start.stopped;
+        self.jammed = start.jammed;
+        self.cycles = start.cycles;
+    }
+
+    fn begin_instruction(&mut self) {
+        self.instruction_pc = self.pc;
+        self.resume_breakpoint = None;
+        self.watch_hit = None;
+        self.fault = None;
+        let registers = self.registers();
+        if let Some(history) = &mut self.history {
+            history.begin(registers);
+        }
+    }
+
+    fn end_instruction(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.end();
+        }
+    }
+
+    fn execute(&mut self) -> Result<u8, CPUError> {
+        if let Some(log) = &mut self.bus_log {
+            log.clear();
+        }
+
+        // Only a reset gets a jammed CPU going again
+        if let Some(opcode) = self.jammed {
+            return Err(CPUError::Jam { opcode, pc: self.pc });
+        }
+
+        if self.stopped {
+            return Ok(0);
+        }
+        // WAI wakes up for any interrupt, and only services it if the
+        // interrupt is enabled
+        if self.waiting {
+            if !self.nmi && !self.irq {
+                self.cycles += 1;
+                return Ok(1);
+            }
+            self.waiting = false;
+        }
+
+        if self.nmi {
+            self.nmi = false;
+            self.dummy_read(self.pc);
+            self.dummy_read(self.pc);
+            self.interrupt(NMI_VECTOR, false);
+            self.cycles += 7;
+            return Ok(7);
+        }
+
+        if self.irq && !self.p.contains(Status::I) {
+            self.dummy_read(self.pc);
+            self.dummy_read(self.pc);
+            self.interrupt(IRQ_VECTOR, false);
+            self.cycles += 7;
+            return Ok(7);
+        }
+
+        self.extra_cycles = 0;
+        self.page_crossed = false;
+
+        let opcode = self.read_byte();
+        let Some(op) = self.opcodes[opcode as usize] else {
+            return Err(CPUError::IllegalInstruction { opcode, pc: self.instruction_pc });
+        };
+
+        // One byte instructions read the next byte anyway and ignore it,
+        // except for the single cycle NOPs of the 65C02
+        if matches!(op.mode, Mode::Imp | Mode::Acc) && op.cycles > 1 {
+            self.dummy_read(self.pc);
+        }
 
-            // RTS
-            0x60 => {
+        match op.mnemonic {
+            Mnemonic::ADC => self.read_mode(op.mode, Self::adc),
+            Mnemonic::AND => self.read_mode(op.mode, Self::and),
+            Mnemonic::BIT if op.mode == Mode::Imm => self.read_mode(op.mode, Self::bit_imm),
+            Mnemonic::BIT => self.read_mode(op.mode, Self::bit),
+            Mnemonic::CMP => self.read_mode(op.mode, Self::cmp),
+            Mnemonic::CPX => self.read_mode(op.mode, Self::cpx),
+            Mnemonic::CPY => self.read_mode(op.mode, Self::cpy),
+            Mnemonic::EOR => self.read_mode(op.mode, Self::eor),
+            Mnemonic::LDA => self.read_mode(op.mode, Self::lda),
+            Mnemonic::LDX => self.read_mode(op.mode, Self::ldx),
+            Mnemonic::LDY => self.read_mode(op.mode, Self::ldy),
+            Mnemonic::ORA => self.read_mode(op.mode, Self::ora),
+            Mnemonic::SBC => self.read_mode(op.mode, Self::sbc),
+
+            Mnemonic::ASL => self.modify_mode(op, Self::asl),
+            Mnemonic::DEC => self.modify_mode(op, Self::dec),
+            Mnemonic::INC => self.modify_mode(op, Self::inc),
+            Mnemonic::LSR => self.modify_mode(op, Self::lsr),
+            Mnemonic::ROL => self.modify_mode(op, Self::rol),
+            Mnemonic::ROR => self.modify_mode(op, Self::ror),
+
+            Mnemonic::STA => self.store_mode(op.mode, Self::sta),
+            Mnemonic::STX => self.store_mode(op.mode, Self::stx),
+            Mnemonic::STY => self.store_mode(op.mode, Self::sty),
+            Mnemonic::STZ => self.store_mode(op.mode, Self::stz),
+
+            Mnemonic::TRB => self.modify_mode(op, Self::trb),
+            Mnemonic::TSB => self.modify_mode(op, Self::tsb),
+
+            Mnemonic::BCC => self.branch(Status::C, false),
+            Mnemonic::BCS => self.branch(Status::C, true),
+            Mnemonic::BMI => self.branch(Status::N, true),
+            Mnemonic::BNE => self.branch(Status::Z, false),
+            Mnemonic::BEQ => self.branch(Status::Z, true),
+            Mnemonic::BPL => self.branch(Status::N, false),
+            Mnemonic::BVC => self.branch(Status::V, false),
+            Mnemonic::BVS => self.branch(Status::V, true),
+            Mnemonic::BRA => self.branch(Status::empty(), true),
+
+            Mnemonic::CLC => self.p.set(Status::C, false),
+            Mnemonic::CLD => self.p.set(Status::D, false),
+            Mnemonic::CLI => self.p.set(Status::I, false),
+            Mnemonic::CLV => self.p.set(Status::V, false),
+            Mnemonic::SEC => self.p.set(Status::C, true),
+            Mnemonic::SED => self.p.set(Status::D, true),
+            Mnemonic::SEI => self.p.set(Status::I, true),
+
+            Mnemonic::DEX => { self.x = self.x.wrapping_sub(1); self.update_zn(self.x); }
+            Mnemonic::DEY => { self.y = self.y.wrapping_sub(1); self.update_zn(self.y); }
+            Mnemonic::INX => { self.x = self.x.wrapping_add(1); self.update_zn(self.x); }
+            Mnemonic::INY => { self.y = self.y.wrapping_add(1); self.update_zn(self.y); }
+
+            Mnemonic::TAX => { self.x = self.a; self.update_zn(self.x); }
+            Mnemonic::TAY => { self.y = self.a; self.update_zn(self.y); }
+            Mnemonic::TXA => { self.a = self.x; self.update_zn(self.a); }
+            Mnemonic::TYA => { self.a = self.y; self.update_zn(self.a); }
+            Mnemonic::TXS => { self.s = self.x; }
+            Mnemonic::TSX => { self.x = self.s; self.update_zn(self.x); }
+
+            Mnemonic::PHA => self.push_byte(self.a),
+            Mnemonic::PHP => self.push_byte((self.p | Status::B | Status::U).bits()),
+            Mnemonic::PLA => { self.stack_dummy_read(); self.a = self.pop_byte(); self.update_zn(self.a); }
+            Mnemonic::PLP => { self.stack_dummy_read(); self.p = Status::from_bits_retain(self.pop_byte() & 0b11001111); }
+            Mnemonic::PHX => self.push_byte(self.x),
+            Mnemonic::PHY => self.push_byte(self.y),
+            Mnemonic::PLX => { self.stack_dummy_read(); self.x = self.pop_byte(); self.update_zn(self.x); }
+            Mnemonic::PLY => { self.stack_dummy_read(); self.y = self.pop_byte(); self.update_zn(self.y); }
+
+            // The byte after BRK is read and skipped
+            Mnemonic::BRK => {
+                self.pc = self.pc.wrapping_add(1);
+                self.interrupt(IRQ_VECTOR, true);
+            }
+
+            Mnemonic::RTI => {
+                self.stack_dummy_read();
+                self.p = Status::from_bits_retain(self.pop_byte() & 0b11001111);
                 self.pc = self.pop_word();
             }
 
-            // NOP
-            0xEA => {
-            }
-
-            0x69 => { self.mod_acc_imm(Self::adc); }
-            0x65 => { self.mod_acc_zpg(Self::adc); }
-            0x75 => { self.mod_acc_zpgx(Self::adc); }
-            0x6D => { self.mod_acc_zpgy(Self::adc); }
-            0x7D => { self.mod_acc_absx(Self::adc); }
-            0x79 => { self.mod_acc_absy(Self::adc); }
-            0x61 => { self.mod_acc_xind(Self::adc); }
-            0x71 => { self.mod_acc_indy(Self::adc); }        
-
-            0x29 => { self.mod_acc_imm(Self::and); }
-            0x25 => { self.mod_acc_zpg(Self::and); }
-            0x35 => { self.mod_acc_zpgx(Self::and); }
-            0x2D => { self.mod_acc_zpgy(Self::and); }
-            0x3D => { self.mod_acc_absx(Self::and); }
-            0x39 => { self.mod_acc_absy(Self::and); }
-            0x21 => { self.mod_acc_xind(Self::and); }
-            0x31 => { self.mod_acc_indy(Self::and); }        
-
-            0x0A => { self.mod_acc(Self::asl); }
-            0x06 => { self.mod_zpg(Self::asl); }
-            0x16 => { self.mod_zpgx(Self::asl); }
-            0x0E => { self.mod_abs(Self::asl); }
-            0x1E => { self.mod_absx(Self::asl); }
-
-            0x24 => { self.mod_acc_zpg(Self::bit); }
-            0x2C => { self.mod_acc_abs(Self::bit); }
-
-            /* BCC */ 0x90 => { self.branch(Status::C, false); }
-            /* BCS */ 0xB0 => { self.branch(Status::C, true); }
-            /* BMI */ 0x30 => { self.branch(Status::N, true); }
-            /* BNE */ 0xD0 => { self.branch(Status::Z, false); }
-            /* BEQ */ 0xF0 => { self.branch(Status::Z, true); }
-            /* BPL */ 0x10 => { self.branch(Status::N, false); }
-            /* BVC */ 0x50 => { self.branch(Status::C, false); }
-            /* BVS */ 0x70 => { self.branch(Status::C, true); }
-                            
-            /* CLC */ 0x18 => { self.p.set(Status::C, false); }
-            /* CLD */ 0xD8 => { self.p.set(Status::D, false); }
-            /* CLI */ 0x58 => { self.p.set(Status::I, false); }
-            /* CLV */ 0xB8 => { self.p.set(Status::V, false); }
-
-            0xC9 => { self.mod_acc_imm(Self::cmp); }
-            0xC5 => { self.mod_acc_zpg(Self::cmp); }
-            0xD5 => { self.mod_acc_zpgx(Self::cmp); }
-            0xCD => { self.mod_acc_abs(Self::cmp); }
-            0xDD => { self.mod_acc_absx(Self::cmp); }
-            0xD9 => { self.mod_acc_absy(Self::cmp); }
-            0xC1 => { self.mod_acc_xind(Self::cmp); }
-            0xD1 => { self.mod_acc_indy(Self::cmp); }
-
-            0xE0 => { self.mod_acc_imm(Self::cpx); }
-            0xE4 => { self.mod_acc_zpg(Self::cpx); }
-            0xEC => { self.mod_acc_abs(Self::cpx); }
-
-            0xC0 => { self.mod_acc_imm(Self::cpy); }
-            0xC4 => { self.mod_acc_zpg(Self::cpy); }
-            0xCC => { self.mod_acc_abs(Self::cpy); }
-
-            0xD6 => { self.mod_zpgx(Self::dec); }
-            0xC6 => { self.mod_zpg(Self::dec); }
-            0xCE => { self.mod_abs(Self::dec); }
-            0xDE => { self.mod_absx(Self::dec); }
-
-            /* DEX */ 0xCA => { self.x = self.x.wrapping_sub(1); self.update_zn(self.x); }
-            /* DEY */ 0x88 => { self.y = self.y.wrapping_sub(1); self.update_zn(self.y); }
-
-            0x49 => { self.mod_acc_imm(Self::eor); }
-            0x45 => { self.mod_acc_zpg(Self::eor); }
-            0x55 => { self.mod_acc_zpgx(Self::eor); }
-            0x4D => { self.mod_acc_zpgy(Self::eor); }
-            0x5D => { self.mod_acc_absx(Self::eor); }
-            0x59 => { self.mod_acc_absy(Self::eor); }
-            0x41 => { self.mod_acc_xind(Self::eor); }
-            0x51 => { self.mod_acc_indy(Self::eor); }        
-
-            0xE6 => { self.mod_zpg(Self::inc); }
-            0xF6 => { self.mod_zpgx(Self::inc); }
-            0xEE => { self.mod_abs(Self::inc); }
-            0xFE => { self.mod_absx(Self::inc); }
-
-            /* INX */ 0xE8 => { self.x = self.x.wrapping_add(1); self.update_zn(self.x); }
-            /* INY */ 0xC8 => { self.y = self.y.wrapping_add(1); self.update_zn(self.y); }
-
-            // JMP
-            0x4C => { self.pc = self.read_word(); }
-
-            // JMP (IND)
-            0x6C => { let address = self.read_word(); self.pc = self.get_word(address); }
-
-            // JSR
-
-            0xA9 => { self.mod_acc_imm(Self::lda); }
-            0xA5 => { self.mod_acc_zpg(Self::lda); }
-            0xB5 => { self.mod_acc_zpgx(Self::lda); }
-            0xAD => { self.mod_acc_zpgy(Self::lda); }
-            0xBD => { self.mod_acc_absx(Self::lda); }
-            0xB9 => { self.mod_acc_absy(Self::lda); }
-            0xA1 => { self.mod_acc_xind(Self::lda); }
-            0xB1 => { self.mod_acc_indy(Self::lda); }        
-
-            0xA2 => { self.mod_acc_imm(Self::ldx); }
-            0xA6 => { self.mod_acc_zpg(Self::ldx); }
-            0xB6 => { self.mod_acc_zpgy(Self::ldx); }
-            0xAE => { self.mod_acc_abs(Self::ldx); }
-            0xBE => { self.mod_acc_absy(Self::ldx); }
-
-            0xA0 => { self.mod_acc_imm(Self::ldy); }
-            0xA4 => { self.mod_acc_zpg(Self::ldy); }
-            0xB4 => { self.mod_acc_zpgx(Self::ldy); }
-            0xAC => { self.mod_acc_abs(Self::ldy); }
-            0xBC => { self.mod_acc_absx(Self::ldy); }
-
-            0x4A => { self.mod_acc(Self::lsr); }
-            0x46 => { self.mod_zpg(Self::lsr); }
-            0x56 => { self.mod_zpgx(Self::lsr); }
-            0x4E => { self.mod_abs(Self::lsr); }
-            0x5E => { self.mod_absx(Self::lsr); }
-
-            // NOP
-
-            0x09 => { self.mod_acc_imm(Self::ora); }
-            0x05 => { self.mod_acc_zpg(Self::ora); }
-            0x15 => { self.mod_acc_zpgx(Self::ora); }
-            0x0D => { self.mod_acc_zpgy(Self::ora); }
-            0x1D => { self.mod_acc_absx(Self::ora); }
-            0x19 => { self.mod_acc_absy(Self::ora); }
-            0x01 => { self.mod_acc_xind(Self::ora); }
-            0x11 => { self.mod_acc_indy(Self::ora); }
-            
-            /* PHA */ 0x48 => { self.push_byte(self.a); }
-            /* PHP */ 0x08 => { self.push_byte(self.p.bits()); }
-            /* PLA */ 0x68 => { self.a = self.pop_byte(); }
-            /* PLP */ 0x28 => { self.p = Status::from_bits_retain(self.pop_byte() & 0b11001111); }
-
-            0x2A => { self.mod_acc(Self::rol); }
-            0x26 => { self.mod_zpg(Self::rol); }
-            0x36 => { self.mod_zpgx(Self::rol); }
-            0x2E => { self.mod_abs(Self::rol); }
-            0x3E => { self.mod_absx(Self::rol); }
-
-            0x6A => { self.mod_acc(Self::ror); }
-            0x66 => { self.mod_zpg(Self::ror); }
-            0x76 => { self.mod_zpgx(Self::ror); }
-            0x6E => { self.mod_abs(Self::ror); }
-            0x7E => { self.mod_absx(Self::ror); }
-
-            // TODO RTI
-
-            0xE9 => { self.mod_acc_imm(Self::sbc); }
-            0xE5 => { self.mod_acc_zpg(Self::sbc); }
-            0xF5 => { self.mod_acc_zpgx(Self::sbc); }
-            0xED => { self.mod_acc_zpgy(Self::sbc); }
-            0xFD => { self.mod_acc_absx(Self::sbc); }
-            0xF9 => { self.mod_acc_absy(Self::sbc); }
-            0xE1 => { self.mod_acc_xind(Self::sbc); }
-            0xF1 => { self.mod_acc_indy(Self::sbc); }
-
-            /* SEC */ 0x38 => { self.p.set(Status::C, true); }
-            /* SED */ 0xF8 => { self.p.set(Status::D, true); }
-            /* SEI */ 0x78 => { self.p.set(Status::I, true); }
-
-            0x85 => { self.set_mem_zpg(Self::sta); }
-            0x95 => { self.set_mem_zpgx(Self::sta); }
-            0x8D => { self.set_mem_abs(Self::sta); }
-            0x9D => { self.set_mem_absx(Self::sta); }
-            0x99 => { self.set_mem_absy(Self::sta); }
-            0x81 => { self.set_mem_xind(Self::sta); }
-            0x91 => { self.set_mem_indy(Self::sta); }
-
-            0x96 => { self.set_mem_zpg(Self::stx); }
-            0x86 => { self.set_mem_zpgy(Self::stx); }
-            0x8E => { self.set_mem_abs(Self::stx); }
-
-            0x84 => { self.set_mem_zpg(Self::sty); }
-            0x94 => { self.set_mem_zpgx(Self::sty); }
-            0x8C => { self.set_mem_abs(Self::sty); }
-
-            /* TAX */ 0xAA => { self.x = self.a; self.update_zn(self.x); }
-            /* TAY */ 0xA8 => { self.y = self.a; self.update_zn(self.y); }
-            /* TXA */ 0x8A => { self.a = self.x; self.update_zn(self.a); }
-            /* TYA */ 0x98 => { self.a = self.y; self.update_zn(self.a); }
-            /* TXS */ 0x9A => { self.s = self.x; }
-            /* TSX */ 0xBA => { self.x = self.s; self.update_zn(self.x); }
-
-            _ => {
-                return Err(CPUError::IllegalInstruction)
-            }
-        }
-
-        Ok(())
+            // Pushes the address of the last byte of the JSR, so RTS adds one
+            Mnemonic::JSR => {
+                let low = self.read_byte() as u16;
+                self.stack_dummy_read();
+                self.push_word(self.pc);
+                self.pc = (self.read_byte() as u16) << 8 | low;
+            }
+
+            Mnemonic::RTS => {
+                self.stack_dummy_read();
+                let pc = self.pop_word();
+                self.dummy_read(pc);
+                self.pc = pc.wrapping_add(1);
+            }
+
+            Mnemonic::JMP if op.mode == Mode::Abs => self.pc = self.read_word(),
+            Mnemonic::JMP if op.mode == Mode::Absxi => {
+                let address = self.read_word();
+                self.dummy_read(self.pc.wrapping_sub(1));
+                self.pc = self.get_word(address.wrapping_add(self.x as u16));
+            }
+            // The NMOS part does not carry into the high byte of the pointer,
+            // so JMP ($12FF) reads the target from $12FF and $1200. The
+            // 65C02 spends a cycle on the carry.
+            Mnemonic::JMP => {
+                let address = self.read_word();
+                let high = if self.variant.is_cmos() {
+                    self.dummy_read(self.pc.wrapping_sub(1));
+                    address.wrapping_add(1)
+                } else {
+                    address & 0xFF00 | address.wrapping_add(1) & 0x00FF
+                };
+                let low = self.get_byte(address) as u16;
+                self.pc = (self.get_byte(high) as u16) << 8 | low;
+            }
+
+            Mnemonic::RMB0 | Mnemonic::RMB1 | Mnemonic::RMB2 | Mnemonic::RMB3 | Mnemonic::RMB4 | Mnemonic::RMB5 | Mnemonic::RMB6 | Mnemonic::RMB7
+            | Mnemonic::SMB0 | Mnemonic::SMB1 | Mnemonic::SMB2 | Mnemonic::SMB3 | Mnemonic::SMB4 | Mnemonic::SMB5 | Mnemonic::SMB6 | Mnemonic::SMB7 => {
+                self.change_bit(1 << (opcode >> 4 & 0x07), opcode & 0x80 != 0)
+            }
+            Mnemonic::BBR0 | Mnemonic::BBR1 | Mnemonic::BBR2 | Mnemonic::BBR3 | Mnemonic::BBR4 | Mnemonic::BBR5 | Mnemonic::BBR6 | Mnemonic::BBR7
+            | Mnemonic::BBS0 | Mnemonic::BBS1 | Mnemonic::BBS2 | Mnemonic::BBS3 | Mnemonic::BBS4 | Mnemonic::BBS5 | Mnemonic::BBS6 | Mnemonic::BBS7 => {
+                self.branch_on_bit(1 << (opcode >> 4 & 0x07), opcode & 0x80 != 0)
+            }
+
+            Mnemonic::WAI => {
+                self.dummy_read(self.pc);
+                self.waiting = true;
+            }
+            Mnemonic::STP => {
+                self.dummy_read(self.pc);
+                self.stopped = true;
+            }
+
+            Mnemonic::NOP if op.mode == Mode::Imp => {}
+            // 5C on the 65C02 reads $FFxx for five cycles
+            Mnemonic::NOP if opcode == 0x5C && self.variant.is_cmos() => {
+                let address = self.read_word();
+                for _ in 0..5 {
+                    self.dummy_read(0xFF00 | address & 0x00FF);
+                }
+            }
+            Mnemonic::NOP => self.read_mode(op.mode, |_, _| {}),
+
+            Mnemonic::ALR => self.read_mode(op.mode, Self::alr),
+            Mnemonic::ANC => self.read_mode(op.mode, Self::anc),
+            Mnemonic::ANE => self.read_mode(op.mode, Self::ane),
+            Mnemonic::ARR => self.read_mode(op.mode, Self::arr),
+            Mnemonic::LAS => self.read_mode(op.mode, Self::las),
+            Mnemonic::LAX => self.read_mode(op.mode, Self::lax),
+            Mnemonic::LXA => self.read_mode(op.mode, Self::lxa),
+            Mnemonic::SBX => self.read_mode(op.mode, Self::sbx),
+
+            Mnemonic::DCP => self.modify_mode(op, Self::dcp),
+            Mnemonic::ISC => self.modify_mode(op, Self::isc),
+            Mnemonic::RLA => self.modify_mode(op, Self::rla),
+            Mnemonic::RRA => self.modify_mode(op, Self::rra),
+            Mnemonic::SLO => self.modify_mode(op, Self::slo),
+            Mnemonic::SRE => self.modify_mode(op, Self::sre),
+
+            Mnemonic::SAX => self.store_mode(op.mode, Self::sax),
+            Mnemonic::SHA | Mnemonic::SHX | Mnemonic::SHY | Mnemonic::TAS => self.store_high(op.mnemonic, op.mode),
+
+            Mnemonic::JAM => {
+                self.pc = self.instruction_pc;
+                self.jammed = Some(opcode);
+                return Err(CPUError::Jam { opcode, pc: self.instruction_pc });
+            }
+        }
+
+        let mut cycles = op.cycles + self.extra_cycles;
+        if op.page_penalty && self.page_crossed {
+            cycles += 1;
+        }
+        self.cycles += cycles as u64;
+        Ok(cycles)
     }
 }
 
+#[cfg(test)]
+mod asm_tests;
+
+#[cfg(test)]
+mod breakpoint_tests;
+
+#[cfg(test)]
+mod bus_tests;
+
 #[cfg(test)]
 mod cpu_tests;
 
+#[cfg(test)]
+mod history_tests;
+
 #[cfg(test)]
 mod ins_tests;
 
+#[cfg(test)]
+mod cycle_tests;
+
+#[cfg(test)]
+mod disasm_tests;
+
+#[cfg(test)]
+mod functional_tests;
+
+#[cfg(test)]
+mod interrupt_tests;
+
+#[cfg(test)]
+mod tick_tests;
+
 #[cfg(test)]
 mod mem_tests;
+
+#[cfg(test)]
+mod opcode_tests;
+
+#[cfg(test)]
+mod savestate_tests;
+
+#[cfg(test)]
+mod single_step_tests;
+
+#[cfg(test)]
+mod testing_tests;
+
+#[cfg(test)]
+mod w65c816_tests;
+
+#[cfg(test)]
+mod watch_tests;
+
+#[cfg(test)]
+mod memory_map_tests;
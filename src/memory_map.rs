@@ -0,0 +1,198 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/
+
+use std::ops::{Index, RangeInclusive};
+
+pub use crate::bus::BusFault;
+use crate::bus::Bus;
+
+/// Value returned when reading an address that is not mapped.
+pub const OPEN_BUS: u8 = 0xFF;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Unmapped,
+    Ram,
+    Rom,
+}
+
+enum Region {
+    Ram(RangeInclusive<u16>),
+    Rom(RangeInclusive<u16>, Vec<u8>),
+    Mirror(RangeInclusive<u16>, RangeInclusive<u16>),
+    Unmapped(RangeInclusive<u16>),
+}
+
+/// Describes a memory map over the full 16 bit address space. Regions
+/// are applied in the order they are declared, so a later region wins
+/// where two overlap. Anything not declared is unmapped.
+#[derive(Default)]
+pub struct MemoryMapBuilder {
+    regions: Vec<Region>,
+}
+
+impl MemoryMapBuilder {
+    pub fn new() -> Self {
+        MemoryMapBuilder { regions: Vec::new() }
+    }
+
+    pub fn ram(mut self, range: RangeInclusive<u16>) -> Self {
+        self.regions.push(Region::Ram(range));
+        self
+    }
+
+    /// Map `data` as ROM starting at `start`.
+    pub fn rom(mut self, start: u16, data: &[u8]) -> Self {
+        assert!(!data.is_empty(), "empty ROM at {:04X}", start);
+        let end = start as usize + data.len() - 1;
+        assert!(end <= 0xFFFF, "ROM at {:04X} does not fit in the address space", start);
+        self.regions.push(Region::Rom(start..=end as u16, data.to_vec()));
+        self
+    }
+
+    /// Make `range` an alias of `target`, repeating `target` as often as
+    /// needed to fill it. The mirror has the same type as the target.
+    pub fn mirror(mut self, range: RangeInclusive<u16>, target: RangeInclusive<u16>) -> Self {
+        assert!(!target.is_empty(), "empty mirror target");
+        self.regions.push(Region::Mirror(range, target));
+        self
+    }
+
+    pub fn unmapped(mut self, range: RangeInclusive<u16>) -> Self {
+        self.regions.push(Region::Unmapped(range));
+        self
+    }
+
+    pub fn build(self) -> MemoryMap {
+        let mut map = MemoryMap {
+            bytes: vec![0; 0x10000].into_boxed_slice(),
+            kinds: vec![Kind::Unmapped; 0x10000].into_boxed_slice(),
+            targets: (0..=0xFFFF).collect(),
+            fault: None,
+        };
+
+        for region in self.regions {
+            match region {
+                Region::Ram(range) => {
+                    for address in range {
+                        map.targets[address as usize] = address;
+                        map.kinds[address as usize] = Kind::Ram;
+                    }
+                }
+                Region::Rom(range, data) => {
+                    for (address, b) in range.zip(data) {
+                        map.targets[address as usize] = address;
+                        map.kinds[address as usize] = Kind::Rom;
+                        map.bytes[address as usize] = b;
+                    }
+                }
+                Region::Mirror(range, target) => {
+                    let size = *target.end() as usize - *target.start() as usize + 1;
+                    let start = *range.start();
+                    for address in range {
+                        let offset = (address - start) as usize % size;
+                        let mirrored = target.start().wrapping_add(offset as u16);
+                        // Resolve through the target so that mirrors of mirrors work
+                        map.targets[address as usize] = map.targets[mirrored as usize];
+                        map.kinds[address as usize] = map.kinds[mirrored as usize];
+                    }
+                }
+                Region::Unmapped(range) => {
+                    for address in range {
+                        map.targets[address as usize] = address;
+                        map.kinds[address as usize] = Kind::Unmapped;
+                    }
+                }
+            }
+        }
+
+        map
+    }
+}
+
+/// A `Bus` made of RAM, ROM, mirrored and unmapped regions. Writes to
+/// ROM and accesses to unmapped addresses do not panic; they are ignored
+/// and the first one is remembered until `take_fault` is called. The CPU
+/// does that after every instruction and turns it into a `CPUError`.
+pub struct MemoryMap {
+    bytes: Box<[u8]>,
+    kinds: Box<[Kind]>,
+    targets: Box<[u16]>,
+    fault: Option<BusFault>,
+}
+
+impl MemoryMap {
+    pub fn builder() -> MemoryMapBuilder {
+        MemoryMapBuilder::new()
+    }
+
+    pub fn is_rom(&self, address: u16) -> bool {
+        self.kinds[address as usize] == Kind::Rom
+    }
+
+    pub fn is_mapped(&self, address: u16) -> bool {
+        self.kinds[address as usize] != Kind::Unmapped
+    }
+
+    /// Copy `data` into memory starting at `address`, bypassing the ROM
+    /// write protection. Unmapped addresses are skipped.
+    pub fn load(&mut self, address: u16, data: &[u8]) {
+        for (i, b) in data.iter().enumerate() {
+            let address = address.wrapping_add(i as u16);
+            if self.is_mapped(address) {
+                self.bytes[self.targets[address as usize] as usize] = *b;
+            }
+        }
+    }
+
+    fn report(&mut self, fault: BusFault) {
+        if self.fault.is_none() {
+            self.fault = Some(fault);
+        }
+    }
+}
+
+impl Bus for MemoryMap {
+    fn read(&mut self, address: u16) -> u8 {
+        match self.kinds[address as usize] {
+            Kind::Unmapped => {
+                self.report(BusFault::UnmappedRead { address });
+                OPEN_BUS
+            }
+            _ => self.bytes[self.targets[address as usize] as usize],
+        }
+    }
+
+    fn write(&mut self, address: u16, v: u8) {
+        match self.kinds[address as usize] {
+            Kind::Ram => self.bytes[self.targets[address as usize] as usize] = v,
+            Kind::Rom => self.report(BusFault::RomWrite { address, v }),
+            Kind::Unmapped => self.report(BusFault::UnmappedWrite { address, v }),
+        }
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self[address]
+    }
+
+    // Like load, ROM takes the value and unmapped addresses ignore it
+    fn poke(&mut self, address: u16, v: u8) {
+        self.load(address, &[v]);
+    }
+
+    fn take_fault(&mut self) -> Option<BusFault> {
+        self.fault.take()
+    }
+}
+
+impl Index<u16> for MemoryMap {
+    type Output = u8;
+
+    fn index(&self, address: u16) -> &u8 {
+        match self.kinds[address as usize] {
+            Kind::Unmapped => &OPEN_BUS,
+            _ => &self.bytes[self.targets[address as usize] as usize],
+        }
+    }
+}
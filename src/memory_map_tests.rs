@@ -0,0 +1,133 @@
+use super::*;
+use memory_map::{BusFault, MemoryMap, OPEN_BUS};
+
+fn new_test_map() -> MemoryMap {
+    MemoryMap::builder()
+        .ram(0x0000..=0x07FF)
+        .mirror(0x0800..=0x1FFF, 0x0000..=0x07FF)
+        .rom(0xC000, &[0xA2, 0x42, 0x8E, 0x00, 0x02, 0x8E, 0x00, 0xC0, 0xFF])
+        .build()
+}
+
+#[test]
+fn ram_is_read_write() {
+    let mut map = new_test_map();
+    map.write(0x0123, 0x42);
+    assert_eq!(0x42, map.read(0x0123));
+    assert_eq!(None, map.take_fault());
+}
+
+#[test]
+fn mirror_aliases_target() {
+    let mut map = new_test_map();
+    map.write(0x0801, 0x11);
+    assert_eq!(0x11, map.read(0x0001));
+    assert_eq!(0x11, map.read(0x1001));
+    assert_eq!(0x11, map.read(0x1801));
+    map.write(0x07FF, 0x22);
+    assert_eq!(0x22, map.read(0x1FFF));
+}
+
+#[test]
+fn mirror_of_the_whole_address_space() {
+    let mut map = MemoryMap::builder()
+        .ram(0x0000..=0x7FFF)
+        .mirror(0x8000..=0xFFFF, 0x0000..=0xFFFF)
+        .build();
+    map.write(0x8001, 0x11);
+    assert_eq!(0x11, map.read(0x0001));
+    assert!(map.is_mapped(0xFFFF));
+}
+
+#[test]
+fn mirror_of_rom_is_rom() {
+    let mut map = MemoryMap::builder()
+        .rom(0xF000, &[0x12, 0x34])
+        .mirror(0xF002..=0xF00F, 0xF000..=0xF001)
+        .build();
+    assert_eq!(0x34, map.read(0xF00D));
+    map.write(0xF00C, 0x00);
+    assert_eq!(Some(BusFault::RomWrite { address: 0xF00C, v: 0x00 }), map.take_fault());
+    assert_eq!(0x12, map.read(0xF000));
+}
+
+#[test]
+fn rom_rejects_writes() {
+    let mut map = new_test_map();
+    map.write(0xC000, 0x00);
+    assert_eq!(0xA2, map.read(0xC000));
+    assert_eq!(Some(BusFault::RomWrite { address: 0xC000, v: 0x00 }), map.take_fault());
+    assert_eq!(None, map.take_fault());
+}
+
+#[test]
+fn unmapped_reads_open_bus() {
+    let mut map = new_test_map();
+    assert!(!map.is_mapped(0x4000));
+    assert_eq!(OPEN_BUS, map.read(0x4000));
+    assert_eq!(Some(BusFault::UnmappedRead { address: 0x4000 }), map.take_fault());
+    map.write(0x4000, 0x01);
+    assert_eq!(Some(BusFault::UnmappedWrite { address: 0x4000, v: 0x01 }), map.take_fault());
+}
+
+#[test]
+fn first_fault_is_kept() {
+    let mut map = new_test_map();
+    map.write(0xC001, 0x01);
+    map.write(0xC002, 0x02);
+    assert_eq!(Some(BusFault::RomWrite { address: 0xC001, v: 0x01 }), map.take_fault());
+}
+
+#[test]
+fn later_regions_win() {
+    let mut map = MemoryMap::builder()
+        .ram(0x0000..=0xFFFF)
+        .unmapped(0x8000..=0x8FFF)
+        .build();
+    assert!(map.is_mapped(0x7FFF));
+    assert!(!map.is_mapped(0x8000));
+    assert!(map.is_mapped(0x9000));
+    map.write(0x9000, 0x01);
+    assert_eq!(0x01, map[0x9000]);
+}
+
+#[test]
+fn load_bypasses_rom_protection() {
+    let mut map = new_test_map();
+    map.load(0xC000, &[0xEA, 0xEA]);
+    assert_eq!(0xEA, map[0xC001]);
+    assert!(map.is_rom(0xC001));
+    assert_eq!(None, map.take_fault());
+}
+
+#[test]
+fn cpu_store_to_rom_is_reported() {
+    let mut cpu = CPU::with_bus(new_test_map());
+    cpu.pc = 0xC000;
+    assert_eq!(cpu.run(), Err(CPUError::RomWrite { address: 0xC000, v: 0x42, pc: 0xC005 }));
+    assert_eq!(0x42, cpu.mem[0x0200]);
+    assert_eq!(0x42, cpu.mem[0x0A00]);
+    assert_eq!(0x8E, cpu.mem[0xC002]);
+    assert_eq!(None, cpu.mem.take_fault());
+}
+
+#[test]
+fn cpu_unmapped_read_is_reported() {
+    let mut map = new_test_map();
+    map.load(0x0200, &[0xAE, 0x00, 0x40]); // LDX $4000
+    let mut cpu = CPU::with_bus(map);
+    cpu.pc = 0x0200;
+    assert_eq!(cpu.step(), Err(CPUError::UnmappedAccess { address: 0x4000, write: false, pc: 0x0200 }));
+    assert_eq!(OPEN_BUS, cpu.x);
+}
+
+#[test]
+fn cpu_load_writes_rom_without_faults() {
+    let mut cpu = CPU::with_bus(new_test_map());
+    cpu.load(0xC000, &[0xA9, 0x07]); // LDA #$07
+    cpu.load(0x4000, &[0x01]);
+    cpu.pc = 0xC000;
+    assert_eq!(cpu.step(), Ok(2));
+    assert_eq!(0x07, cpu.a);
+    assert_eq!(OPEN_BUS, cpu.peek(0x4000));
+}
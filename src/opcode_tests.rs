@@ -0,0 +1,154 @@
+use super::*;
+use opcodes::Opcode;
+
+fn documented() -> impl Iterator<Item = (u8, Opcode)> {
+    (0..=255u8).filter_map(|opcode| OPCODES[opcode as usize].map(|op| (opcode, op)))
+}
+
+fn changes_flow(op: &Opcode) -> bool {
+    matches!(op.mnemonic, Mnemonic::BRK | Mnemonic::JMP | Mnemonic::JSR | Mnemonic::RTI | Mnemonic::RTS | Mnemonic::JAM)
+        || op.mode == Mode::Rel
+}
+
+#[test]
+fn table_is_complete() {
+    assert_eq!(151 + 12, documented().count());
+    for (opcode, op) in documented() {
+        assert_eq!(op.mode.bytes(), op.bytes, "{:02X}", opcode);
+        assert!(!op.page_penalty || matches!(op.mode, Mode::Absx | Mode::Absy | Mode::Indy), "{:02X}", opcode);
+    }
+}
+
+#[test]
+fn length_and_cycles_match_table() {
+    for (opcode, op) in documented().filter(|(_, op)| !changes_flow(op)) {
+        let mut cpu = CPU::new();
+        cpu.load(0x0400, &[opcode, 0x10, 0x20]);
+        assert_eq!(cpu.step(), Ok(op.cycles), "{:02X} {} {:?}", opcode, op.mnemonic, op.mode);
+        assert_eq!(0x0400 + op.bytes as u16, cpu.pc, "{:02X} {} {:?}", opcode, op.mnemonic, op.mode);
+    }
+}
+
+#[test]
+fn page_penalty_matches_table() {
+    for (opcode, op) in documented().filter(|(_, op)| matches!(op.mode, Mode::Absx | Mode::Absy | Mode::Indy)) {
+        let mut cpu = CPU::new();
+        cpu.load(0x0400, &[opcode, 0x10, 0x20]);
+        cpu.load(0x0010, &[0xF0, 0x20]);
+        cpu.x = 0xF0;
+        cpu.y = 0xF0;
+        let expected = op.cycles + op.page_penalty as u8;
+        assert_eq!(cpu.step(), Ok(expected), "{:02X} {} {:?}", opcode, op.mnemonic, op.mode);
+    }
+}
+
+#[test]
+fn absolute_modes_are_not_zero_page() {
+    for opcode in [0x6D, 0x2D, 0x4D, 0xAD, 0x0D, 0xED] {
+        assert_eq!(Mode::Abs, OPCODES[opcode].unwrap().mode);
+    }
+    let mut cpu = CPU::new();
+    cpu.mem[0x2010] = 0x42;
+    cpu.load(0x0400, &[0xAD, 0x10, 0x20]); // LDA $2010
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(0x42, cpu.a);
+}
+
+#[test]
+fn stx_modes() {
+    let mut cpu = CPU::new();
+    cpu.x = 0x42;
+    cpu.y = 0x01;
+    cpu.load(0x0400, &[0x86, 0x10, 0x96, 0x20]); // STX $10, STX $20,Y
+    assert_eq!(cpu.step(), Ok(3));
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(0x42, cpu.mem[0x0010]);
+    assert_eq!(0x42, cpu.mem[0x0021]);
+}
+
+#[test]
+fn ldx_zpgy_uses_y() {
+    let mut cpu = CPU::new();
+    cpu.mem[0x0012] = 0x42;
+    cpu.x = 0x01;
+    cpu.y = 0x02;
+    cpu.load(0x0400, &[0xB6, 0x10]); // LDX $10,Y
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(0x42, cpu.x);
+}
+
+#[test]
+fn bvc_and_bvs_test_v() {
+    let mut cpu = CPU::new();
+    cpu.p = Status::V;
+    cpu.load(0x0400, &[0x50, 0x10, 0x70, 0x10]); // BVC, BVS
+    assert_eq!(cpu.step(), Ok(2));
+    assert_eq!(cpu.step(), Ok(3));
+    assert_eq!(0x0414, cpu.pc);
+}
+
+#[test]
+fn illegal_table_is_complete() {
+    for (opcode, op) in NMOS_ILLEGAL.iter().enumerate() {
+        let op = op.unwrap_or_else(|| panic!("{:02X} missing", opcode));
+        assert_eq!(op.mode.bytes(), op.bytes, "{:02X}", opcode);
+        if let Some(documented) = OPCODES[opcode] {
+            assert_eq!(documented, op, "{:02X}", opcode);
+        }
+    }
+    assert_eq!(12, NMOS_ILLEGAL.iter().flatten().filter(|op| op.mnemonic == Mnemonic::JAM).count());
+}
+
+#[test]
+fn illegal_length_and_cycles_match_table() {
+    for (opcode, op) in (0..=255u8).map(|opcode| (opcode, NMOS_ILLEGAL[opcode as usize].unwrap())).filter(|(_, op)| !changes_flow(op)) {
+        let mut cpu = CPU::builder().illegal_opcodes(true).build();
+        cpu.load(0x0400, &[opcode, 0x10, 0x20]);
+        assert_eq!(cpu.step(), Ok(op.cycles), "{:02X} {} {:?}", opcode, op.mnemonic, op.mode);
+        assert_eq!(0x0400 + op.bytes as u16, cpu.pc, "{:02X} {} {:?}", opcode, op.mnemonic, op.mode);
+    }
+}
+
+#[test]
+fn illegal_opcodes_are_off_by_default() {
+    let mut cpu = CPU::new();
+    cpu.load(0x0400, &[0xA7, 0x10]); // LAX $10
+    assert_eq!(cpu.step(), Err(CPUError::IllegalInstruction { opcode: 0xA7, pc: 0x0400 }));
+}
+
+#[test]
+fn cmos_table_is_complete() {
+    for (opcode, op) in CMOS.iter().enumerate() {
+        let op = op.unwrap_or_else(|| panic!("{:02X} missing", opcode));
+        assert_eq!(op.mode.bytes(), op.bytes, "{:02X}", opcode);
+        assert_ne!(Mnemonic::JAM, op.mnemonic, "{:02X}", opcode);
+    }
+    // The NMOS instructions other than NOP, and 27 new ones
+    assert_eq!(150 + 27, CMOS.iter().flatten().filter(|op| op.mnemonic != Mnemonic::NOP).count());
+}
+
+#[test]
+fn cmos_length_and_cycles_match_table() {
+    for (opcode, op) in (0..=255u8).map(|opcode| (opcode, CMOS[opcode as usize].unwrap())).filter(|(_, op)| !changes_flow(op)) {
+        let mut cpu = CPU::builder().variant(Variant::Cmos65C02).build();
+        cpu.load(0x0400, &[opcode, 0x10, 0x20]);
+        assert_eq!(cpu.step(), Ok(op.cycles), "{:02X} {} {:?}", opcode, op.mnemonic, op.mode);
+        assert_eq!(0x0400 + op.bytes as u16, cpu.pc, "{:02X} {} {:?}", opcode, op.mnemonic, op.mode);
+    }
+}
+
+#[test]
+fn rockwell_and_wdc_tables() {
+    assert_eq!(Some(Mnemonic::RMB0), ROCKWELL[0x07].map(|op| op.mnemonic));
+    assert_eq!(Some(Mnemonic::SMB7), ROCKWELL[0xF7].map(|op| op.mnemonic));
+    assert_eq!(Some(Mnemonic::BBR3), ROCKWELL[0x3F].map(|op| op.mnemonic));
+    assert_eq!(Some(Mnemonic::BBS4), ROCKWELL[0xCF].map(|op| op.mnemonic));
+    assert_eq!(Some(Mnemonic::NOP), ROCKWELL[0xCB].map(|op| op.mnemonic));
+    assert_eq!(Some(Mnemonic::WAI), WDC[0xCB].map(|op| op.mnemonic));
+    assert_eq!(Some(Mnemonic::STP), WDC[0xDB].map(|op| op.mnemonic));
+    for opcode in 0..=255 {
+        if CMOS[opcode].unwrap().mnemonic != Mnemonic::NOP {
+            assert_eq!(CMOS[opcode], WDC[opcode], "{:02X}", opcode);
+        }
+    }
+}
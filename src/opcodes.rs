@@ -0,0 +1,505 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mnemonic {
+    ADC, AND, ASL, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRK, BVC, BVS, CLC,
+    CLD, CLI, CLV, CMP, CPX, CPY, DEC, DEX, DEY, EOR, INC, INX, INY, JMP,
+    JSR, LDA, LDX, LDY, LSR, NOP, ORA, PHA, PHP, PLA, PLP, ROL, ROR, RTI,
+    RTS, SBC, SEC, SED, SEI, STA, STX, STY, TAX, TAY, TSX, TXA, TXS, TYA,
+    JAM,
+    // Undocumented NMOS instructions
+    ALR, ANC, ANE, ARR, DCP, ISC, LAS, LAX, LXA, RLA, RRA, SAX, SBX, SHA,
+    SHX, SHY, SLO, SRE, TAS,
+    // 65C02 instructions
+    BRA, PHX, PHY, PLX, PLY, STZ, TRB, TSB,
+    // Rockwell and WDC 65C02 instructions
+    RMB0, RMB1, RMB2, RMB3, RMB4, RMB5, RMB6, RMB7,
+    SMB0, SMB1, SMB2, SMB3, SMB4, SMB5, SMB6, SMB7,
+    BBR0, BBR1, BBR2, BBR3, BBR4, BBR5, BBR6, BBR7,
+    BBS0, BBS1, BBS2, BBS3, BBS4, BBS5, BBS6, BBS7,
+    WAI, STP,
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Addressing modes, named like the `get_byte_*` helpers on `CPU`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Imp,
+    Acc,
+    Imm,
+    Zpg,
+    Zpgx,
+    Zpgy,
+    Abs,
+    Absx,
+    Absy,
+    Ind,
+    Xind,
+    Indy,
+    Rel,
+    // 65C02 ($12) and JMP ($1234,X)
+    Zpi,
+    Absxi,
+    // BBR and BBS $12,label
+    Zpr,
+}
+
+impl Mode {
+    /// Length of an instruction in this mode, including the opcode.
+    pub const fn bytes(self) -> u8 {
+        match self {
+            Mode::Imp | Mode::Acc => 1,
+            Mode::Imm | Mode::Zpg | Mode::Zpgx | Mode::Zpgy | Mode::Xind | Mode::Indy | Mode::Rel | Mode::Zpi => 2,
+            Mode::Abs | Mode::Absx | Mode::Absy | Mode::Ind | Mode::Absxi | Mode::Zpr => 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Opcode {
+    pub mnemonic: Mnemonic,
+    pub mode: Mode,
+    /// Length in bytes, including the opcode.
+    pub bytes: u8,
+    /// Cycles without page crossing or taken branch penalties.
+    pub cycles: u8,
+    /// Whether crossing a page while indexing costs an extra cycle.
+    pub page_penalty: bool,
+}
+
+const fn op(mnemonic: Mnemonic, mode: Mode, cycles: u8) -> Option<Opcode> {
+    Some(Opcode { mnemonic, mode, bytes: mode.bytes(), cycles, page_penalty: false })
+}
+
+// Same as op, for reads that take an extra cycle when crossing a page
+const fn opp(mnemonic: Mnemonic, mode: Mode, cycles: u8) -> Option<Opcode> {
+    Some(Opcode { mnemonic, mode, bytes: mode.bytes(), cycles, page_penalty: true })
+}
+
+pub type OpcodeTable = [Option<Opcode>; 256];
+
+/// Every NMOS 6502 opcode. Undocumented opcodes are `None`, except for
+/// the ones that jam the CPU.
+pub static OPCODES: OpcodeTable = nmos();
+
+/// `OPCODES` plus the undocumented NMOS opcodes, so that every opcode is
+/// defined. The undocumented NOPs read their operand like the
+/// instruction they replace.
+pub static NMOS_ILLEGAL: OpcodeTable = nmos_illegal();
+
+/// The 65C02 opcodes. The opcodes it does not use are NOPs, so every
+/// opcode is defined.
+pub static CMOS: OpcodeTable = cmos();
+
+/// `CMOS` plus the Rockwell bit instructions, RMB, SMB, BBR and BBS.
+pub static ROCKWELL: OpcodeTable = rockwell();
+
+/// `ROCKWELL` plus WAI and STP.
+pub static WDC: OpcodeTable = wdc();
+
+const fn nmos() -> OpcodeTable {
+    use Mnemonic::*;
+    use Mode::*;
+
+    let mut t = [None; 256];
+
+    t[0x69] = op(ADC, Imm, 2);
+    t[0x65] = op(ADC, Zpg, 3);
+    t[0x75] = op(ADC, Zpgx, 4);
+    t[0x6D] = op(ADC, Abs, 4);
+    t[0x7D] = opp(ADC, Absx, 4);
+    t[0x79] = opp(ADC, Absy, 4);
+    t[0x61] = op(ADC, Xind, 6);
+    t[0x71] = opp(ADC, Indy, 5);
+
+    t[0x29] = op(AND, Imm, 2);
+    t[0x25] = op(AND, Zpg, 3);
+    t[0x35] = op(AND, Zpgx, 4);
+    t[0x2D] = op(AND, Abs, 4);
+    t[0x3D] = opp(AND, Absx, 4);
+    t[0x39] = opp(AND, Absy, 4);
+    t[0x21] = op(AND, Xind, 6);
+    t[0x31] = opp(AND, Indy, 5);
+
+    t[0x0A] = op(ASL, Acc, 2);
+    t[0x06] = op(ASL, Zpg, 5);
+    t[0x16] = op(ASL, Zpgx, 6);
+    t[0x0E] = op(ASL, Abs, 6);
+    t[0x1E] = op(ASL, Absx, 7);
+
+    t[0x90] = op(BCC, Rel, 2);
+    t[0xB0] = op(BCS, Rel, 2);
+    t[0xF0] = op(BEQ, Rel, 2);
+    t[0x30] = op(BMI, Rel, 2);
+    t[0xD0] = op(BNE, Rel, 2);
+    t[0x10] = op(BPL, Rel, 2);
+    t[0x50] = op(BVC, Rel, 2);
+    t[0x70] = op(BVS, Rel, 2);
+
+    t[0x24] = op(BIT, Zpg, 3);
+    t[0x2C] = op(BIT, Abs, 4);
+
+    t[0x00] = op(BRK, Imp, 7);
+
+    t[0x18] = op(CLC, Imp, 2);
+    t[0xD8] = op(CLD, Imp, 2);
+    t[0x58] = op(CLI, Imp, 2);
+    t[0xB8] = op(CLV, Imp, 2);
+
+    t[0xC9] = op(CMP, Imm, 2);
+    t[0xC5] = op(CMP, Zpg, 3);
+    t[0xD5] = op(CMP, Zpgx, 4);
+    t[0xCD] = op(CMP, Abs, 4);
+    t[0xDD] = opp(CMP, Absx, 4);
+    t[0xD9] = opp(CMP, Absy, 4);
+    t[0xC1] = op(CMP, Xind, 6);
+    t[0xD1] = opp(CMP, Indy, 5);
+
+    t[0xE0] = op(CPX, Imm, 2);
+    t[0xE4] = op(CPX, Zpg, 3);
+    t[0xEC] = op(CPX, Abs, 4);
+
+    t[0xC0] = op(CPY, Imm, 2);
+    t[0xC4] = op(CPY, Zpg, 3);
+    t[0xCC] = op(CPY, Abs, 4);
+
+    t[0xC6] = op(DEC, Zpg, 5);
+    t[0xD6] = op(DEC, Zpgx, 6);
+    t[0xCE] = op(DEC, Abs, 6);
+    t[0xDE] = op(DEC, Absx, 7);
+
+    t[0xCA] = op(DEX, Imp, 2);
+    t[0x88] = op(DEY, Imp, 2);
+
+    t[0x49] = op(EOR, Imm, 2);
+    t[0x45] = op(EOR, Zpg, 3);
+    t[0x55] = op(EOR, Zpgx, 4);
+    t[0x4D] = op(EOR, Abs, 4);
+    t[0x5D] = opp(EOR, Absx, 4);
+    t[0x59] = opp(EOR, Absy, 4);
+    t[0x41] = op(EOR, Xind, 6);
+    t[0x51] = opp(EOR, Indy, 5);
+
+    t[0xE6] = op(INC, Zpg, 5);
+    t[0xF6] = op(INC, Zpgx, 6);
+    t[0xEE] = op(INC, Abs, 6);
+    t[0xFE] = op(INC, Absx, 7);
+
+    t[0xE8] = op(INX, Imp, 2);
+    t[0xC8] = op(INY, Imp, 2);
+
+    t[0x4C] = op(JMP, Abs, 3);
+    t[0x6C] = op(JMP, Ind, 5);
+
+    t[0x20] = op(JSR, Abs, 6);
+
+    t[0xA9] = op(LDA, Imm, 2);
+    t[0xA5] = op(LDA, Zpg, 3);
+    t[0xB5] = op(LDA, Zpgx, 4);
+    t[0xAD] = op(LDA, Abs, 4);
+    t[0xBD] = opp(LDA, Absx, 4);
+    t[0xB9] = opp(LDA, Absy, 4);
+    t[0xA1] = op(LDA, Xind, 6);
+    t[0xB1] = opp(LDA, Indy, 5);
+
+    t[0xA2] = op(LDX, Imm, 2);
+    t[0xA6] = op(LDX, Zpg, 3);
+    t[0xB6] = op(LDX, Zpgy, 4);
+    t[0xAE] = op(LDX, Abs, 4);
+    t[0xBE] = opp(LDX, Absy, 4);
+
+    t[0xA0] = op(LDY, Imm, 2);
+    t[0xA4] = op(LDY, Zpg, 3);
+    t[0xB4] = op(LDY, Zpgx, 4);
+    t[0xAC] = op(LDY, Abs, 4);
+    t[0xBC] = opp(LDY, Absx, 4);
+
+    t[0x4A] = op(LSR, Acc, 2);
+    t[0x46] = op(LSR, Zpg, 5);
+    t[0x56] = op(LSR, Zpgx, 6);
+    t[0x4E] = op(LSR, Abs, 6);
+    t[0x5E] = op(LSR, Absx, 7);
+
+    t[0xEA] = op(NOP, Imp, 2);
+
+    t[0x09] = op(ORA, Imm, 2);
+    t[0x05] = op(ORA, Zpg, 3);
+    t[0x15] = op(ORA, Zpgx, 4);
+    t[0x0D] = op(ORA, Abs, 4);
+    t[0x1D] = opp(ORA, Absx, 4);
+    t[0x19] = opp(ORA, Absy, 4);
+    t[0x01] = op(ORA, Xind, 6);
+    t[0x11] = opp(ORA, Indy, 5);
+
+    t[0x48] = op(PHA, Imp, 3);
+    t[0x08] = op(PHP, Imp, 3);
+    t[0x68] = op(PLA, Imp, 4);
+    t[0x28] = op(PLP, Imp, 4);
+
+    t[0x2A] = op(ROL, Acc, 2);
+    t[0x26] = op(ROL, Zpg, 5);
+    t[0x36] = op(ROL, Zpgx, 6);
+    t[0x2E] = op(ROL, Abs, 6);
+    t[0x3E] = op(ROL, Absx, 7);
+
+    t[0x6A] = op(ROR, Acc, 2);
+    t[0x66] = op(ROR, Zpg, 5);
+    t[0x76] = op(ROR, Zpgx, 6);
+    t[0x6E] = op(ROR, Abs, 6);
+    t[0x7E] = op(ROR, Absx, 7);
+
+    t[0x40] = op(RTI, Imp, 6);
+    t[0x60] = op(RTS, Imp, 6);
+
+    t[0xE9] = op(SBC, Imm, 2);
+    t[0xE5] = op(SBC, Zpg, 3);
+    t[0xF5] = op(SBC, Zpgx, 4);
+    t[0xED] = op(SBC, Abs, 4);
+    t[0xFD] = opp(SBC, Absx, 4);
+    t[0xF9] = opp(SBC, Absy, 4);
+    t[0xE1] = op(SBC, Xind, 6);
+    t[0xF1] = opp(SBC, Indy, 5);
+
+    t[0x38] = op(SEC, Imp, 2);
+    t[0xF8] = op(SED, Imp, 2);
+    t[0x78] = op(SEI, Imp, 2);
+
+    t[0x85] = op(STA, Zpg, 3);
+    t[0x95] = op(STA, Zpgx, 4);
+    t[0x8D] = op(STA, Abs, 4);
+    t[0x9D] = op(STA, Absx, 5);
+    t[0x99] = op(STA, Absy, 5);
+    t[0x81] = op(STA, Xind, 6);
+    t[0x91] = op(STA, Indy, 6);
+
+    t[0x86] = op(STX, Zpg, 3);
+    t[0x96] = op(STX, Zpgy, 4);
+    t[0x8E] = op(STX, Abs, 4);
+
+    t[0x84] = op(STY, Zpg, 3);
+    t[0x94] = op(STY, Zpgx, 4);
+    t[0x8C] = op(STY, Abs, 4);
+
+    t[0xAA] = op(TAX, Imp, 2);
+    t[0xA8] = op(TAY, Imp, 2);
+    t[0xBA] = op(TSX, Imp, 2);
+    t[0x8A] = op(TXA, Imp, 2);
+    t[0x9A] = op(TXS, Imp, 2);
+    t[0x98] = op(TYA, Imp, 2);
+
+    t[0x02] = op(JAM, Imp, 0);
+    t[0x12] = op(JAM, Imp, 0);
+    t[0x22] = op(JAM, Imp, 0);
+    t[0x32] = op(JAM, Imp, 0);
+    t[0x42] = op(JAM, Imp, 0);
+    t[0x52] = op(JAM, Imp, 0);
+    t[0x62] = op(JAM, Imp, 0);
+    t[0x72] = op(JAM, Imp, 0);
+    t[0x92] = op(JAM, Imp, 0);
+    t[0xB2] = op(JAM, Imp, 0);
+    t[0xD2] = op(JAM, Imp, 0);
+    t[0xF2] = op(JAM, Imp, 0);
+
+    t
+}
+
+const fn nmos_illegal() -> OpcodeTable {
+    use Mnemonic::*;
+    use Mode::*;
+
+    let mut t = nmos();
+
+    // Read-modify-write followed by an ALU operation
+    let rmw = [(SLO, 0x00), (RLA, 0x20), (SRE, 0x40), (RRA, 0x60), (DCP, 0xC0), (ISC, 0xE0)];
+    let mut i = 0;
+    while i < rmw.len() {
+        let (mnemonic, base) = rmw[i];
+        t[base + 0x07] = op(mnemonic, Zpg, 5);
+        t[base + 0x17] = op(mnemonic, Zpgx, 6);
+        t[base + 0x0F] = op(mnemonic, Abs, 6);
+        t[base + 0x1F] = op(mnemonic, Absx, 7);
+        t[base + 0x1B] = op(mnemonic, Absy, 7);
+        t[base + 0x03] = op(mnemonic, Xind, 8);
+        t[base + 0x13] = op(mnemonic, Indy, 8);
+        i += 1;
+    }
+
+    t[0x87] = op(SAX, Zpg, 3);
+    t[0x97] = op(SAX, Zpgy, 4);
+    t[0x8F] = op(SAX, Abs, 4);
+    t[0x83] = op(SAX, Xind, 6);
+
+    t[0xA7] = op(LAX, Zpg, 3);
+    t[0xB7] = op(LAX, Zpgy, 4);
+    t[0xAF] = op(LAX, Abs, 4);
+    t[0xBF] = opp(LAX, Absy, 4);
+    t[0xA3] = op(LAX, Xind, 6);
+    t[0xB3] = opp(LAX, Indy, 5);
+
+    t[0x0B] = op(ANC, Imm, 2);
+    t[0x2B] = op(ANC, Imm, 2);
+    t[0x4B] = op(ALR, Imm, 2);
+    t[0x6B] = op(ARR, Imm, 2);
+    t[0x8B] = op(ANE, Imm, 2);
+    t[0xAB] = op(LXA, Imm, 2);
+    t[0xCB] = op(SBX, Imm, 2);
+    t[0xEB] = op(SBC, Imm, 2);
+
+    t[0x93] = op(SHA, Indy, 6);
+    t[0x9F] = op(SHA, Absy, 5);
+    t[0x9E] = op(SHX, Absy, 5);
+    t[0x9C] = op(SHY, Absx, 5);
+    t[0x9B] = op(TAS, Absy, 5);
+    t[0xBB] = opp(LAS, Absy, 4);
+
+    t[0x1A] = op(NOP, Imp, 2);
+    t[0x3A] = op(NOP, Imp, 2);
+    t[0x5A] = op(NOP, Imp, 2);
+    t[0x7A] = op(NOP, Imp, 2);
+    t[0xDA] = op(NOP, Imp, 2);
+    t[0xFA] = op(NOP, Imp, 2);
+    t[0x80] = op(NOP, Imm, 2);
+    t[0x82] = op(NOP, Imm, 2);
+    t[0x89] = op(NOP, Imm, 2);
+    t[0xC2] = op(NOP, Imm, 2);
+    t[0xE2] = op(NOP, Imm, 2);
+    t[0x04] = op(NOP, Zpg, 3);
+    t[0x44] = op(NOP, Zpg, 3);
+    t[0x64] = op(NOP, Zpg, 3);
+    t[0x14] = op(NOP, Zpgx, 4);
+    t[0x34] = op(NOP, Zpgx, 4);
+    t[0x54] = op(NOP, Zpgx, 4);
+    t[0x74] = op(NOP, Zpgx, 4);
+    t[0xD4] = op(NOP, Zpgx, 4);
+    t[0xF4] = op(NOP, Zpgx, 4);
+    t[0x0C] = op(NOP, Abs, 4);
+    t[0x1C] = opp(NOP, Absx, 4);
+    t[0x3C] = opp(NOP, Absx, 4);
+    t[0x5C] = opp(NOP, Absx, 4);
+    t[0x7C] = opp(NOP, Absx, 4);
+    t[0xDC] = opp(NOP, Absx, 4);
+    t[0xFC] = opp(NOP, Absx, 4);
+
+    t
+}
+
+const fn cmos() -> OpcodeTable {
+    use Mnemonic::*;
+    use Mode::*;
+
+    let mut t = nmos();
+
+    t[0x80] = op(BRA, Rel, 2);
+
+    t[0xDA] = op(PHX, Imp, 3);
+    t[0x5A] = op(PHY, Imp, 3);
+    t[0xFA] = op(PLX, Imp, 4);
+    t[0x7A] = op(PLY, Imp, 4);
+
+    t[0x64] = op(STZ, Zpg, 3);
+    t[0x74] = op(STZ, Zpgx, 4);
+    t[0x9C] = op(STZ, Abs, 4);
+    t[0x9E] = op(STZ, Absx, 5);
+
+    t[0x14] = op(TRB, Zpg, 5);
+    t[0x1C] = op(TRB, Abs, 6);
+    t[0x04] = op(TSB, Zpg, 5);
+    t[0x0C] = op(TSB, Abs, 6);
+
+    t[0x1A] = op(INC, Acc, 2);
+    t[0x3A] = op(DEC, Acc, 2);
+
+    // Shifts and rotates only pay for a page cross, INC and DEC do not
+    t[0x1E] = opp(ASL, Absx, 6);
+    t[0x3E] = opp(ROL, Absx, 6);
+    t[0x5E] = opp(LSR, Absx, 6);
+    t[0x7E] = opp(ROR, Absx, 6);
+
+    t[0x89] = op(BIT, Imm, 2);
+    t[0x34] = op(BIT, Zpgx, 4);
+    t[0x3C] = opp(BIT, Absx, 4);
+
+    t[0x12] = op(ORA, Zpi, 5);
+    t[0x32] = op(AND, Zpi, 5);
+    t[0x52] = op(EOR, Zpi, 5);
+    t[0x72] = op(ADC, Zpi, 5);
+    t[0x92] = op(STA, Zpi, 5);
+    t[0xB2] = op(LDA, Zpi, 5);
+    t[0xD2] = op(CMP, Zpi, 5);
+    t[0xF2] = op(SBC, Zpi, 5);
+
+    // The page wrap is fixed at the cost of a cycle
+    t[0x6C] = op(JMP, Ind, 6);
+    t[0x7C] = op(JMP, Absxi, 6);
+
+    // NOPs that skip an operand
+    t[0x02] = op(NOP, Imm, 2);
+    t[0x22] = op(NOP, Imm, 2);
+    t[0x42] = op(NOP, Imm, 2);
+    t[0x62] = op(NOP, Imm, 2);
+    t[0x82] = op(NOP, Imm, 2);
+    t[0xC2] = op(NOP, Imm, 2);
+    t[0xE2] = op(NOP, Imm, 2);
+    t[0x44] = op(NOP, Zpg, 3);
+    t[0x54] = op(NOP, Zpgx, 4);
+    t[0xD4] = op(NOP, Zpgx, 4);
+    t[0xF4] = op(NOP, Zpgx, 4);
+    t[0xDC] = op(NOP, Abs, 4);
+    t[0xFC] = op(NOP, Abs, 4);
+    t[0x5C] = op(NOP, Abs, 8);
+
+    // Everything else is a one byte NOP that takes a single cycle
+    let mut i = 0;
+    while i < t.len() {
+        if t[i].is_none() {
+            t[i] = op(NOP, Imp, 1);
+        }
+        i += 1;
+    }
+
+    t
+}
+
+const fn rockwell() -> OpcodeTable {
+    use Mnemonic::*;
+    use Mode::*;
+
+    let mut t = cmos();
+
+    // The bit number is in bits 4 to 6 of the opcode
+    let rmb = [RMB0, RMB1, RMB2, RMB3, RMB4, RMB5, RMB6, RMB7];
+    let smb = [SMB0, SMB1, SMB2, SMB3, SMB4, SMB5, SMB6, SMB7];
+    let bbr = [BBR0, BBR1, BBR2, BBR3, BBR4, BBR5, BBR6, BBR7];
+    let bbs = [BBS0, BBS1, BBS2, BBS3, BBS4, BBS5, BBS6, BBS7];
+    let mut i = 0;
+    while i < 8 {
+        t[i << 4 | 0x07] = op(rmb[i], Zpg, 5);
+        t[i << 4 | 0x87] = op(smb[i], Zpg, 5);
+        t[i << 4 | 0x0F] = op(bbr[i], Zpr, 5);
+        t[i << 4 | 0x8F] = op(bbs[i], Zpr, 5);
+        i += 1;
+    }
+
+    t
+}
+
+const fn wdc() -> OpcodeTable {
+    use Mnemonic::*;
+    use Mode::*;
+
+    let mut t = rockwell();
+
+    t[0xCB] = op(WAI, Imp, 3);
+    t[0xDB] = op(STP, Imp, 3);
+
+    t
+}
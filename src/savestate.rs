@@ -0,0 +1,277 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/
+
+//! Snapshots of a running `CPU` that can be written to a file and loaded
+//! again later.
+//!
+//! ```
+//! use cpu::CPU;
+//! use cpu::savestate::SaveState;
+//!
+//! let mut cpu = CPU::new();
+//! cpu.load(0x0400, &[0xE8, 0xE8]); // INX, INX
+//! cpu.step().unwrap();
+//! let bytes = cpu.save_state().to_bytes();
+//!
+//! cpu.step().unwrap();
+//! assert_eq!(2, cpu.x());
+//! cpu.load_state(&SaveState::from_bytes(&bytes).unwrap()).unwrap();
+//! assert_eq!(1, cpu.x());
+//! assert_eq!(0x0401, cpu.pc());
+//! ```
+//!
+//! # Format
+//!
+//! All numbers are little endian. Version 1 is 65561 bytes long:
+//!
+//! | Offset | Size  | Contents                                          |
+//! |--------|-------|---------------------------------------------------|
+//! | 0      | 4     | `6502`                                            |
+//! | 4      | 2     | Version, 1                                        |
+//! | 6      | 1     | Variant: 0 NMOS 6502, 1 65C02, 2 Rockwell 65C02, 3 WDC 65C02, 4 Ricoh 2A03 |
+//! | 7      | 1     | Flags, see below                                  |
+//! | 8      | 1     | The opcode that jammed the CPU, if flag 5 is set  |
+//! | 9      | 1     | The ANE and LXA constant                          |
+//! | 10     | 2     | PC                                                |
+//! | 12     | 1     | A                                                 |
+//! | 13     | 1     | X                                                 |
+//! | 14     | 1     | Y                                                 |
+//! | 15     | 1     | S                                                 |
+//! | 16     | 1     | P                                                 |
+//! | 17     | 8     | Cycles                                            |
+//! | 25     | 65536 | Memory from 0x0000 to 0xFFFF                      |
+//!
+//! The flags are bit 0 for undocumented opcodes, 1 for a raised IRQ, 2 for
+//! a pending NMI, 3 after WAI, 4 after STP and 5 for a jammed CPU.
+//!
+//! With the `serde` feature `SaveState` can also be serialized with any
+//! serde format, using the field names of the struct.
+
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::opcodes::NMOS_ILLEGAL;
+use crate::{Status, Variant, CPU};
+
+/// The first bytes of every save state.
+pub const FILE_MAGIC: [u8; 4] = *b"6502";
+
+/// The version written by `SaveState::to_bytes`.
+pub const VERSION: u16 = 1;
+
+const HEADER: usize = 25;
+const MEMORY: usize = 0x10000;
+
+// Flags
+const ILLEGAL_OPCODES: u8 = 1 << 0;
+const IRQ: u8 = 1 << 1;
+const NMI: u8 = 1 << 2;
+const WAITING: u8 = 1 << 3;
+const STOPPED: u8 = 1 << 4;
+const JAMMED: u8 = 1 << 5;
+
+/// Why `SaveState::from_bytes` or `CPU::load_state` failed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SaveStateError {
+    /// The data does not start with `FILE_MAGIC`.
+    NotASaveState,
+    /// The data was written by a version this crate can not read.
+    UnsupportedVersion(u16),
+    /// The variant byte is not one of the known variants.
+    UnknownVariant(u8),
+    /// The data, or the memory of a `SaveState`, is not as long as the
+    /// format requires.
+    WrongLength { expected: usize, actual: usize },
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {}", version),
+            SaveStateError::UnknownVariant(variant) => write!(f, "unknown variant {}", variant),
+            SaveStateError::WrongLength { expected, actual } => write!(f, "save state is {} bytes, expected {}", actual, expected),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+/// Everything needed to carry on running a CPU: the registers, the
+/// interrupt lines, the cycle counter and all 64 KiB of memory.
+/// Breakpoints, watchpoints and the bus log are not part of it.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SaveState {
+    pub variant: Variant,
+    pub illegal_opcodes: bool,
+    pub magic: u8,
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    pub irq: bool,
+    pub nmi: bool,
+    pub waiting: bool,
+    pub stopped: bool,
+    pub jammed: Option<u8>,
+    pub cycles: u64,
+    /// Memory as `Bus::peek` sees it, 0x10000 bytes.
+    pub mem: Vec<u8>,
+}
+
+fn variant_byte(variant: Variant) -> u8 {
+    match variant {
+        Variant::Nmos6502 => 0,
+        Variant::Cmos65C02 => 1,
+        Variant::Rockwell65C02 => 2,
+        Variant::Wdc65C02 => 3,
+        Variant::Ricoh2A03 => 4,
+    }
+}
+
+fn variant_from_byte(b: u8) -> Option<Variant> {
+    match b {
+        0 => Some(Variant::Nmos6502),
+        1 => Some(Variant::Cmos65C02),
+        2 => Some(Variant::Rockwell65C02),
+        3 => Some(Variant::Wdc65C02),
+        4 => Some(Variant::Ricoh2A03),
+        _ => None,
+    }
+}
+
+impl SaveState {
+    /// Encode the state in the current version of the format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        for (set, flag) in [
+            (self.illegal_opcodes, ILLEGAL_OPCODES),
+            (self.irq, IRQ),
+            (self.nmi, NMI),
+            (self.waiting, WAITING),
+            (self.stopped, STOPPED),
+            (self.jammed.is_some(), JAMMED),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER + MEMORY);
+        bytes.extend_from_slice(&FILE_MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.push(variant_byte(self.variant));
+        bytes.push(flags);
+        bytes.push(self.jammed.unwrap_or(0));
+        bytes.push(self.magic);
+        bytes.extend_from_slice(&self.pc.to_le_bytes());
+        bytes.extend_from_slice(&[self.a, self.x, self.y, self.s, self.p]);
+        bytes.extend_from_slice(&self.cycles.to_le_bytes());
+        bytes.extend_from_slice(&self.mem);
+        bytes.resize(HEADER + MEMORY, 0);
+        bytes
+    }
+
+    /// Decode a state written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SaveStateError> {
+        if bytes.len() < 6 || bytes[0..4] != FILE_MAGIC {
+            return Err(SaveStateError::NotASaveState);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        if bytes.len() != HEADER + MEMORY {
+            return Err(SaveStateError::WrongLength { expected: HEADER + MEMORY, actual: bytes.len() });
+        }
+        let variant = variant_from_byte(bytes[6]).ok_or(SaveStateError::UnknownVariant(bytes[6]))?;
+        let flags = bytes[7];
+        Ok(SaveState {
+            variant,
+            illegal_opcodes: flags & ILLEGAL_OPCODES != 0,
+            magic: bytes[9],
+            pc: u16::from_le_bytes([bytes[10], bytes[11]]),
+            a: bytes[12],
+            x: bytes[13],
+            y: bytes[14],
+            s: bytes[15],
+            p: bytes[16],
+            irq: flags & IRQ != 0,
+            nmi: flags & NMI != 0,
+            waiting: flags & WAITING != 0,
+            stopped: flags & STOPPED != 0,
+            jammed: if flags & JAMMED != 0 { Some(bytes[8]) } else { None },
+            cycles: u64::from_le_bytes(bytes[17..25].try_into().unwrap()),
+            mem: bytes[HEADER..].to_vec(),
+        })
+    }
+}
+
+impl<B: Bus> CPU<B> {
+    /// Take a snapshot of the CPU and its memory. If `tick` is part way
+    /// through an instruction the snapshot is from before that
+    /// instruction, although memory it already wrote keeps the new value.
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            variant: self.variant,
+            illegal_opcodes: self.illegal_opcodes(),
+            magic: self.magic,
+            pc: self.pc,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            s: self.s,
+            p: self.p.bits(),
+            irq: self.irq,
+            nmi: self.nmi,
+            waiting: self.waiting,
+            stopped: self.stopped,
+            jammed: self.jammed,
+            // Only the cycle counter moves before the last tick
+            cycles: self.tick.as_ref().map_or(self.cycles, |tick| tick.start.cycles),
+            mem: (0..=0xFFFF).map(|address| self.mem.peek(address)).collect(),
+        }
+    }
+
+    fn illegal_opcodes(&self) -> bool {
+        std::ptr::eq(self.opcodes, &NMOS_ILLEGAL)
+    }
+
+    /// Go back to a snapshot taken with `save_state`. Memory is restored
+    /// with `Bus::poke`, so memory mapped devices see no writes. An
+    /// instruction that `tick` is part way through is abandoned, and the
+    /// history is cleared. A state without exactly 64 KiB of memory is
+    /// rejected and leaves the CPU as it was.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), SaveStateError> {
+        if state.mem.len() != MEMORY {
+            return Err(SaveStateError::WrongLength { expected: MEMORY, actual: state.mem.len() });
+        }
+        self.variant = state.variant;
+        self.opcodes = crate::opcode_table(state.variant, state.illegal_opcodes);
+        self.magic = state.magic;
+        self.pc = state.pc;
+        self.a = state.a;
+        self.x = state.x;
+        self.y = state.y;
+        self.s = state.s;
+        self.p = Status::from_bits_retain(state.p);
+        self.irq = state.irq;
+        self.nmi = state.nmi;
+        self.waiting = state.waiting;
+        self.stopped = state.stopped;
+        self.jammed = state.jammed;
+        self.cycles = state.cycles;
+        self.tick = None;
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        for (address, v) in state.mem.iter().enumerate() {
+            self.mem.poke(address as u16, *v);
+        }
+        Ok(())
+    }
+}
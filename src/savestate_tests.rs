@@ -0,0 +1,185 @@
+use super::*;
+use memory_map::MemoryMap;
+use savestate::{SaveState, SaveStateError, FILE_MAGIC, VERSION};
+
+// A CPU in the middle of something, with every part of the state set
+fn busy_cpu() -> CPU {
+    let mut cpu = CPU::builder().illegal_opcodes(true).magic(0x11).build();
+    cpu.load(0xFFFA, &[0x00, 0x05, 0x00, 0x04, 0x00, 0x06]);
+    cpu.load(0x0400, &[
+        0xA9, 0x42, // LDA #$42
+        0xA7, 0x10, // LAX $10
+        0x85, 0x20, // STA $20
+        0xE8,       // INX
+    ]);
+    cpu.load(0x0500, &[0x40]); // RTI
+    cpu.load(0x0010, &[0x99]);
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    cpu.set_p(Status::C | Status::I);
+    cpu.raise_irq();
+    cpu.trigger_nmi();
+    cpu
+}
+
+#[test]
+fn round_trips_through_bytes() {
+    let cpu = busy_cpu();
+    let state = cpu.save_state();
+    assert_eq!(0x0404, state.pc);
+    assert_eq!(0x99, state.a);
+    assert!(state.illegal_opcodes && state.irq && state.nmi);
+    assert_eq!(5, state.cycles);
+    assert_eq!(state, SaveState::from_bytes(&state.to_bytes()).unwrap());
+}
+
+#[test]
+fn load_state_restores_everything() {
+    let mut original = busy_cpu();
+    let mut restored = CPU::builder().variant(Variant::Cmos65C02).build();
+    restored.load_state(&original.save_state()).unwrap();
+    assert!(std::ptr::eq(&NMOS_ILLEGAL, restored.opcodes()));
+    assert_eq!(Variant::Nmos6502, restored.variant());
+
+    // The pending NMI is serviced first, then the program carries on
+    for _ in 0..3 {
+        assert_eq!(original.step(), restored.step());
+        assert_eq!((original.pc(), original.a(), original.x(), original.s()), (restored.pc(), restored.a(), restored.x(), restored.s()));
+        assert_eq!(original.p(), restored.p());
+        assert_eq!(original.cycles(), restored.cycles());
+    }
+    assert_eq!(0x0406, restored.pc());
+    for address in [0x0010, 0x0020, 0x01FD, 0x01FE, 0x01FF] {
+        assert_eq!(original.peek(address), restored.peek(address), "{:04X}", address);
+    }
+}
+
+#[test]
+fn load_state_restores_wai_and_jam() {
+    let mut cpu = CPU::builder().variant(Variant::Wdc65C02).build();
+    cpu.load(0x0400, &[0xCB]); // WAI
+    cpu.step().unwrap();
+    let state = cpu.save_state();
+    let mut restored = CPU::new();
+    restored.load_state(&SaveState::from_bytes(&state.to_bytes()).unwrap()).unwrap();
+    assert!(restored.is_waiting());
+    assert_eq!(Ok(StopReason::Waiting), restored.run());
+
+    let mut cpu = CPU::builder().illegal_opcodes(true).build();
+    cpu.load(0x0400, &[0x02]); // JAM
+    assert!(cpu.step().is_err());
+    let mut restored = CPU::new();
+    restored.load_state(&SaveState::from_bytes(&cpu.save_state().to_bytes()).unwrap()).unwrap();
+    assert_eq!(cpu.step(), restored.step());
+    assert!(matches!(restored.step(), Err(CPUError::Jam { opcode: 0x02, .. })));
+}
+
+#[test]
+fn save_state_during_tick_is_from_the_instruction_start() {
+    let mut cpu = CPU::new();
+    cpu.load(0x0400, &[0xEE, 0x34, 0x12]); // INC $1234
+    cpu.tick().unwrap();
+    cpu.tick().unwrap();
+    let state = cpu.save_state();
+    assert_eq!((0x0400, 0), (state.pc, state.cycles));
+
+    cpu.load_state(&state).unwrap();
+    assert_eq!(Ok(6), cpu.step());
+    assert_eq!(1, cpu.peek(0x1234));
+}
+
+// RAM that counts the writes made through the bus
+struct Device {
+    ram: Ram,
+    writes: usize,
+}
+
+impl Bus for Device {
+    fn read(&mut self, address: u16) -> u8 {
+        self.ram.read(address)
+    }
+
+    fn write(&mut self, address: u16, v: u8) {
+        self.writes += 1;
+        self.ram.write(address, v);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.ram.peek(address)
+    }
+
+    fn poke(&mut self, address: u16, v: u8) {
+        self.ram.poke(address, v);
+    }
+}
+
+#[test]
+fn load_state_pokes_memory() {
+    let mut cpu = CPU::with_bus(Device { ram: Ram::new(), writes: 0 });
+    let mut state = cpu.save_state();
+    state.mem[0x1234] = 0x42;
+    cpu.load_state(&state).unwrap();
+    assert_eq!(0x42, cpu.peek(0x1234));
+    assert_eq!(0, cpu.mem.writes);
+
+    // ROM takes the saved contents, and nothing is left to fault
+    let map = MemoryMap::builder().ram(0x0000..=0x7FFF).rom(0xF000, &[0; 0x1000]).build();
+    let mut cpu = CPU::with_bus(map);
+    let mut state = cpu.save_state();
+    state.mem[0xF000] = 0xEA;
+    cpu.load_state(&state).unwrap();
+    assert_eq!(0xEA, cpu.peek(0xF000));
+    assert_eq!(None, cpu.mem.take_fault());
+}
+
+#[test]
+fn load_state_rejects_short_memory() {
+    let mut cpu = CPU::new();
+    cpu.set_a(0x42);
+    let mut state = CPU::new().save_state();
+    state.mem.truncate(0x100);
+    assert_eq!(Err(SaveStateError::WrongLength { expected: 0x10000, actual: 0x100 }), cpu.load_state(&state));
+    assert_eq!(0x42, cpu.a());
+}
+
+#[test]
+fn format_layout() {
+    let mut cpu = CPU::builder().variant(Variant::Ricoh2A03).build();
+    cpu.set_pc(0x1234);
+    cpu.set_a(0xAA);
+    cpu.load(0xFFFF, &[0x77]);
+    let bytes = cpu.save_state().to_bytes();
+    assert_eq!(25 + 0x10000, bytes.len());
+    assert_eq!(FILE_MAGIC, bytes[0..4]);
+    assert_eq!(VERSION.to_le_bytes(), bytes[4..6]);
+    assert_eq!(4, bytes[6]);
+    assert_eq!([0x34, 0x12, 0xAA], bytes[10..13]);
+    assert_eq!(0x77, bytes[bytes.len() - 1]);
+}
+
+#[test]
+fn from_bytes_rejects_bad_data() {
+    let bytes = CPU::new().save_state().to_bytes();
+    assert_eq!(Err(SaveStateError::NotASaveState), SaveState::from_bytes(b"NES\x1a"));
+
+    let mut newer = bytes.clone();
+    newer[4] = 2;
+    assert_eq!(Err(SaveStateError::UnsupportedVersion(2)), SaveState::from_bytes(&newer));
+
+    let mut unknown = bytes.clone();
+    unknown[6] = 9;
+    assert_eq!(Err(SaveStateError::UnknownVariant(9)), SaveState::from_bytes(&unknown));
+
+    assert_eq!(
+        Err(SaveStateError::WrongLength { expected: bytes.len(), actual: 100 }),
+        SaveState::from_bytes(&bytes[..100])
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn round_trips_through_serde() {
+    let state = busy_cpu().save_state();
+    let json = serde_json::to_string(&state).unwrap();
+    assert_eq!(state, serde_json::from_str(&json).unwrap());
+}
@@ -0,0 +1,192 @@
+// Tom Harte's SingleStepTests, see
+// https://github.com/SingleStepTests/65x02
+//
+// Each opcode has a file like a9.json with thousands of cases. A case
+// gives the registers and RAM before and after one instruction, and the
+// bus activity of every cycle, which is checked against the bus log. Put
+// the nmos6502 files in tests/single_step, or point CPU_SINGLE_STEP_TESTS
+// at them, and run cargo test -- --ignored. A missing directory is a
+// failure then.
+
+use super::*;
+use bus::BusCycle;
+use serde_json::Value;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+// Cases shown for each failing opcode
+const SHOW: usize = 3;
+
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+struct Case {
+    name: String,
+    initial: State,
+    expected: State,
+    cycles: Vec<BusCycle>,
+}
+
+fn state(v: &Value) -> State {
+    let n = |key: &str| v[key].as_u64().unwrap_or_else(|| panic!("missing {}", key));
+    let ram = v["ram"].as_array().expect("missing ram");
+    State {
+        pc: n("pc") as u16,
+        s: n("s") as u8,
+        a: n("a") as u8,
+        x: n("x") as u8,
+        y: n("y") as u8,
+        p: n("p") as u8,
+        ram: ram.iter().map(|e| (e[0].as_u64().unwrap() as u16, e[1].as_u64().unwrap() as u8)).collect(),
+    }
+}
+
+fn bus_cycle(v: &Value) -> BusCycle {
+    BusCycle {
+        address: v[0].as_u64().unwrap() as u16,
+        data: v[1].as_u64().unwrap() as u8,
+        write: v[2].as_str() == Some("write"),
+    }
+}
+
+fn cases(json: &str) -> Vec<Case> {
+    let v: Value = serde_json::from_str(json).expect("bad test file");
+    v.as_array()
+        .expect("expected a list of cases")
+        .iter()
+        .map(|case| Case {
+            name: case["name"].as_str().unwrap_or("").to_string(),
+            initial: state(&case["initial"]),
+            expected: state(&case["final"]),
+            cycles: case["cycles"].as_array().expect("missing cycles").iter().map(bus_cycle).collect(),
+        })
+        .collect()
+}
+
+// B and U are not real flags, so they are not compared
+fn flags(p: u8) -> u8 {
+    p & !(Status::B | Status::U).bits()
+}
+
+// Runs one case and returns what did not match. Reuses the CPU between
+// cases and puts back the RAM it touched.
+fn run_case(cpu: &mut CPU, case: &Case) -> Vec<String> {
+    let initial = &case.initial;
+    cpu.pc = initial.pc;
+    cpu.s = initial.s;
+    cpu.a = initial.a;
+    cpu.x = initial.x;
+    cpu.y = initial.y;
+    cpu.p = Status::from_bits_retain(flags(initial.p));
+    for (address, v) in &initial.ram {
+        cpu.mem[*address] = *v;
+    }
+
+    let mut diffs = Vec::new();
+    match cpu.step() {
+        Ok(cycles) if cycles as usize != case.cycles.len() => {
+            diffs.push(format!("cycles: {} expected {}", cycles, case.cycles.len()));
+        }
+        Ok(_) => {}
+        Err(e) => diffs.push(format!("error: {}", e)),
+    }
+    let show = |c: &BusCycle| format!("{:04X} {:02X} {}", c.address, c.data, if c.write { "write" } else { "read" });
+    let bus = cpu.bus_log().iter().map(Some).chain(std::iter::repeat(None));
+    if let Some((i, (actual, expected))) = bus.zip(&case.cycles).enumerate().find(|(_, (a, e))| *a != Some(*e)) {
+        let actual = actual.map_or("nothing".to_string(), show);
+        diffs.push(format!("cycle {}: {} expected {}", i + 1, actual, show(expected)));
+    }
+
+    let expected = &case.expected;
+    let mut compare = |name: &str, actual: u16, expected: u16| {
+        if actual != expected {
+            diffs.push(format!("{}: {:02X} expected {:02X}", name, actual, expected));
+        }
+    };
+    compare("pc", cpu.pc, expected.pc);
+    compare("s", cpu.s as u16, expected.s as u16);
+    compare("a", cpu.a as u16, expected.a as u16);
+    compare("x", cpu.x as u16, expected.x as u16);
+    compare("y", cpu.y as u16, expected.y as u16);
+    compare("p", flags(cpu.p.bits()) as u16, flags(expected.p) as u16);
+    for (address, v) in &expected.ram {
+        compare(&format!("{:04X}", address), cpu.mem[*address] as u16, *v as u16);
+    }
+
+    for (address, _) in initial.ram.iter().chain(&expected.ram) {
+        cpu.mem[*address] = 0;
+    }
+    diffs
+}
+
+#[test]
+#[ignore = "needs the SingleStepTests files, see the top of this file"]
+fn single_step_tests() {
+    let dir = std::env::var_os("CPU_SINGLE_STEP_TESTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/single_step"));
+    assert!(dir.is_dir(), "{} not found", dir.display());
+
+    let mut cpu = CPU::builder().illegal_opcodes(true).bus_log(true).build();
+    let mut summary = String::new();
+    let mut failures = String::new();
+    for (opcode, op) in NMOS_ILLEGAL.iter().enumerate() {
+        let Some(op) = op.filter(|op| op.mnemonic != Mnemonic::JAM) else {
+            continue;
+        };
+        let Ok(json) = std::fs::read_to_string(dir.join(format!("{:02x}.json", opcode))) else {
+            continue;
+        };
+
+        let cases = cases(&json);
+        let mut failed = 0;
+        for case in &cases {
+            let diffs = run_case(&mut cpu, case);
+            if diffs.is_empty() {
+                continue;
+            }
+            if failed < SHOW {
+                writeln!(failures, "{:02X} {} {:?} \"{}\": {}", opcode, op.mnemonic, op.mode, case.name, diffs.join(", ")).unwrap();
+            }
+            failed += 1;
+        }
+        let passed = cases.len() - failed;
+        writeln!(summary, "{:02X} {} {:?}: {}/{} passed", opcode, op.mnemonic, op.mode, passed, cases.len()).unwrap();
+    }
+
+    eprint!("{}", summary);
+    assert!(failures.is_empty(), "\n{}", failures);
+}
+
+#[test]
+fn run_case_reports_mismatches() {
+    let json = r#"[
+        {
+            "name": "a9 42 00",
+            "initial": { "pc": 1024, "s": 253, "a": 0, "x": 0, "y": 0, "p": 38, "ram": [[1024, 169], [1025, 66]] },
+            "final": { "pc": 1026, "s": 253, "a": 66, "x": 0, "y": 0, "p": 36, "ram": [[1024, 169], [1025, 66]] },
+            "cycles": [[1024, 169, "read"], [1025, 66, "read"]]
+        },
+        {
+            "name": "a9 42 01",
+            "initial": { "pc": 1024, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[1024, 169], [1025, 66]] },
+            "final": { "pc": 1026, "s": 253, "a": 67, "x": 0, "y": 0, "p": 38, "ram": [[1024, 169], [1025, 66], [16, 1]] },
+            "cycles": [[1024, 169, "read"], [1025, 66, "write"]]
+        }
+    ]"#;
+    let cases = cases(json);
+    let mut cpu = CPU::builder().bus_log(true).build();
+    assert!(run_case(&mut cpu, &cases[0]).is_empty());
+    assert_eq!(
+        vec!["cycle 2: 0401 42 read expected 0401 42 write", "a: 42 expected 43", "p: 04 expected 06", "0010: 00 expected 01"],
+        run_case(&mut cpu, &cases[1])
+    );
+    assert_eq!(0, cpu.mem[0x0400]);
+}
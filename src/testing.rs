@@ -0,0 +1,166 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/
+
+//! Helpers for testing short programs written in assembly.
+//!
+//! ```
+//! use cpu::{asm, Status, StopReason};
+//!
+//! let run = asm!(
+//!     "ldx #1",
+//!     "dex",
+//! ).run();
+//! assert_eq!(Ok(StopReason::Breakpoint(run.end)), run.result);
+//! assert_eq!(0, run.x());
+//! assert!(run.p().contains(Status::Z));
+//! ```
+
+use std::ops::{Deref, DerefMut};
+
+use crate::asm::{assemble_with, Program};
+use crate::{CPUError, Status, StopReason, Variant, CPU, RESET_VECTOR};
+
+/// Where snippets are assembled. The reset vector points here.
+pub const ORIGIN: u16 = 0x0400;
+
+/// Instructions a snippet may run before it is stopped.
+pub const INSTRUCTION_LIMIT: u64 = 100_000;
+
+/// Build a `Snippet` from lines of assembly.
+#[macro_export]
+macro_rules! asm {
+    ($($line:expr),* $(,)?) => {
+        $crate::testing::Snippet::new(&[$($line),*].join("\n"))
+    };
+}
+
+/// A program to run from a known starting state. The source is
+/// assembled at `ORIGIN` and runs until it falls off the end of its
+/// first segment, or an error stops the CPU.
+pub struct Snippet {
+    source: String,
+    variant: Variant,
+    illegal_opcodes: bool,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: Status,
+    data: Vec<(u16, Vec<u8>)>,
+    limit: u64,
+}
+
+impl Snippet {
+    pub fn new(source: &str) -> Self {
+        Snippet {
+            source: source.to_string(),
+            variant: Variant::Nmos6502,
+            illegal_opcodes: false,
+            a: 0,
+            x: 0,
+            y: 0,
+            p: Status::empty(),
+            data: Vec::new(),
+            limit: INSTRUCTION_LIMIT,
+        }
+    }
+
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    pub fn illegal_opcodes(mut self, illegal_opcodes: bool) -> Self {
+        self.illegal_opcodes = illegal_opcodes;
+        self
+    }
+
+    pub fn a(mut self, a: u8) -> Self {
+        self.a = a;
+        self
+    }
+
+    pub fn x(mut self, x: u8) -> Self {
+        self.x = x;
+        self
+    }
+
+    pub fn y(mut self, y: u8) -> Self {
+        self.y = y;
+        self
+    }
+
+    pub fn p(mut self, p: Status) -> Self {
+        self.p = p;
+        self
+    }
+
+    /// Store `data` at `address` before the snippet runs.
+    pub fn poke(mut self, address: u16, data: &[u8]) -> Self {
+        self.data.push((address, data.to_vec()));
+        self
+    }
+
+    pub fn limit(mut self, instructions: u64) -> Self {
+        self.limit = instructions;
+        self
+    }
+
+    /// Assemble and run the snippet. Panics if it does not assemble.
+    pub fn run(self) -> Run {
+        let mut cpu = CPU::builder().variant(self.variant).illegal_opcodes(self.illegal_opcodes).build();
+        let source = format!(".org ${:04X}\n{}", ORIGIN, self.source);
+        let program = assemble_with(cpu.opcodes(), &source).unwrap_or_else(|e| panic!("{}\n{}", e, source));
+        let end = program.segments.first().map_or(ORIGIN, |segment| ORIGIN.wrapping_add(segment.bytes.len() as u16));
+
+        cpu.load(RESET_VECTOR, &ORIGIN.to_le_bytes());
+        for (address, data) in &self.data {
+            cpu.load(*address, data);
+        }
+        program.load_into(&mut cpu);
+        cpu.set_pc(ORIGIN);
+        cpu.set_a(self.a);
+        cpu.set_x(self.x);
+        cpu.set_y(self.y);
+        cpu.set_p(self.p);
+        cpu.add_breakpoint(end);
+
+        let result = cpu.run_for_instructions(self.limit);
+        Run { cpu, program, end, result }
+    }
+}
+
+/// The state after running a `Snippet`. Derefs to the `CPU`.
+pub struct Run {
+    pub cpu: CPU,
+    pub program: Program,
+    /// The address just after the snippet.
+    pub end: u16,
+    pub result: Result<StopReason, CPUError>,
+}
+
+impl Run {
+    /// True if the snippet ran to its end.
+    pub fn finished(&self) -> bool {
+        self.result == Ok(StopReason::Breakpoint(self.end))
+    }
+
+    /// The value of a symbol defined by the snippet.
+    pub fn symbol(&self, name: &str) -> u16 {
+        self.program.symbol(name).unwrap_or_else(|| panic!("no symbol {}", name))
+    }
+}
+
+impl Deref for Run {
+    type Target = CPU;
+
+    fn deref(&self) -> &CPU {
+        &self.cpu
+    }
+}
+
+impl DerefMut for Run {
+    fn deref_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+}
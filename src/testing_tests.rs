@@ -0,0 +1,46 @@
+use super::*;
+use testing::ORIGIN;
+
+#[test]
+fn starts_at_the_reset_vector() {
+    let run = asm!("nop").run();
+    assert_eq!(ORIGIN, run.peek(RESET_VECTOR) as u16 | (run.peek(RESET_VECTOR + 1) as u16) << 8);
+    assert_eq!(ORIGIN + 1, run.end);
+    assert_eq!(ORIGIN + 1, run.pc());
+    assert!(run.finished());
+}
+
+#[test]
+fn initial_state() {
+    let run = asm!("lda $10", "sta $11")
+        .poke(0x0010, &[0x99])
+        .x(1)
+        .y(2)
+        .p(Status::C)
+        .run();
+    assert_eq!(0x99, run.peek(0x0011));
+    assert_eq!(1, run.x());
+    assert_eq!(2, run.y());
+    assert_eq!(Status::C | Status::N, run.p());
+}
+
+#[test]
+fn symbols() {
+    let run = asm!("jmp skip", "value: .byte 7", "skip: lda value").run();
+    assert!(run.finished());
+    assert_eq!(ORIGIN + 3, run.symbol("value"));
+    assert_eq!(7, run.a());
+}
+
+#[test]
+fn stops_at_the_limit() {
+    let run = asm!("loop: jmp loop").limit(10).run();
+    assert_eq!(Ok(StopReason::InstructionLimit), run.result);
+    assert!(!run.finished());
+}
+
+#[test]
+fn stops_on_errors() {
+    let run = asm!("nop", ".byte $FF").run();
+    assert_eq!(Err(CPUError::IllegalInstruction { opcode: 0xFF, pc: ORIGIN + 1 }), run.result);
+}
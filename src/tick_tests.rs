@@ -0,0 +1,135 @@
+use super::*;
+
+fn load(cpu: &mut CPU, program: &[u8]) {
+    for (i, b) in program.iter().enumerate() {
+        cpu.mem[0x0400 + i as u16] = *b;
+    }
+    cpu.pc = 0x0400;
+}
+
+fn registers(cpu: &CPU) -> (u16, u8, u8, u8, u8, Status, u64) {
+    (cpu.pc, cpu.a, cpu.x, cpu.y, cpu.s, cpu.p, cpu.cycles)
+}
+
+#[test]
+fn tick_matches_step() {
+    for variant in [Variant::Nmos6502, Variant::Cmos65C02, Variant::Rockwell65C02, Variant::Wdc65C02, Variant::Ricoh2A03] {
+        let opcodes = CPU::builder().variant(variant).illegal_opcodes(true).build().opcodes();
+        for (opcode, op) in opcodes.iter().enumerate() {
+            let Some(op) = op.filter(|op| op.mnemonic != Mnemonic::JAM) else {
+                continue;
+            };
+            let name = format!("{:?} {:02X} {} {:?}", variant, opcode, op.mnemonic, op.mode);
+            for (operand, p) in [(0x10, Status::empty()), (0xF0, Status::all())] {
+                let setup = || {
+                    let mut cpu = CPU::builder().variant(variant).illegal_opcodes(true).build();
+                    cpu.x = 0x20;
+                    cpu.y = 0x20;
+                    cpu.p = p;
+                    cpu.mem[0x0010] = 0xF0;
+                    cpu.mem[0x00F0] = 0xF0;
+                    cpu.mem[0x00F1] = 0x12;
+                    load(&mut cpu, &[opcode as u8, operand, 0x12]);
+                    cpu
+                };
+                let mut stepped = setup();
+                let cycles = stepped.step().unwrap();
+
+                let mut ticked = setup();
+                for _ in 1..cycles {
+                    assert_eq!(Ok(None), ticked.tick(), "{}", name);
+                }
+                assert_eq!(Ok(Some(cycles)), ticked.tick(), "{}", name);
+                assert_eq!(registers(&stepped), registers(&ticked), "{}", name);
+                for address in [0x0010, 0x00F0, 0x0110, 0x01FD, 0x01FE, 0x01FF, 0x1210, 0x1230, 0x1300] {
+                    assert_eq!(stepped.mem[address], ticked.mem[address], "{} at {:04X}", name, address);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn registers_change_on_the_last_cycle() {
+    let mut cpu = CPU::new();
+    load(&mut cpu, &[0xA9, 0x42]); // LDA #$42
+    assert_eq!(Ok(None), cpu.tick());
+    assert_eq!(0x00, cpu.a);
+    assert_eq!(0x0400, cpu.pc);
+    assert_eq!(1, cpu.cycles());
+    assert_eq!(Ok(Some(2)), cpu.tick());
+    assert_eq!(0x42, cpu.a);
+    assert_eq!(0x0402, cpu.pc);
+    assert_eq!(2, cpu.cycles());
+}
+
+#[test]
+fn reads_happen_on_their_cycle() {
+    let mut cpu = CPU::new();
+    load(&mut cpu, &[0xAD, 0x34, 0x12]); // LDA $1234
+    cpu.mem[0x1234] = 0x11;
+    for _ in 0..3 {
+        assert_eq!(Ok(None), cpu.tick());
+    }
+    // Another device changes memory before the fourth cycle reads it
+    cpu.mem[0x1234] = 0x22;
+    assert_eq!(Ok(Some(4)), cpu.tick());
+    assert_eq!(0x22, cpu.a);
+}
+
+#[test]
+fn writes_happen_on_their_cycle() {
+    let mut cpu = CPU::new();
+    load(&mut cpu, &[0xEE, 0x34, 0x12]); // INC $1234
+    cpu.mem[0x1234] = 0x41;
+    let mut seen = Vec::new();
+    while cpu.tick() == Ok(None) {
+        seen.push(cpu.mem[0x1234]);
+    }
+    seen.push(cpu.mem[0x1234]);
+    assert_eq!(vec![0x41, 0x41, 0x41, 0x41, 0x41, 0x42], seen);
+}
+
+#[test]
+fn step_finishes_a_ticked_instruction() {
+    let mut cpu = CPU::new();
+    load(&mut cpu, &[0xA2, 0x05, 0xE8]); // LDX #$05, INX
+    assert_eq!(Ok(None), cpu.tick());
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(0x05, cpu.x);
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(0x06, cpu.x);
+    assert_eq!(4, cpu.cycles());
+}
+
+#[test]
+fn nmi_during_an_instruction_stays_pending() {
+    let mut cpu = CPU::new();
+    cpu.mem[0xFFFA] = 0x00;
+    cpu.mem[0xFFFB] = 0x05;
+    load(&mut cpu, &[0xEA]); // NOP
+    assert_eq!(Ok(None), cpu.tick());
+    cpu.trigger_nmi();
+    assert_eq!(Ok(Some(2)), cpu.tick());
+    assert_eq!(0x0401, cpu.pc);
+    assert_eq!(Ok(7), cpu.step());
+    assert_eq!(0x0500, cpu.pc);
+}
+
+#[test]
+fn tick_reports_errors_on_the_last_cycle() {
+    let mut cpu = CPU::new();
+    load(&mut cpu, &[0xFF]);
+    assert_eq!(Err(CPUError::IllegalInstruction { opcode: 0xFF, pc: 0x0400 }), cpu.tick());
+
+    let mut cpu = CPU::builder()
+        .build_with_bus(memory_map::MemoryMap::builder().ram(0x0000..=0x07FF).build());
+    cpu.load(0x0400, &[0x8D, 0x00, 0x90]); // STA $9000
+    for _ in 0..3 {
+        assert_eq!(Ok(None), cpu.tick());
+    }
+    assert_eq!(
+        Err(CPUError::UnmappedAccess { address: 0x9000, write: true, pc: 0x0400 }),
+        cpu.tick()
+    );
+}
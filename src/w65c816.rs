@@ -0,0 +1,1320 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/
+
+//! A 65C816, the 16 bit successor of the 65C02 in the SNES and the
+//! Apple IIgs.
+//!
+//! It starts in emulation mode, where the registers are 8 bits and the
+//! stack is in page one like on the 6502. XCE with C clear switches to
+//! native mode, where REP and SEP clear and set the M and X flags to pick
+//! 8 or 16 bit accumulator and index registers. Addresses are 24 bits:
+//! code runs in the program bank, absolute data accesses go to the data
+//! bank, and the direct page register moves the zero page anywhere in
+//! bank 0.
+//!
+//! Memory is any `bus::Bus`. `bus::LongRam` covers all 16 MiB, a bus
+//! that does not implement the long accesses sees every bank as bank 0.
+//!
+//! ```
+//! use cpu::w65c816::W65C816;
+//!
+//! let mut cpu = W65C816::new();
+//! cpu.load(0x000400, &[
+//!     0x18,                   // CLC
+//!     0xFB,                   // XCE
+//!     0xC2, 0x20,             // REP #$20
+//!     0xA9, 0x34, 0x12,       // LDA #$1234
+//!     0x8F, 0x00, 0x00, 0x7E, // STA $7E0000
+//! ]);
+//! for _ in 0..5 {
+//!     cpu.step().unwrap();
+//! }
+//! assert!(!cpu.e());
+//! assert_eq!(0x1234, cpu.c());
+//! assert_eq!(0x34, cpu.peek(0x7E0000));
+//! ```
+//!
+//! There is no tick, bus log or disassembler for this core yet.
+
+use std::collections::HashSet;
+
+use crate::bus::{Bus, LongRam};
+use crate::{CPUError, Status, StopReason, IRQ_VECTOR, NMI_VECTOR, RESET_VECTOR};
+
+// The 65C816 uses the two bits the 6502 leaves to U and B. In emulation
+// mode M and X are always set, and X is the B flag of the 6502 when the
+// status is pushed.
+impl Status {
+    /// An 8 bit accumulator and memory.
+    pub const M: Status = Status::U;
+    /// 8 bit index registers.
+    pub const X: Status = Status::B;
+}
+
+// Native mode vectors. Emulation mode uses the 6502 ones, plus its own
+// COP vector.
+pub const COP_VECTOR: u16 = 0xFFE4;
+pub const BRK_VECTOR: u16 = 0xFFE6;
+pub const NATIVE_NMI_VECTOR: u16 = 0xFFEA;
+pub const NATIVE_IRQ_VECTOR: u16 = 0xFFEE;
+pub const EMULATION_COP_VECTOR: u16 = 0xFFF4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Imm,
+    Dp,
+    Dpx,
+    Dpy,
+    // (dp), [dp], (dp,X), (dp),Y and [dp],Y
+    Dpi,
+    Dpil,
+    Dpxi,
+    Dpiy,
+    Dpily,
+    Abs,
+    Absx,
+    Absy,
+    Long,
+    Longx,
+    // sr,S and (sr,S),Y
+    Sr,
+    Sriy,
+}
+
+// ORA, AND, EOR, ADC, STA, LDA, CMP and SBC are in bits 5 to 7 of the
+// opcode, and share these modes selected by the low five bits
+fn group_mode(opcode: u8) -> Option<Mode> {
+    match opcode & 0x1F {
+        0x01 => Some(Mode::Dpxi),
+        0x03 => Some(Mode::Sr),
+        0x05 => Some(Mode::Dp),
+        0x07 => Some(Mode::Dpil),
+        0x09 => Some(Mode::Imm),
+        0x0D => Some(Mode::Abs),
+        0x0F => Some(Mode::Long),
+        0x11 => Some(Mode::Dpiy),
+        0x12 => Some(Mode::Dpi),
+        0x13 => Some(Mode::Sriy),
+        0x15 => Some(Mode::Dpx),
+        0x17 => Some(Mode::Dpily),
+        0x19 => Some(Mode::Absy),
+        0x1D => Some(Mode::Absx),
+        0x1F => Some(Mode::Longx),
+        _ => None,
+    }
+}
+
+// The address of the high byte of 16 bit data at `address`. Direct page
+// and stack relative data stays in bank 0 and wraps around at its end.
+fn next_address(mode: Mode, address: u32) -> u32 {
+    match mode {
+        Mode::Dp | Mode::Dpx | Mode::Dpy | Mode::Sr => (address as u16).wrapping_add(1) as u32,
+        _ => address.wrapping_add(1) & 0xFFFFFF,
+    }
+}
+
+// The mask and sign bit of an 8 or 16 bit value
+fn width(wide: bool) -> (u16, u16) {
+    if wide {
+        (0xFFFF, 0x8000)
+    } else {
+        (0x00FF, 0x0080)
+    }
+}
+
+type ReadOp<B> = fn(&mut W65C816<B>, u16);
+type ModOp<B> = fn(&mut W65C816<B>, u16) -> u16;
+
+/// A 65C816 attached to the memory `B`. The registers are laid out like
+/// those of `CPU`, widened to 16 bits, plus the direct page and bank
+/// registers and the emulation flag.
+pub struct W65C816<B: Bus = LongRam> {
+    // The accumulator is C, with A in the low byte and B in the high byte
+    c: u16,
+    x: u16,
+    y: u16,
+    s: u16,
+    d: u16,
+    pc: u16,
+    pbr: u8,
+    dbr: u8,
+    p: Status,
+    e: bool,
+    mem: B,
+    irq: bool,
+    nmi: bool,
+    waiting: bool,
+    stopped: bool,
+    cycles: u64,
+    breakpoints: HashSet<u32>,
+    // The breakpoint the last run stopped at, until the next instruction
+    resume_breakpoint: Option<u32>,
+    instruction_pc: u16,
+}
+
+impl W65C816<LongRam> {
+    /// Create a 65C816 with 16 MiB of RAM, in emulation mode and starting
+    /// at 0x0400 in bank 0.
+    pub fn new() -> Self {
+        W65C816::with_bus(LongRam::new())
+    }
+}
+
+impl Default for W65C816<LongRam> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: Bus> W65C816<B> {
+    /// Create a 65C816 attached to `mem`, in emulation mode and starting
+    /// at 0x0400 in bank 0.
+    pub fn with_bus(mem: B) -> Self {
+        W65C816 {
+            c: 0,
+            x: 0,
+            y: 0,
+            s: 0x01FF,
+            d: 0,
+            pc: 0x0400,
+            pbr: 0,
+            dbr: 0,
+            p: Status::M | Status::X,
+            e: true,
+            mem,
+            irq: false,
+            nmi: false,
+            waiting: false,
+            stopped: false,
+            cycles: 0,
+            breakpoints: HashSet::new(),
+            resume_breakpoint: None,
+            instruction_pc: 0x0400,
+        }
+    }
+
+    // Registers
+
+    pub fn a(&self) -> u8 {
+        self.c as u8
+    }
+
+    pub fn b(&self) -> u8 {
+        (self.c >> 8) as u8
+    }
+
+    /// The whole 16 bit accumulator, B and A.
+    pub fn c(&self) -> u16 {
+        self.c
+    }
+
+    pub fn set_c(&mut self, c: u16) {
+        self.c = c;
+    }
+
+    pub fn x(&self) -> u16 {
+        self.x
+    }
+
+    /// With 8 bit index registers the high byte is dropped.
+    pub fn set_x(&mut self, x: u16) {
+        self.x = x;
+        self.fix_mode();
+    }
+
+    pub fn y(&self) -> u16 {
+        self.y
+    }
+
+    /// With 8 bit index registers the high byte is dropped.
+    pub fn set_y(&mut self, y: u16) {
+        self.y = y;
+        self.fix_mode();
+    }
+
+    /// The stack pointer. In emulation mode it stays in page one.
+    pub fn s(&self) -> u16 {
+        self.s
+    }
+
+    pub fn set_s(&mut self, s: u16) {
+        self.s = s;
+        self.fix_mode();
+    }
+
+    /// The direct page register, where direct page addresses start.
+    pub fn d(&self) -> u16 {
+        self.d
+    }
+
+    pub fn set_d(&mut self, d: u16) {
+        self.d = d;
+    }
+
+    /// The data bank register, the bank of absolute data addresses.
+    pub fn dbr(&self) -> u8 {
+        self.dbr
+    }
+
+    pub fn set_dbr(&mut self, dbr: u8) {
+        self.dbr = dbr;
+    }
+
+    /// The program bank register, the bank `pc` is in.
+    pub fn pbr(&self) -> u8 {
+        self.pbr
+    }
+
+    pub fn set_pbr(&mut self, pbr: u8) {
+        self.pbr = pbr;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn p(&self) -> Status {
+        self.p
+    }
+
+    /// In emulation mode M and X stay set.
+    pub fn set_p(&mut self, p: Status) {
+        self.p = p;
+        self.fix_mode();
+    }
+
+    /// True in emulation mode.
+    pub fn e(&self) -> bool {
+        self.e
+    }
+
+    // Emulation mode keeps the registers 8 bits and the stack in page
+    // one, and 8 bit index registers lose their high byte
+    fn fix_mode(&mut self) {
+        if self.e {
+            self.p.insert(Status::M | Status::X);
+            self.s = 0x0100 | self.s & 0x00FF;
+        }
+        if self.p.contains(Status::X) {
+            self.x &= 0x00FF;
+            self.y &= 0x00FF;
+        }
+    }
+
+    // Memory
+
+    pub fn mem(&self) -> &B {
+        &self.mem
+    }
+
+    pub fn mem_mut(&mut self) -> &mut B {
+        &mut self.mem
+    }
+
+    /// Read memory without side effects.
+    pub fn peek(&self, address: u32) -> u8 {
+        self.mem.peek_long(address & 0xFFFFFF)
+    }
+
+    /// Write `data` to memory starting at the 24 bit `address` with
+    /// `Bus::poke_long`, so ROM can be loaded and nothing faults.
+    pub fn load(&mut self, address: u32, data: &[u8]) {
+        for (i, b) in data.iter().enumerate() {
+            self.mem.poke_long(address.wrapping_add(i as u32) & 0xFFFFFF, *b);
+        }
+    }
+
+    // Every bus access takes a cycle
+    fn read(&mut self, address: u32) -> u8 {
+        self.cycles += 1;
+        self.mem.read_long(address & 0xFFFFFF)
+    }
+
+    fn write(&mut self, address: u32, v: u8) {
+        self.cycles += 1;
+        self.mem.write_long(address & 0xFFFFFF, v);
+    }
+
+    // A cycle spent inside the CPU
+    fn io(&mut self) {
+        self.cycles += 1;
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let b = self.read((self.pbr as u32) << 16 | self.pc as u32);
+        self.pc = self.pc.wrapping_add(1);
+        b
+    }
+
+    fn fetch_word(&mut self) -> u16 {
+        self.fetch() as u16 | (self.fetch() as u16) << 8
+    }
+
+    fn fetch_long(&mut self) -> u32 {
+        self.fetch_word() as u32 | (self.fetch() as u32) << 16
+    }
+
+    fn read_word(&mut self, address: u32) -> u16 {
+        self.read(address) as u16 | (self.read(address.wrapping_add(1)) as u16) << 8
+    }
+
+    // Pointers in bank 0 wrap around at the end of the bank
+    fn read_pointer(&mut self, address: u16) -> u16 {
+        self.read(address as u32) as u16 | (self.read(address.wrapping_add(1) as u32) as u16) << 8
+    }
+
+    fn read_long_pointer(&mut self, address: u16) -> u32 {
+        self.read_pointer(address) as u32 | (self.read(address.wrapping_add(2) as u32) as u32) << 16
+    }
+
+    // Data is 16 bits wide when `wide`, low byte first
+    fn read_data(&mut self, mode: Mode, address: u32, wide: bool) -> u16 {
+        let low = self.read(address) as u16;
+        if wide {
+            (self.read(next_address(mode, address)) as u16) << 8 | low
+        } else {
+            low
+        }
+    }
+
+    fn write_data(&mut self, mode: Mode, address: u32, v: u16, wide: bool) {
+        self.write(address, v as u8);
+        if wide {
+            self.write(next_address(mode, address), (v >> 8) as u8);
+        }
+    }
+
+    fn push(&mut self, v: u8) {
+        self.write(self.s as u32, v);
+        self.s = self.s.wrapping_sub(1);
+        if self.e {
+            self.s = 0x0100 | self.s & 0x00FF;
+        }
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.s = self.s.wrapping_add(1);
+        if self.e {
+            self.s = 0x0100 | self.s & 0x00FF;
+        }
+        self.read(self.s as u32)
+    }
+
+    fn push_word(&mut self, v: u16) {
+        self.push((v >> 8) as u8);
+        self.push(v as u8);
+    }
+
+    fn pull_word(&mut self) -> u16 {
+        self.pull() as u16 | (self.pull() as u16) << 8
+    }
+
+    fn push_data(&mut self, v: u16, wide: bool) {
+        if wide {
+            self.push((v >> 8) as u8);
+        }
+        self.push(v as u8);
+    }
+
+    fn pull_data(&mut self, wide: bool) -> u16 {
+        let low = self.pull() as u16;
+        if wide {
+            (self.pull() as u16) << 8 | low
+        } else {
+            low
+        }
+    }
+
+    // Register widths
+
+    fn wide_acc(&self) -> bool {
+        !self.p.contains(Status::M)
+    }
+
+    fn wide_index(&self) -> bool {
+        !self.p.contains(Status::X)
+    }
+
+    fn acc(&self) -> u16 {
+        if self.wide_acc() {
+            self.c
+        } else {
+            self.c & 0x00FF
+        }
+    }
+
+    // With an 8 bit accumulator B is left alone
+    fn set_acc(&mut self, v: u16) {
+        if self.wide_acc() {
+            self.c = v;
+        } else {
+            self.c = self.c & 0xFF00 | v & 0x00FF;
+        }
+        self.update_nz(v, self.wide_acc());
+    }
+
+    fn index(&self, v: u16) -> u16 {
+        if self.wide_index() {
+            v
+        } else {
+            v & 0x00FF
+        }
+    }
+
+    fn update_nz(&mut self, v: u16, wide: bool) {
+        let (mask, sign) = width(wide);
+        self.p.set(Status::Z, v & mask == 0);
+        self.p.set(Status::N, v & sign != 0);
+    }
+
+    // Addressing
+
+    // Direct page addresses cost a cycle when the direct page does not
+    // start on a page boundary
+    fn direct(&mut self, offset: u8) -> u16 {
+        if self.d & 0x00FF != 0 {
+            self.io();
+        }
+        self.d.wrapping_add(offset as u16)
+    }
+
+    // In emulation mode with the direct page on a page boundary, indexing
+    // wraps around in the page like on the 6502
+    fn direct_indexed(&mut self, offset: u8, index: u16) -> u16 {
+        let address = self.direct(offset);
+        self.io();
+        if self.e && self.d & 0x00FF == 0 {
+            self.d | offset.wrapping_add(index as u8) as u16
+        } else {
+            address.wrapping_add(index)
+        }
+    }
+
+    // The same goes for the high byte of a (dp), (dp,X) or (dp),Y pointer
+    fn direct_pointer(&mut self, address: u16) -> u16 {
+        if self.e && self.d & 0x00FF == 0 {
+            let high = address & 0xFF00 | (address as u8).wrapping_add(1) as u16;
+            self.read(address as u32) as u16 | (self.read(high as u32) as u16) << 8
+        } else {
+            self.read_pointer(address)
+        }
+    }
+
+    // Indexing costs a cycle when it crosses a page, when the index
+    // registers are 16 bits and always for writes
+    fn indexed(&mut self, base: u32, index: u16, write: bool) -> u32 {
+        let address = base.wrapping_add(index as u32) & 0xFFFFFF;
+        if write || self.wide_index() || base & 0xFFFF00 != address & 0xFFFF00 {
+            self.io();
+        }
+        address
+    }
+
+    // Fetches the operand and returns the 24 bit address it points to
+    fn address(&mut self, mode: Mode, write: bool) -> u32 {
+        let bank = (self.dbr as u32) << 16;
+        match mode {
+            Mode::Dp => {
+                let offset = self.fetch();
+                self.direct(offset) as u32
+            }
+            Mode::Dpx => {
+                let offset = self.fetch();
+                self.direct_indexed(offset, self.x) as u32
+            }
+            Mode::Dpy => {
+                let offset = self.fetch();
+                self.direct_indexed(offset, self.y) as u32
+            }
+            Mode::Dpi => {
+                let offset = self.fetch();
+                let pointer = self.direct(offset);
+                bank | self.direct_pointer(pointer) as u32
+            }
+            Mode::Dpil => {
+                let offset = self.fetch();
+                let pointer = self.direct(offset);
+                self.read_long_pointer(pointer)
+            }
+            Mode::Dpxi => {
+                let offset = self.fetch();
+                let pointer = self.direct_indexed(offset, self.x);
+                bank | self.direct_pointer(pointer) as u32
+            }
+            Mode::Dpiy => {
+                let offset = self.fetch();
+                let pointer = self.direct(offset);
+                let base = bank | self.direct_pointer(pointer) as u32;
+                self.indexed(base, self.y, write)
+            }
+            Mode::Dpily => {
+                let offset = self.fetch();
+                let pointer = self.direct(offset);
+                self.read_long_pointer(pointer).wrapping_add(self.y as u32) & 0xFFFFFF
+            }
+            Mode::Abs => bank | self.fetch_word() as u32,
+            Mode::Absx => {
+                let base = bank | self.fetch_word() as u32;
+                self.indexed(base, self.x, write)
+            }
+            Mode::Absy => {
+                let base = bank | self.fetch_word() as u32;
+                self.indexed(base, self.y, write)
+            }
+            Mode::Long => self.fetch_long(),
+            Mode::Longx => self.fetch_long().wrapping_add(self.x as u32) & 0xFFFFFF,
+            Mode::Sr => {
+                let offset = self.fetch();
+                self.io();
+                self.s.wrapping_add(offset as u16) as u32
+            }
+            Mode::Sriy => {
+                let offset = self.fetch();
+                self.io();
+                let pointer = self.s.wrapping_add(offset as u16);
+                let base = bank | self.read_pointer(pointer) as u32;
+                self.io();
+                base.wrapping_add(self.y as u32) & 0xFFFFFF
+            }
+            Mode::Imm => unreachable!("immediate operands have no address"),
+        }
+    }
+
+    fn operand(&mut self, mode: Mode, wide: bool) -> u16 {
+        if mode == Mode::Imm {
+            let low = self.fetch() as u16;
+            return if wide { (self.fetch() as u16) << 8 | low } else { low };
+        }
+        let address = self.address(mode, false);
+        self.read_data(mode, address, wide)
+    }
+
+    fn read_acc(&mut self, mode: Mode, op: ReadOp<B>) {
+        let v = self.operand(mode, self.wide_acc());
+        op(self, v);
+    }
+
+    fn read_index(&mut self, mode: Mode, op: ReadOp<B>) {
+        let v = self.operand(mode, self.wide_index());
+        op(self, v);
+    }
+
+    fn store(&mut self, mode: Mode, v: u16, wide: bool) {
+        let address = self.address(mode, true);
+        self.write_data(mode, address, v, wide);
+    }
+
+    // Read-modify-write instructions spend a cycle on the modify
+    fn modify(&mut self, mode: Mode, op: ModOp<B>) {
+        let wide = self.wide_acc();
+        let address = self.address(mode, true);
+        let v = self.read_data(mode, address, wide);
+        self.io();
+        let r = op(self, v);
+        self.write_data(mode, address, r, wide);
+    }
+
+    fn modify_acc(&mut self, op: ModOp<B>) {
+        self.io();
+        let r = op(self, self.acc());
+        self.set_acc(r);
+    }
+
+    // Accumulator Operations
+
+    fn ora(&mut self, v: u16) {
+        self.set_acc(self.acc() | v);
+    }
+
+    fn and(&mut self, v: u16) {
+        self.set_acc(self.acc() & v);
+    }
+
+    fn eor(&mut self, v: u16) {
+        self.set_acc(self.acc() ^ v);
+    }
+
+    fn lda(&mut self, v: u16) {
+        self.set_acc(v);
+    }
+
+    fn adc(&mut self, v: u16) {
+        self.add(v, false);
+    }
+
+    fn sbc(&mut self, v: u16) {
+        self.add(v, true);
+    }
+
+    // SBC adds the inverted operand. In decimal mode each digit is
+    // adjusted as it is added, and V comes from the result before the top
+    // digit is adjusted.
+    fn add(&mut self, v: u16, subtract: bool) {
+        let wide = self.wide_acc();
+        let (mask, sign) = width(wide);
+        let a = self.acc() as i32;
+        let v = (if subtract { !v } else { v } & mask) as i32;
+        let mut carry = self.p.contains(Status::C) as i32;
+        let mut r = 0;
+        let mut unadjusted = 0;
+        if self.p.contains(Status::D) {
+            for shift in (0..if wide { 16 } else { 8 }).step_by(4) {
+                let mut digit = (a >> shift & 0x0F) + (v >> shift & 0x0F) + carry;
+                unadjusted = r | digit << shift;
+                if subtract && digit <= 0x0F {
+                    digit -= 0x06;
+                } else if !subtract && digit > 0x09 {
+                    digit += 0x06;
+                }
+                carry = (digit > 0x0F) as i32;
+                r |= (digit & 0x0F) << shift;
+            }
+        } else {
+            unadjusted = a + v + carry;
+            carry = (unadjusted > mask as i32) as i32;
+            r = unadjusted & mask as i32;
+        }
+        self.p.set(Status::V, !(a ^ v) & (a ^ unadjusted) & sign as i32 != 0);
+        self.p.set(Status::C, carry != 0);
+        self.set_acc(r as u16);
+    }
+
+    fn compare(&mut self, register: u16, v: u16, wide: bool) {
+        let (mask, _) = width(wide);
+        let (register, v) = (register & mask, v & mask);
+        self.p.set(Status::C, register >= v);
+        self.update_nz(register.wrapping_sub(v), wide);
+    }
+
+    fn cmp(&mut self, v: u16) {
+        self.compare(self.acc(), v, self.wide_acc());
+    }
+
+    fn cpx(&mut self, v: u16) {
+        self.compare(self.x, v, self.wide_index());
+    }
+
+    fn cpy(&mut self, v: u16) {
+        self.compare(self.y, v, self.wide_index());
+    }
+
+    fn bit(&mut self, v: u16) {
+        let (_, sign) = width(self.wide_acc());
+        self.p.set(Status::N, v & sign != 0);
+        self.p.set(Status::V, v & sign >> 1 != 0);
+        self.bit_imm(v);
+    }
+
+    // BIT #imm only changes Z
+    fn bit_imm(&mut self, v: u16) {
+        self.p.set(Status::Z, self.acc() & v == 0);
+    }
+
+    fn ldx(&mut self, v: u16) {
+        self.x = v;
+        self.update_nz(v, self.wide_index());
+    }
+
+    fn ldy(&mut self, v: u16) {
+        self.y = v;
+        self.update_nz(v, self.wide_index());
+    }
+
+    // Memory Operations
+
+    fn asl(&mut self, v: u16) -> u16 {
+        let (mask, sign) = width(self.wide_acc());
+        self.p.set(Status::C, v & sign != 0);
+        let r = v << 1 & mask;
+        self.update_nz(r, self.wide_acc());
+        r
+    }
+
+    fn lsr(&mut self, v: u16) -> u16 {
+        self.p.set(Status::C, v & 0x0001 != 0);
+        let r = v >> 1;
+        self.update_nz(r, self.wide_acc());
+        r
+    }
+
+    fn rol(&mut self, v: u16) -> u16 {
+        let (mask, sign) = width(self.wide_acc());
+        let carry = self.p.contains(Status::C) as u16;
+        self.p.set(Status::C, v & sign != 0);
+        let r = (v << 1 | carry) & mask;
+        self.update_nz(r, self.wide_acc());
+        r
+    }
+
+    fn ror(&mut self, v: u16) -> u16 {
+        let (_, sign) = width(self.wide_acc());
+        let carry = if self.p.contains(Status::C) { sign } else { 0 };
+        self.p.set(Status::C, v & 0x0001 != 0);
+        let r = v >> 1 | carry;
+        self.update_nz(r, self.wide_acc());
+        r
+    }
+
+    fn inc(&mut self, v: u16) -> u16 {
+        let (mask, _) = width(self.wide_acc());
+        let r = v.wrapping_add(1) & mask;
+        self.update_nz(r, self.wide_acc());
+        r
+    }
+
+    fn dec(&mut self, v: u16) -> u16 {
+        let (mask, _) = width(self.wide_acc());
+        let r = v.wrapping_sub(1) & mask;
+        self.update_nz(r, self.wide_acc());
+        r
+    }
+
+    fn trb(&mut self, v: u16) -> u16 {
+        self.p.set(Status::Z, self.acc() & v == 0);
+        v & !self.acc()
+    }
+
+    fn tsb(&mut self, v: u16) -> u16 {
+        self.p.set(Status::Z, self.acc() & v == 0);
+        v | self.acc()
+    }
+
+    // Flow
+
+    // A taken branch costs a cycle, and another one in emulation mode
+    // when it crosses a page
+    fn branch(&mut self, taken: bool) {
+        let offset = self.fetch() as i8 as u16;
+        if taken {
+            let target = self.pc.wrapping_add(offset);
+            self.io();
+            if self.e && target & 0xFF00 != self.pc & 0xFF00 {
+                self.io();
+            }
+            self.pc = target;
+        }
+    }
+
+    // Block moves copy one byte per step and run again until C wraps
+    // around to 0xFFFF
+    fn block_move(&mut self, step: u16) {
+        let destination = self.fetch();
+        let source = self.fetch();
+        self.dbr = destination;
+        let v = self.read((source as u32) << 16 | self.x as u32);
+        self.write((destination as u32) << 16 | self.y as u32, v);
+        self.io();
+        self.io();
+        self.x = self.index(self.x.wrapping_add(step));
+        self.y = self.index(self.y.wrapping_add(step));
+        self.c = self.c.wrapping_sub(1);
+        if self.c != 0xFFFF {
+            self.pc = self.pc.wrapping_sub(3);
+        }
+    }
+
+    // Interrupts
+
+    /// Assert the IRQ line. It stays asserted until `clear_irq` is called
+    /// and is serviced before every instruction while `Status::I` is clear.
+    pub fn raise_irq(&mut self) {
+        self.irq = true;
+    }
+
+    pub fn clear_irq(&mut self) {
+        self.irq = false;
+    }
+
+    /// Signal an NMI edge. The NMI is serviced once, before the next
+    /// instruction, regardless of `Status::I`.
+    pub fn trigger_nmi(&mut self) {
+        self.nmi = true;
+    }
+
+    /// Perform the reset sequence. The CPU goes back to emulation mode
+    /// with the direct page and both banks at 0, and continues at the
+    /// address in the reset vector.
+    pub fn reset(&mut self) {
+        self.e = true;
+        self.d = 0;
+        self.dbr = 0;
+        self.pbr = 0;
+        self.s = self.s.wrapping_sub(3);
+        self.p.insert(Status::I);
+        self.p.remove(Status::D);
+        self.fix_mode();
+        self.nmi = false;
+        self.waiting = false;
+        self.stopped = false;
+        self.pc = self.read_word(RESET_VECTOR as u32);
+        self.cycles += 5;
+    }
+
+    // Native mode also pushes the program bank. In emulation mode the
+    // pushed X is the B flag of the 6502. Interrupts always leave decimal
+    // mode and run in bank 0.
+    fn interrupt(&mut self, native: u16, emulation: u16, brk: bool) {
+        if !self.e {
+            self.push(self.pbr);
+        }
+        self.push_word(self.pc);
+        let mut p = self.p;
+        if self.e {
+            p.set(Status::X, brk);
+        }
+        self.push(p.bits());
+        self.p.insert(Status::I);
+        self.p.remove(Status::D);
+        self.pbr = 0;
+        let vector = if self.e { emulation } else { native };
+        self.pc = self.read_word(vector as u32);
+    }
+
+    /// True after WAI, until an interrupt is raised.
+    pub fn is_waiting(&self) -> bool {
+        self.waiting
+    }
+
+    /// True after STP, until a reset.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Total number of cycles executed since the CPU was created.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Breakpoints
+
+    /// Stop the `run` functions before executing the instruction at the
+    /// 24 bit `address`. `StopReason::Breakpoint` holds the pc, `pbr` has
+    /// the bank. Resuming works like `CPU::add_breakpoint`.
+    pub fn add_breakpoint(&mut self, address: u32) {
+        self.breakpoints.insert(address & 0xFFFFFF);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u32) {
+        self.breakpoints.remove(&(address & 0xFFFFFF));
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Call step until it fails or hits a breakpoint.
+    pub fn run(&mut self) -> Result<StopReason, CPUError> {
+        self.run_with(|_, _, _| None)
+    }
+
+    /// Call step until `pred` returns true, a breakpoint is hit or it
+    /// fails. `pred` is checked before every instruction, including the
+    /// first one.
+    pub fn run_until<F: FnMut(&Self) -> bool>(&mut self, mut pred: F) -> Result<StopReason, CPUError> {
+        self.run_with(|cpu, _, _| if pred(cpu) { Some(StopReason::Condition) } else { None })
+    }
+
+    /// Run for at least `n` cycles. Instructions are never split, so this
+    /// can overshoot by a few cycles.
+    pub fn run_for_cycles(&mut self, n: u64) -> Result<StopReason, CPUError> {
+        self.run_with(|_, _, cycles| if cycles >= n { Some(StopReason::CycleLimit) } else { None })
+    }
+
+    pub fn run_for_instructions(&mut self, n: u64) -> Result<StopReason, CPUError> {
+        self.run_with(|_, instructions, _| if instructions >= n { Some(StopReason::InstructionLimit) } else { None })
+    }
+
+    // The same loop as CPU::run_with
+    fn run_with<F>(&mut self, mut done: F) -> Result<StopReason, CPUError>
+    where
+        F: FnMut(&Self, u64, u64) -> Option<StopReason>,
+    {
+        let start = self.cycles;
+        let mut instructions = 0;
+        loop {
+            if let Some(reason) = done(self, instructions, self.cycles - start) {
+                return Ok(reason);
+            }
+            if self.stopped {
+                return Ok(StopReason::Stopped);
+            }
+            if self.waiting && !self.nmi && !self.irq {
+                return Ok(StopReason::Waiting);
+            }
+            let address = (self.pbr as u32) << 16 | self.pc as u32;
+            if self.breakpoints.contains(&address) && self.resume_breakpoint != Some(address) {
+                self.resume_breakpoint = Some(address);
+                return Ok(StopReason::Breakpoint(self.pc));
+            }
+            self.step()?;
+            instructions += 1;
+        }
+    }
+
+    /// Step one instruction, or service a pending interrupt, and return
+    /// the number of cycles that took. Every opcode is defined, so this
+    /// only fails when the bus reports a fault. After WAI this idles for
+    /// one cycle until an interrupt is raised, after STP it does nothing
+    /// and returns 0.
+    pub fn step(&mut self) -> Result<u8, CPUError> {
+        self.instruction_pc = self.pc;
+        self.resume_breakpoint = None;
+        let start = self.cycles;
+        self.execute();
+        if let Some(fault) = self.mem.take_fault() {
+            return Err(CPUError::from_bus_fault(fault, self.instruction_pc));
+        }
+        Ok((self.cycles - start) as u8)
+    }
+
+    fn execute(&mut self) {
+        if self.stopped {
+            return;
+        }
+        if self.waiting {
+            if !self.nmi && !self.irq {
+                self.io();
+                return;
+            }
+            self.waiting = false;
+        }
+
+        if self.nmi {
+            self.nmi = false;
+            self.io();
+            self.io();
+            self.interrupt(NATIVE_NMI_VECTOR, NMI_VECTOR, false);
+            return;
+        }
+
+        if self.irq && !self.p.contains(Status::I) {
+            self.io();
+            self.io();
+            self.interrupt(NATIVE_IRQ_VECTOR, IRQ_VECTOR, false);
+            return;
+        }
+
+        let opcode = self.fetch();
+
+        if opcode != 0x89 {
+            if let Some(mode) = group_mode(opcode) {
+                match opcode >> 5 {
+                    0 => self.read_acc(mode, Self::ora),
+                    1 => self.read_acc(mode, Self::and),
+                    2 => self.read_acc(mode, Self::eor),
+                    3 => self.read_acc(mode, Self::adc),
+                    4 => self.store(mode, self.c, self.wide_acc()),
+                    5 => self.read_acc(mode, Self::lda),
+                    6 => self.read_acc(mode, Self::cmp),
+                    _ => self.read_acc(mode, Self::sbc),
+                }
+                return;
+            }
+        }
+
+        let wide_acc = self.wide_acc();
+        let wide_index = self.wide_index();
+        match opcode {
+            // Loads, stores and compares of the index registers
+            0xA2 => self.read_index(Mode::Imm, Self::ldx),
+            0xA6 => self.read_index(Mode::Dp, Self::ldx),
+            0xB6 => self.read_index(Mode::Dpy, Self::ldx),
+            0xAE => self.read_index(Mode::Abs, Self::ldx),
+            0xBE => self.read_index(Mode::Absy, Self::ldx),
+            0xA0 => self.read_index(Mode::Imm, Self::ldy),
+            0xA4 => self.read_index(Mode::Dp, Self::ldy),
+            0xB4 => self.read_index(Mode::Dpx, Self::ldy),
+            0xAC => self.read_index(Mode::Abs, Self::ldy),
+            0xBC => self.read_index(Mode::Absx, Self::ldy),
+            0x86 => self.store(Mode::Dp, self.x, wide_index),
+            0x96 => self.store(Mode::Dpy, self.x, wide_index),
+            0x8E => self.store(Mode::Abs, self.x, wide_index),
+            0x84 => self.store(Mode::Dp, self.y, wide_index),
+            0x94 => self.store(Mode::Dpx, self.y, wide_index),
+            0x8C => self.store(Mode::Abs, self.y, wide_index),
+            0xE0 => self.read_index(Mode::Imm, Self::cpx),
+            0xE4 => self.read_index(Mode::Dp, Self::cpx),
+            0xEC => self.read_index(Mode::Abs, Self::cpx),
+            0xC0 => self.read_index(Mode::Imm, Self::cpy),
+            0xC4 => self.read_index(Mode::Dp, Self::cpy),
+            0xCC => self.read_index(Mode::Abs, Self::cpy),
+
+            0x64 => self.store(Mode::Dp, 0, wide_acc),
+            0x74 => self.store(Mode::Dpx, 0, wide_acc),
+            0x9C => self.store(Mode::Abs, 0, wide_acc),
+            0x9E => self.store(Mode::Absx, 0, wide_acc),
+
+            0x89 => self.read_acc(Mode::Imm, Self::bit_imm),
+            0x24 => self.read_acc(Mode::Dp, Self::bit),
+            0x34 => self.read_acc(Mode::Dpx, Self::bit),
+            0x2C => self.read_acc(Mode::Abs, Self::bit),
+            0x3C => self.read_acc(Mode::Absx, Self::bit),
+
+            0x0A => self.modify_acc(Self::asl),
+            0x06 => self.modify(Mode::Dp, Self::asl),
+            0x16 => self.modify(Mode::Dpx, Self::asl),
+            0x0E => self.modify(Mode::Abs, Self::asl),
+            0x1E => self.modify(Mode::Absx, Self::asl),
+            0x4A => self.modify_acc(Self::lsr),
+            0x46 => self.modify(Mode::Dp, Self::lsr),
+            0x56 => self.modify(Mode::Dpx, Self::lsr),
+            0x4E => self.modify(Mode::Abs, Self::lsr),
+            0x5E => self.modify(Mode::Absx, Self::lsr),
+            0x2A => self.modify_acc(Self::rol),
+            0x26 => self.modify(Mode::Dp, Self::rol),
+            0x36 => self.modify(Mode::Dpx, Self::rol),
+            0x2E => self.modify(Mode::Abs, Self::rol),
+            0x3E => self.modify(Mode::Absx, Self::rol),
+            0x6A => self.modify_acc(Self::ror),
+            0x66 => self.modify(Mode::Dp, Self::ror),
+            0x76 => self.modify(Mode::Dpx, Self::ror),
+            0x6E => self.modify(Mode::Abs, Self::ror),
+            0x7E => self.modify(Mode::Absx, Self::ror),
+            0x1A => self.modify_acc(Self::inc),
+            0xE6 => self.modify(Mode::Dp, Self::inc),
+            0xF6 => self.modify(Mode::Dpx, Self::inc),
+            0xEE => self.modify(Mode::Abs, Self::inc),
+            0xFE => self.modify(Mode::Absx, Self::inc),
+            0x3A => self.modify_acc(Self::dec),
+            0xC6 => self.modify(Mode::Dp, Self::dec),
+            0xD6 => self.modify(Mode::Dpx, Self::dec),
+            0xCE => self.modify(Mode::Abs, Self::dec),
+            0xDE => self.modify(Mode::Absx, Self::dec),
+            0x14 => self.modify(Mode::Dp, Self::trb),
+            0x1C => self.modify(Mode::Abs, Self::trb),
+            0x04 => self.modify(Mode::Dp, Self::tsb),
+            0x0C => self.modify(Mode::Abs, Self::tsb),
+
+            0xE8 => { self.io(); self.x = self.index(self.x.wrapping_add(1)); self.update_nz(self.x, wide_index); }
+            0xC8 => { self.io(); self.y = self.index(self.y.wrapping_add(1)); self.update_nz(self.y, wide_index); }
+            0xCA => { self.io(); self.x = self.index(self.x.wrapping_sub(1)); self.update_nz(self.x, wide_index); }
+            0x88 => { self.io(); self.y = self.index(self.y.wrapping_sub(1)); self.update_nz(self.y, wide_index); }
+
+            // Transfers take the width of the destination, except for the
+            // ones with C, D and S which are always 16 bits
+            0xAA => { self.io(); self.x = self.index(self.c); self.update_nz(self.x, wide_index); }
+            0xA8 => { self.io(); self.y = self.index(self.c); self.update_nz(self.y, wide_index); }
+            0x8A => { self.io(); self.set_acc(self.x); }
+            0x98 => { self.io(); self.set_acc(self.y); }
+            0x9B => { self.io(); self.y = self.x; self.update_nz(self.y, wide_index); }
+            0xBB => { self.io(); self.x = self.y; self.update_nz(self.x, wide_index); }
+            0xBA => { self.io(); self.x = self.index(self.s); self.update_nz(self.x, wide_index); }
+            0x9A => { self.io(); self.s = self.x; self.fix_mode(); }
+            0x1B => { self.io(); self.s = self.c; self.fix_mode(); }
+            0x3B => { self.io(); self.c = self.s; self.update_nz(self.c, true); }
+            0x5B => { self.io(); self.d = self.c; self.update_nz(self.d, true); }
+            0x7B => { self.io(); self.c = self.d; self.update_nz(self.c, true); }
+            0xEB => {
+                self.io();
+                self.io();
+                self.c = self.c.rotate_left(8);
+                self.update_nz(self.c, false);
+            }
+
+            0x18 => { self.io(); self.p.remove(Status::C); }
+            0x38 => { self.io(); self.p.insert(Status::C); }
+            0x58 => { self.io(); self.p.remove(Status::I); }
+            0x78 => { self.io(); self.p.insert(Status::I); }
+            0xB8 => { self.io(); self.p.remove(Status::V); }
+            0xD8 => { self.io(); self.p.remove(Status::D); }
+            0xF8 => { self.io(); self.p.insert(Status::D); }
+            0xC2 => {
+                let v = self.fetch();
+                self.io();
+                self.p.remove(Status::from_bits_retain(v));
+                self.fix_mode();
+            }
+            0xE2 => {
+                let v = self.fetch();
+                self.io();
+                self.p.insert(Status::from_bits_retain(v));
+                self.fix_mode();
+            }
+            // XCE swaps C and E
+            0xFB => {
+                self.io();
+                let c = self.p.contains(Status::C);
+                self.p.set(Status::C, self.e);
+                self.e = c;
+                self.fix_mode();
+            }
+
+            0x48 => { self.io(); self.push_data(self.c, wide_acc); }
+            0xDA => { self.io(); self.push_data(self.x, wide_index); }
+            0x5A => { self.io(); self.push_data(self.y, wide_index); }
+            0x08 => { self.io(); self.push(self.p.bits()); }
+            0x8B => { self.io(); self.push(self.dbr); }
+            0x0B => { self.io(); self.push_word(self.d); }
+            0x4B => { self.io(); self.push(self.pbr); }
+            0x68 => {
+                self.io();
+                self.io();
+                let v = self.pull_data(wide_acc);
+                self.set_acc(v);
+            }
+            0xFA => {
+                self.io();
+                self.io();
+                self.x = self.pull_data(wide_index);
+                self.update_nz(self.x, wide_index);
+            }
+            0x7A => {
+                self.io();
+                self.io();
+                self.y = self.pull_data(wide_index);
+                self.update_nz(self.y, wide_index);
+            }
+            0x28 => {
+                self.io();
+                self.io();
+                self.p = Status::from_bits_retain(self.pull());
+                self.fix_mode();
+            }
+            0xAB => {
+                self.io();
+                self.io();
+                self.dbr = self.pull();
+                self.update_nz(self.dbr as u16, false);
+            }
+            0x2B => {
+                self.io();
+                self.io();
+                self.d = self.pull_word();
+                self.update_nz(self.d, true);
+            }
+            // PEA, PEI and PER push an address without changing a register
+            0xF4 => {
+                let v = self.fetch_word();
+                self.push_word(v);
+            }
+            0xD4 => {
+                let offset = self.fetch();
+                let pointer = self.direct(offset);
+                let v = self.direct_pointer(pointer);
+                self.push_word(v);
+            }
+            0x62 => {
+                let offset = self.fetch_word();
+                self.io();
+                self.push_word(self.pc.wrapping_add(offset));
+            }
+
+            0x10 => self.branch(!self.p.contains(Status::N)),
+            0x30 => self.branch(self.p.contains(Status::N)),
+            0x50 => self.branch(!self.p.contains(Status::V)),
+            0x70 => self.branch(self.p.contains(Status::V)),
+            0x90 => self.branch(!self.p.contains(Status::C)),
+            0xB0 => self.branch(self.p.contains(Status::C)),
+            0xD0 => self.branch(!self.p.contains(Status::Z)),
+            0xF0 => self.branch(self.p.contains(Status::Z)),
+            0x80 => self.branch(true),
+            0x82 => {
+                let offset = self.fetch_word();
+                self.io();
+                self.pc = self.pc.wrapping_add(offset);
+            }
+
+            0x4C => self.pc = self.fetch_word(),
+            0x5C => {
+                let target = self.fetch_long();
+                self.pc = target as u16;
+                self.pbr = (target >> 16) as u8;
+            }
+            0x6C => {
+                let pointer = self.fetch_word();
+                self.pc = self.read_pointer(pointer);
+            }
+            0x7C => {
+                let pointer = self.fetch_word().wrapping_add(self.x);
+                self.io();
+                self.pc = self.read_word((self.pbr as u32) << 16 | pointer as u32);
+            }
+            0xDC => {
+                let pointer = self.fetch_word();
+                let target = self.read_long_pointer(pointer);
+                self.pc = target as u16;
+                self.pbr = (target >> 16) as u8;
+            }
+            // Subroutine calls push the address of their last byte
+            0x20 => {
+                let target = self.fetch_word();
+                self.io();
+                self.push_word(self.pc.wrapping_sub(1));
+                self.pc = target;
+            }
+            0xFC => {
+                let low = self.fetch() as u16;
+                self.push_word(self.pc);
+                let pointer = ((self.fetch() as u16) << 8 | low).wrapping_add(self.x);
+                self.io();
+                self.pc = self.read_word((self.pbr as u32) << 16 | pointer as u32);
+            }
+            0x22 => {
+                let target = self.fetch_word();
+                self.push(self.pbr);
+                self.io();
+                let bank = self.fetch();
+                self.push_word(self.pc.wrapping_sub(1));
+                self.pc = target;
+                self.pbr = bank;
+            }
+            0x60 => {
+                self.io();
+                self.io();
+                self.pc = self.pull_word().wrapping_add(1);
+                self.io();
+            }
+            0x6B => {
+                self.io();
+                self.io();
+                self.pc = self.pull_word().wrapping_add(1);
+                self.pbr = self.pull();
+            }
+            0x40 => {
+                self.io();
+                self.io();
+                self.p = Status::from_bits_retain(self.pull());
+                self.fix_mode();
+                self.pc = self.pull_word();
+                if !self.e {
+                    self.pbr = self.pull();
+                }
+            }
+            // The byte after BRK and COP is a signature that is skipped
+            0x00 => {
+                self.fetch();
+                self.interrupt(BRK_VECTOR, IRQ_VECTOR, true);
+            }
+            0x02 => {
+                self.fetch();
+                self.interrupt(COP_VECTOR, EMULATION_COP_VECTOR, true);
+            }
+
+            0x54 => self.block_move(1),
+            0x44 => self.block_move(0xFFFF),
+
+            0xCB => {
+                self.io();
+                self.io();
+                self.waiting = true;
+            }
+            0xDB => {
+                self.io();
+                self.io();
+                self.stopped = true;
+            }
+            0xEA => self.io(),
+            // WDM is reserved for future expansion and skips a byte
+            0x42 => {
+                self.fetch();
+            }
+
+            _ => unreachable!("{:02X} is decoded by group_mode", opcode),
+        }
+    }
+}
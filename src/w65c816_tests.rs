@@ -0,0 +1,530 @@
+use super::*;
+use memory_map::MemoryMap;
+use w65c816::W65C816;
+
+// Runs `program` from 0x000400 until it reaches its end
+fn run(cpu: &mut W65C816, program: &[u8]) {
+    cpu.load(0x000400, program);
+    cpu.set_pc(0x0400);
+    let end = 0x0400 + program.len() as u16;
+    assert_eq!(Ok(StopReason::Condition), cpu.run_until(|cpu| cpu.pbr() == 0 && cpu.pc() == end));
+}
+
+fn native() -> W65C816 {
+    let mut cpu = W65C816::new();
+    run(&mut cpu, &[0x18, 0xFB]); // CLC, XCE
+    cpu
+}
+
+#[test]
+fn starts_in_emulation_mode() {
+    let mut cpu = W65C816::new();
+    assert!(cpu.e());
+    assert!(cpu.p().contains(Status::M | Status::X));
+    assert_eq!(0x01FF, cpu.s());
+
+    // REP can not clear M and X, and the stack stays in page one
+    run(&mut cpu, &[0xC2, 0x30]); // REP #$30
+    assert!(cpu.p().contains(Status::M | Status::X));
+    cpu.set_s(0x1234);
+    assert_eq!(0x0134, cpu.s());
+}
+
+#[test]
+fn xce_switches_modes() {
+    let mut cpu = native();
+    assert!(!cpu.e());
+    assert!(cpu.p().contains(Status::C));
+
+    run(&mut cpu, &[
+        0xC2, 0x30,       // REP #$30
+        0xA9, 0x34, 0x12, // LDA #$1234
+        0xA2, 0x78, 0x56, // LDX #$5678
+    ]);
+    assert_eq!(0x1234, cpu.c());
+    assert_eq!(0x5678, cpu.x());
+
+    // Going back to emulation mode drops the high byte of the index
+    // registers but keeps B
+    run(&mut cpu, &[0x38, 0xFB]); // SEC, XCE
+    assert!(cpu.e());
+    assert!(!cpu.p().contains(Status::C));
+    assert!(cpu.p().contains(Status::M | Status::X));
+    assert_eq!(0x0078, cpu.x());
+    assert_eq!(0x12, cpu.b());
+    assert_eq!(0x34, cpu.a());
+}
+
+#[test]
+fn sixteen_bit_accumulator() {
+    let mut cpu = native();
+    cpu.load(0x000400, &[
+        0xC2, 0x21,       // REP #$21
+        0xA9, 0xFF, 0xFF, // LDA #$FFFF
+        0x69, 0x01, 0x00, // ADC #$0001
+        0x8D, 0x00, 0x20, // STA $2000
+    ]);
+    cpu.set_pc(0x0400);
+    assert_eq!(Ok(3), cpu.step());
+    assert_eq!(Ok(3), cpu.step());
+    assert_eq!(0xFFFF, cpu.c());
+    assert!(cpu.p().contains(Status::N));
+    assert_eq!(Ok(3), cpu.step());
+    assert_eq!(0x0000, cpu.c());
+    assert!(cpu.p().contains(Status::Z | Status::C));
+    assert_eq!(Ok(5), cpu.step());
+    assert_eq!(0x00, cpu.peek(0x002000));
+    assert_eq!(0x00, cpu.peek(0x002001));
+
+    // With an 8 bit accumulator B is left alone
+    run(&mut cpu, &[
+        0xA9, 0x34, 0x12, // LDA #$1234
+        0xE2, 0x20,       // SEP #$20
+        0xA9, 0xFF,       // LDA #$FF
+    ]);
+    assert_eq!(0x12FF, cpu.c());
+}
+
+#[test]
+fn long_addressing_uses_banks() {
+    let mut cpu = W65C816::new();
+    cpu.load(0x123456, &[0x11]);
+    cpu.load(0x7F0015, &[0x22]);
+    cpu.set_dbr(0x12);
+    run(&mut cpu, &[
+        0xAD, 0x56, 0x34,       // LDA $3456
+        0x8F, 0x00, 0x00, 0x7E, // STA $7E0000
+        0xA2, 0x05,             // LDX #$05
+        0xBF, 0x10, 0x00, 0x7F, // LDA $7F0010,X
+    ]);
+    assert_eq!(0x11, cpu.peek(0x7E0000));
+    assert_eq!(0x22, cpu.a());
+}
+
+#[test]
+fn direct_page_register() {
+    let mut cpu = W65C816::new();
+    cpu.load(0x002010, &[0x42, 0x43]);
+    cpu.set_d(0x2000);
+    cpu.load(0x000400, &[0xA5, 0x10]); // LDA $10
+    cpu.set_pc(0x0400);
+    assert_eq!(Ok(3), cpu.step());
+    assert_eq!(0x42, cpu.a());
+
+    // A direct page that does not start on a page boundary costs a cycle
+    cpu.set_d(0x2001);
+    cpu.set_pc(0x0400);
+    assert_eq!(Ok(4), cpu.step());
+    assert_eq!(0x43, cpu.a());
+}
+
+#[test]
+fn emulation_mode_direct_page_wraps() {
+    let mut cpu = W65C816::new();
+    cpu.load(0x000010, &[0x42]);
+    run(&mut cpu, &[
+        0xA2, 0x20, // LDX #$20
+        0xB5, 0xF0, // LDA $F0,X
+    ]);
+    assert_eq!(0x42, cpu.a());
+
+    let mut cpu = native();
+    cpu.load(0x000110, &[0x43]);
+    run(&mut cpu, &[
+        0xA2, 0x20, // LDX #$20
+        0xB5, 0xF0, // LDA $F0,X
+    ]);
+    assert_eq!(0x43, cpu.a());
+}
+
+#[test]
+fn emulation_mode_direct_pointers_wrap() {
+    let mut cpu = W65C816::new();
+    cpu.load(0x0000FF, &[0x00]);
+    cpu.load(0x000000, &[0x20]);
+    cpu.load(0x000100, &[0x30]);
+    cpu.load(0x002000, &[0x42]);
+    cpu.load(0x003000, &[0x43]);
+    run(&mut cpu, &[0xB1, 0xFF]); // LDA ($FF),Y
+    assert_eq!(0x42, cpu.a());
+    run(&mut cpu, &[0xB2, 0xFF]); // LDA ($FF)
+    assert_eq!(0x42, cpu.a());
+    run(&mut cpu, &[0xA2, 0x00, 0xA1, 0xFF]); // LDX #$00, LDA ($FF,X)
+    assert_eq!(0x42, cpu.a());
+    run(&mut cpu, &[0xD4, 0xFF]); // PEI ($FF)
+    assert_eq!([0x00, 0x20], [cpu.peek(0x01FE), cpu.peek(0x01FF)]);
+
+    // [dp] pointers do not wrap
+    cpu.load(0x000101, &[0x00]);
+    run(&mut cpu, &[0xA7, 0xFF]); // LDA [$FF]
+    assert_eq!(0x43, cpu.a());
+
+    // Neither do pointers in native mode
+    let mut cpu = native();
+    cpu.load(0x0000FF, &[0x00]);
+    cpu.load(0x000000, &[0x20]);
+    cpu.load(0x000100, &[0x30]);
+    cpu.load(0x003000, &[0x43]);
+    run(&mut cpu, &[0xB1, 0xFF]); // LDA ($FF),Y
+    assert_eq!(0x43, cpu.a());
+}
+
+#[test]
+fn direct_page_data_stays_in_bank_0() {
+    let mut cpu = native();
+    cpu.load(0x00FFFF, &[0x34]);
+    cpu.load(0x000000, &[0x12]);
+    cpu.load(0x010000, &[0x56]);
+    cpu.set_d(0xFF00);
+    run(&mut cpu, &[
+        0xC2, 0x20, // REP #$20
+        0xA5, 0xFF, // LDA $FF
+    ]);
+    assert_eq!(0x1234, cpu.c());
+
+    run(&mut cpu, &[0xE6, 0xFF]); // INC $FF
+    assert_eq!([0x35, 0x12, 0x56], [cpu.peek(0x00FFFF), cpu.peek(0x000000), cpu.peek(0x010000)]);
+
+    cpu.set_d(0);
+    cpu.set_s(0xFFFE);
+    run(&mut cpu, &[0xA3, 0x01]); // LDA $01,S
+    assert_eq!(0x1235, cpu.c());
+}
+
+#[test]
+fn stack_relative() {
+    let mut cpu = native();
+    run(&mut cpu, &[
+        0xC2, 0x20,       // REP #$20
+        0xF4, 0x34, 0x12, // PEA $1234
+        0xA3, 0x01,       // LDA $01,S
+    ]);
+    assert_eq!(0x1234, cpu.c());
+    assert_eq!(0x01FD, cpu.s());
+}
+
+#[test]
+fn indirect_addressing_modes() {
+    let mut cpu = native();
+    cpu.set_dbr(0x12);
+    cpu.load(0x000010, &[0x00, 0x20, 0x7E]);
+    cpu.load(0x000018, &[0x10, 0x20]);
+    cpu.load(0x122000, &[0x11]);
+    cpu.load(0x122010, &[0x44]);
+    cpu.load(0x122100, &[0x55]);
+    cpu.load(0x7E2000, &[0x22]);
+    cpu.load(0x7E2010, &[0x33]);
+    cpu.load(0x000400, &[
+        0xB2, 0x10,       // LDA ($10)
+        0xA7, 0x10,       // LDA [$10]
+        0xA0, 0x10,       // LDY #$10
+        0xB7, 0x10,       // LDA [$10],Y
+        0xB1, 0x10,       // LDA ($10),Y
+        0xA2, 0x08,       // LDX #$08
+        0xA1, 0x10,       // LDA ($10,X)
+        0xC2, 0x10,       // REP #$10
+        0xA0, 0x00, 0x01, // LDY #$0100
+        0xB1, 0x10,       // LDA ($10),Y
+        0xF4, 0x00, 0x20, // PEA $2000
+        0xB3, 0x01,       // LDA ($01,S),Y
+    ]);
+    cpu.set_pc(0x0400);
+    assert_eq!(Ok(5), cpu.step());
+    assert_eq!(0x11, cpu.a());
+    assert_eq!(Ok(6), cpu.step());
+    assert_eq!(0x22, cpu.a());
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(Ok(6), cpu.step());
+    assert_eq!(0x33, cpu.a());
+    assert_eq!(Ok(5), cpu.step());
+    assert_eq!(0x44, cpu.a());
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(Ok(6), cpu.step());
+    assert_eq!(0x44, cpu.a());
+
+    // 16 bit index registers always take the extra cycle
+    assert_eq!(Ok(3), cpu.step());
+    assert_eq!(Ok(3), cpu.step());
+    assert_eq!(Ok(6), cpu.step());
+    assert_eq!(0x55, cpu.a());
+    assert_eq!(Ok(5), cpu.step());
+    assert_eq!(Ok(7), cpu.step());
+    assert_eq!(0x55, cpu.a());
+}
+
+#[test]
+fn indirect_jumps() {
+    let mut cpu = native();
+    cpu.load(0x003002, &[0x00, 0x50]);
+    cpu.load(0x003010, &[0x00, 0x80, 0x02]);
+    cpu.load(0x000400, &[
+        0xA2, 0x02,       // LDX #$02
+        0x7C, 0x00, 0x30, // JMP ($3000,X)
+    ]);
+    cpu.load(0x005000, &[0xFC, 0x00, 0x30]); // JSR ($3000,X)
+    cpu.set_pc(0x0400);
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(Ok(6), cpu.step());
+    assert_eq!(0x5000, cpu.pc());
+    assert_eq!(Ok(8), cpu.step());
+    assert_eq!(0x5000, cpu.pc());
+    assert_eq!([0x02, 0x50], [cpu.peek(0x01FE), cpu.peek(0x01FF)]);
+    assert_eq!(0x01FD, cpu.s());
+
+    cpu.load(0x005000, &[0xDC, 0x10, 0x30]); // JML [$3010]
+    assert_eq!(Ok(6), cpu.step());
+    assert_eq!((0x02, 0x8000), (cpu.pbr(), cpu.pc()));
+}
+
+#[test]
+fn pei_per_and_brl() {
+    let mut cpu = native();
+    cpu.load(0x000010, &[0x34, 0x12]);
+    cpu.load(0x000400, &[
+        0xD4, 0x10,       // PEI ($10)
+        0x62, 0x00, 0x01, // PER $0505
+        0x82, 0xF8, 0x00, // BRL $0500
+    ]);
+    cpu.set_pc(0x0400);
+    assert_eq!(Ok(6), cpu.step());
+    assert_eq!([0x34, 0x12], [cpu.peek(0x01FE), cpu.peek(0x01FF)]);
+    assert_eq!(Ok(6), cpu.step());
+    assert_eq!([0x05, 0x05], [cpu.peek(0x01FC), cpu.peek(0x01FD)]);
+    assert_eq!(0x01FB, cpu.s());
+    assert_eq!(Ok(4), cpu.step());
+    assert_eq!(0x0500, cpu.pc());
+}
+
+#[test]
+fn register_transfers() {
+    let mut cpu = native();
+    cpu.load(0x000400, &[
+        0xC2, 0x20,       // REP #$20
+        0xA9, 0x34, 0x12, // LDA #$1234
+        0x1B,             // TCS
+        0x5B,             // TCD
+        0xA9, 0x00, 0x80, // LDA #$8000
+        0x3B,             // TSC
+        0xA9, 0x00, 0x80, // LDA #$8000
+        0x7B,             // TDC
+        0xEB,             // XBA
+    ]);
+    cpu.set_pc(0x0400);
+    for _ in 0..2 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(0x1234, cpu.s());
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(0x1234, cpu.d());
+    cpu.step().unwrap();
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(0x1234, cpu.c());
+    assert!(!cpu.p().contains(Status::N));
+    cpu.step().unwrap();
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(0x1234, cpu.c());
+
+    // XBA swaps B and A and sets N and Z from the new A
+    assert_eq!(Ok(3), cpu.step());
+    assert_eq!(0x3412, cpu.c());
+    assert!(!cpu.p().contains(Status::N | Status::Z));
+}
+
+#[test]
+fn decimal_mode_is_16_bits() {
+    let mut cpu = native();
+    run(&mut cpu, &[
+        0xC2, 0x20,       // REP #$20
+        0xF8,             // SED
+        0x18,             // CLC
+        0xA9, 0x99, 0x19, // LDA #$1999
+        0x69, 0x01, 0x00, // ADC #$0001
+    ]);
+    assert_eq!(0x2000, cpu.c());
+    assert!(!cpu.p().contains(Status::C));
+
+    run(&mut cpu, &[
+        0x38,             // SEC
+        0xA9, 0x00, 0x10, // LDA #$1000
+        0xE9, 0x01, 0x00, // SBC #$0001
+    ]);
+    assert_eq!(0x0999, cpu.c());
+    assert!(cpu.p().contains(Status::C));
+
+    // Every digit carries
+    run(&mut cpu, &[
+        0x18,             // CLC
+        0xA9, 0x34, 0x12, // LDA #$1234
+        0x69, 0x66, 0x87, // ADC #$8766
+    ]);
+    assert_eq!(0x0000, cpu.c());
+    assert!(cpu.p().contains(Status::C | Status::Z));
+
+    // Every digit borrows
+    run(&mut cpu, &[
+        0x38,             // SEC
+        0xA9, 0x00, 0x00, // LDA #$0000
+        0xE9, 0x01, 0x00, // SBC #$0001
+    ]);
+    assert_eq!(0x9999, cpu.c());
+    assert!(!cpu.p().contains(Status::C));
+    assert!(cpu.p().contains(Status::N));
+}
+
+#[test]
+fn block_move() {
+    let mut cpu = native();
+    cpu.load(0x011000, &[1, 2, 3]);
+    cpu.load(0x000400, &[
+        0xC2, 0x30,       // REP #$30
+        0xA9, 0x02, 0x00, // LDA #$0002
+        0xA2, 0x00, 0x10, // LDX #$1000
+        0xA0, 0x00, 0x20, // LDY #$2000
+        0x54, 0x7E, 0x01, // MVN $01,$7E
+    ]);
+    cpu.set_pc(0x0400);
+    for _ in 0..4 {
+        cpu.step().unwrap();
+    }
+    // One byte per step
+    for _ in 0..3 {
+        assert_eq!(0x040B, cpu.pc());
+        assert_eq!(Ok(7), cpu.step());
+    }
+    assert_eq!(0x040E, cpu.pc());
+    assert_eq!(0xFFFF, cpu.c());
+    assert_eq!(0x1003, cpu.x());
+    assert_eq!(0x2003, cpu.y());
+    assert_eq!(0x7E, cpu.dbr());
+    assert_eq!([1, 2, 3], [cpu.peek(0x7E2000), cpu.peek(0x7E2001), cpu.peek(0x7E2002)]);
+}
+
+#[test]
+fn block_move_backwards() {
+    let mut cpu = native();
+    cpu.load(0x011000, &[1, 2, 3]);
+    cpu.load(0x000400, &[
+        0xC2, 0x30,       // REP #$30
+        0xA9, 0x02, 0x00, // LDA #$0002
+        0xA2, 0x02, 0x10, // LDX #$1002
+        0xA0, 0x02, 0x20, // LDY #$2002
+        0x44, 0x7E, 0x01, // MVP $01,$7E
+    ]);
+    cpu.set_pc(0x0400);
+    for _ in 0..4 {
+        cpu.step().unwrap();
+    }
+    for _ in 0..3 {
+        assert_eq!(0x040B, cpu.pc());
+        assert_eq!(Ok(7), cpu.step());
+    }
+    assert_eq!(0x040E, cpu.pc());
+    assert_eq!(0xFFFF, cpu.c());
+    assert_eq!(0x0FFF, cpu.x());
+    assert_eq!(0x1FFF, cpu.y());
+    assert_eq!(0x7E, cpu.dbr());
+    assert_eq!([1, 2, 3], [cpu.peek(0x7E2000), cpu.peek(0x7E2001), cpu.peek(0x7E2002)]);
+}
+
+#[test]
+fn jsl_and_rtl() {
+    let mut cpu = W65C816::new();
+    cpu.load(0x028000, &[0x6B]); // RTL
+    cpu.load(0x000400, &[0x22, 0x00, 0x80, 0x02]); // JSL $028000
+    cpu.set_pc(0x0400);
+    assert_eq!(Ok(8), cpu.step());
+    assert_eq!((0x02, 0x8000), (cpu.pbr(), cpu.pc()));
+    assert_eq!(0x01FC, cpu.s());
+    assert_eq!([0x03, 0x04, 0x00], [cpu.peek(0x01FD), cpu.peek(0x01FE), cpu.peek(0x01FF)]);
+    assert_eq!(Ok(6), cpu.step());
+    assert_eq!((0x00, 0x0404), (cpu.pbr(), cpu.pc()));
+    assert_eq!(0x01FF, cpu.s());
+}
+
+#[test]
+fn native_interrupts_push_the_program_bank() {
+    let mut cpu = native();
+    cpu.load(0x00FFE6, &[0x00, 0x90]);
+    cpu.load(0x00FFEA, &[0x00, 0xA0]);
+    cpu.load(0x031000, &[0x00, 0xEA]); // BRK $EA
+    cpu.set_pbr(0x03);
+    cpu.set_pc(0x1000);
+    cpu.set_p(cpu.p() | Status::D);
+    assert_eq!(Ok(8), cpu.step());
+    assert_eq!((0x00, 0x9000), (cpu.pbr(), cpu.pc()));
+    assert!(cpu.p().contains(Status::I));
+    assert!(!cpu.p().contains(Status::D));
+    assert_eq!([0x03, 0x10, 0x02], [cpu.peek(0x01FF), cpu.peek(0x01FE), cpu.peek(0x01FD)]);
+    assert_eq!(0x01FB, cpu.s());
+
+    cpu.load(0x00A000, &[0x40]); // RTI
+    cpu.trigger_nmi();
+    cpu.step().unwrap();
+    assert_eq!((0x00, 0xA000), (cpu.pbr(), cpu.pc()));
+    cpu.step().unwrap();
+    assert_eq!((0x00, 0x9000), (cpu.pbr(), cpu.pc()));
+}
+
+#[test]
+fn wai_and_stp() {
+    let mut cpu = W65C816::new();
+    cpu.load(0x00FFFE, &[0x00, 0x05]);
+    cpu.load(0x000500, &[0xDB]); // STP
+    cpu.load(0x000400, &[0x58, 0xCB]); // CLI, WAI
+    cpu.set_pc(0x0400);
+    assert_eq!(Ok(StopReason::Waiting), cpu.run_until(|_| false));
+    assert!(cpu.is_waiting());
+    assert_eq!(Ok(1), cpu.step());
+
+    cpu.raise_irq();
+    assert_eq!(Ok(StopReason::Stopped), cpu.run_until(|_| false));
+    assert!(cpu.is_stopped());
+    assert_eq!(Ok(0), cpu.step());
+}
+
+#[test]
+fn run_stops_at_long_breakpoints() {
+    let mut cpu = W65C816::new();
+    cpu.load(0x000400, &[0x5C, 0x00, 0x80, 0x02]); // JML $028000
+    cpu.load(0x028000, &[0xE8, 0x80, 0xFD]); // INX, BRA $8000
+    cpu.add_breakpoint(0x008000);
+    cpu.add_breakpoint(0x028000);
+    assert_eq!(Ok(StopReason::Breakpoint(0x8000)), cpu.run());
+    assert_eq!((0x02, 0x00), (cpu.pbr(), cpu.x()));
+
+    // Resuming steps over the breakpoint it stopped at
+    assert_eq!(Ok(StopReason::Breakpoint(0x8000)), cpu.run());
+    assert_eq!(0x01, cpu.x());
+    cpu.clear_breakpoints();
+    assert_eq!(Ok(StopReason::InstructionLimit), cpu.run_for_instructions(4));
+    assert_eq!(0x03, cpu.x());
+}
+
+#[test]
+fn bus_faults_are_errors() {
+    // A 16 bit bus sees every bank as bank 0
+    let map = MemoryMap::builder().ram(0x0000..=0x7FFF).rom(0x8000, &[0xEA]).build();
+    let mut cpu = W65C816::with_bus(map);
+    cpu.load(0x000400, &[
+        0xAF, 0x00, 0x80, 0x7E, // LDA $7E8000
+        0x8D, 0x00, 0x80,       // STA $8000
+    ]);
+    assert_eq!(Ok(5), cpu.step());
+    assert_eq!(0xEA, cpu.a());
+    assert_eq!(Err(CPUError::RomWrite { address: 0x8000, v: 0xEA, pc: 0x0404 }), cpu.step());
+    cpu.set_pc(0x9000);
+    assert_eq!(Err(CPUError::UnmappedAccess { address: 0x9000, write: false, pc: 0x9000 }), cpu.step());
+}
+
+#[test]
+fn load_writes_rom_without_faults() {
+    let map = MemoryMap::builder().ram(0x0000..=0x7FFF).rom(0x8000, &[0; 0x100]).build();
+    let mut cpu = W65C816::with_bus(map);
+    cpu.load(0x008000, &[0xA9, 0x07]); // LDA #$07
+    cpu.load(0x009000, &[0x01]);
+    cpu.set_pc(0x8000);
+    assert_eq!(Ok(2), cpu.step());
+    assert_eq!(0x07, cpu.a());
+    assert_eq!(memory_map::OPEN_BUS, cpu.peek(0x009000));
+}
@@ -0,0 +1,67 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/
+
+use std::ops::RangeInclusive;
+
+/// The kind of memory access that triggers a watchpoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+/// Stops the `run` functions after an instruction reads or writes an
+/// address in `range`. Instruction fetches do not count as reads.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watchpoint {
+    range: RangeInclusive<u16>,
+    kind: WatchKind,
+    value: Option<u8>,
+}
+
+impl Watchpoint {
+    pub fn new(range: RangeInclusive<u16>, kind: WatchKind) -> Self {
+        Watchpoint { range, kind, value: None }
+    }
+
+    pub fn read(range: RangeInclusive<u16>) -> Self {
+        Watchpoint::new(range, WatchKind::Read)
+    }
+
+    pub fn write(range: RangeInclusive<u16>) -> Self {
+        Watchpoint::new(range, WatchKind::Write)
+    }
+
+    pub fn access(range: RangeInclusive<u16>) -> Self {
+        Watchpoint::new(range, WatchKind::Access)
+    }
+
+    /// Only trigger when the value read or written is `v`.
+    pub fn with_value(mut self, v: u8) -> Self {
+        self.value = Some(v);
+        self
+    }
+
+    pub(crate) fn matches(&self, address: u16, write: bool, v: u8) -> bool {
+        let kind = match self.kind {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        };
+        kind && self.range.contains(&address) && self.value.is_none_or(|value| value == v)
+    }
+}
+
+/// The access that triggered a watchpoint. For reads `old` and `new` are
+/// both the value that was read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchHit {
+    pub address: u16,
+    pub write: bool,
+    pub old: u8,
+    pub new: u8,
+    /// Address of the instruction that made the access.
+    pub pc: u16,
+}
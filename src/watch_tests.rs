@@ -0,0 +1,73 @@
+use super::*;
+use watch::{WatchKind, Watchpoint};
+
+fn new_test_cpu() -> CPU {
+    let mut cpu = CPU::new();
+    cpu.mem[0x0400] = 0xA2; // LDX #$00
+    cpu.mem[0x0401] = 0x00;
+    cpu.mem[0x0402] = 0xE8; // INX
+    cpu.mem[0x0403] = 0x86; // STX $10
+    cpu.mem[0x0404] = 0x10;
+    cpu.mem[0x0405] = 0xA6; // LDX $10
+    cpu.mem[0x0406] = 0x10;
+    cpu.mem[0x0407] = 0x4C; // JMP $0402
+    cpu.mem[0x0408] = 0x02;
+    cpu.mem[0x0409] = 0x04;
+    cpu.mem[0x0010] = 0x42;
+    cpu
+}
+
+#[test]
+fn write_watchpoint() {
+    let mut cpu = new_test_cpu();
+    cpu.add_watchpoint(Watchpoint::write(0x0010..=0x0010));
+    let hit = WatchHit { address: 0x0010, write: true, old: 0x42, new: 0x01, pc: 0x0403 };
+    assert_eq!(cpu.run(), Ok(StopReason::Watchpoint(hit)));
+    assert_eq!(0x0405, cpu.pc);
+    let hit = WatchHit { address: 0x0010, write: true, old: 0x01, new: 0x02, pc: 0x0403 };
+    assert_eq!(cpu.run(), Ok(StopReason::Watchpoint(hit)));
+}
+
+#[test]
+fn read_watchpoint() {
+    let mut cpu = new_test_cpu();
+    cpu.add_watchpoint(Watchpoint::read(0x0000..=0x00FF));
+    let hit = WatchHit { address: 0x0010, write: false, old: 0x01, new: 0x01, pc: 0x0405 };
+    assert_eq!(cpu.run(), Ok(StopReason::Watchpoint(hit)));
+    cpu.clear_watchpoints();
+    assert_eq!(cpu.run_for_instructions(20), Ok(StopReason::InstructionLimit));
+}
+
+#[test]
+fn access_watchpoint() {
+    let mut cpu = new_test_cpu();
+    cpu.add_watchpoint(Watchpoint::new(0x0010..=0x0010, WatchKind::Access));
+    assert!(matches!(cpu.run(), Ok(StopReason::Watchpoint(WatchHit { write: true, .. }))));
+    assert!(matches!(cpu.run(), Ok(StopReason::Watchpoint(WatchHit { write: false, .. }))));
+}
+
+#[test]
+fn watchpoint_with_value() {
+    let mut cpu = new_test_cpu();
+    cpu.add_watchpoint(Watchpoint::write(0x0010..=0x0010).with_value(0x05));
+    let hit = WatchHit { address: 0x0010, write: true, old: 0x04, new: 0x05, pc: 0x0403 };
+    assert_eq!(cpu.run(), Ok(StopReason::Watchpoint(hit)));
+    assert_eq!(0x05, cpu.x);
+}
+
+#[test]
+fn fetches_do_not_trigger_read_watchpoints() {
+    let mut cpu = new_test_cpu();
+    cpu.add_watchpoint(Watchpoint::read(0x0400..=0x04FF));
+    assert_eq!(cpu.run_for_instructions(20), Ok(StopReason::InstructionLimit));
+}
+
+#[test]
+fn stack_writes_trigger_watchpoints() {
+    let mut cpu = new_test_cpu();
+    cpu.mem[0x0400] = 0x48; // PHA
+    cpu.a = 0x99;
+    cpu.add_watchpoint(Watchpoint::access(0x0100..=0x01FF));
+    let hit = WatchHit { address: 0x01FF, write: true, old: 0x00, new: 0x99, pc: 0x0400 };
+    assert_eq!(cpu.run(), Ok(StopReason::Watchpoint(hit)));
+}